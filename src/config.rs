@@ -6,18 +6,18 @@
 //! - Unit conversion for dimensions (mm, cm, in, pt)
 //! - Dimension type with flexible deserialization
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use csv::ReaderBuilder;
 use serde::{Deserialize, Deserializer};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufReader, Read};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 /// Dimension value that can be specified as:
 /// - A number (interpreted as points)
 /// - A string with unit: e.g., "100 mm", "10 cm", "1 in" (inches)
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Dimension(pub f64);
 
 impl Dimension {
@@ -101,7 +101,283 @@ impl<'de> Deserialize<'de> for Dimension {
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Colour for text and symbols, written as:
+/// - `"#RRGGBB"` or `"#RGB"`: DeviceRGB
+/// - `"cmyk(c, m, y, k)"`: DeviceCMYK, components 0-1 or percentages (`"100%"`)
+/// - `"gray(v)"`: DeviceGray, 0 (black) to 1 (white) or a percentage
+/// - `"spot(name, c, m, y, k)"`: a Separation (spot colour) printed on its own
+///   plate, with a CMYK alternate for devices without that colorant. An
+///   optional sixth component sets the tint (default: 1, full strength)
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColorSpec {
+    Gray(f64),
+    Rgb(f64, f64, f64),
+    Cmyk(f64, f64, f64, f64),
+    Separation {
+        /// Colorant name, e.g. `"PANTONE 485 C"`
+        name: String,
+        tint: f64,
+        /// CMYK colour shown in place of the colorant
+        alternate: [f64; 4],
+    },
+}
+
+impl ColorSpec {
+    /// Parse a colour string
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        if let Some(hex) = value.strip_prefix('#') {
+            let digits: Vec<u32> = hex.chars()
+                .map(|c| c.to_digit(16))
+                .collect::<Option<_>>()
+                .ok_or_else(|| format!("invalid hex colour '{}'", value))?;
+            let channels: Vec<f64> = match digits.len() {
+                3 => digits.iter().map(|d| (d * 17) as f64 / 255.0).collect(),
+                6 => digits.chunks(2).map(|pair| (pair[0] * 16 + pair[1]) as f64 / 255.0).collect(),
+                _ => return Err(format!("hex colour '{}' must have 3 or 6 digits", value)),
+            };
+            return Ok(ColorSpec::Rgb(channels[0], channels[1], channels[2]));
+        }
+
+        let unknown = || format!(
+            "unknown colour '{}'. Use #RRGGBB, cmyk(c, m, y, k), gray(v) or spot(name, c, m, y, k)",
+            value
+        );
+        let (function, args) = value
+            .strip_suffix(')')
+            .and_then(|v| v.split_once('('))
+            .ok_or_else(unknown)?;
+        let function = function.trim().to_lowercase();
+        let mut args: Vec<&str> = args.split(',').map(str::trim).collect();
+
+        // The colorant name keeps its case; the other arguments are components
+        let spot_name = match function.as_str() {
+            "spot" => Some(args.remove(0)),
+            _ => None,
+        };
+        let components = args
+            .into_iter()
+            .map(parse_color_component)
+            .collect::<Result<Vec<f64>, String>>()?;
+        match (function.as_str(), spot_name, components.as_slice()) {
+            ("cmyk", _, &[c, m, y, k]) => Ok(ColorSpec::Cmyk(c, m, y, k)),
+            ("gray" | "grey", _, &[v]) => Ok(ColorSpec::Gray(v)),
+            ("spot", Some(""), _) => Err(format!("spot colour '{}' needs a colorant name", value)),
+            ("spot", Some(name), &[c, m, y, k]) | ("spot", Some(name), &[c, m, y, k, _]) => Ok(ColorSpec::Separation {
+                name: name.to_string(),
+                tint: components.get(4).copied().unwrap_or(1.0),
+                alternate: [c, m, y, k],
+            }),
+            _ => Err(unknown()),
+        }
+    }
+}
+
+/// Parse a colour component: a number from 0 to 1 or a percentage
+fn parse_color_component(value: &str) -> Result<f64, String> {
+    let (number, scale) = match value.strip_suffix('%') {
+        Some(number) => (number.trim(), 100.0),
+        None => (value, 1.0),
+    };
+    let component = number.parse::<f64>()
+        .map_err(|_| format!("invalid colour component '{}'", value))?
+        / scale;
+    if !(0.0..=1.0).contains(&component) {
+        return Err(format!("colour component '{}' is out of range", value));
+    }
+    Ok(component)
+}
+
+impl<'de> Deserialize<'de> for ColorSpec {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        ColorSpec::parse(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Output page size, written as a paper name (`"A4"`, `"Letter"`, ...) or
+/// as `{"width": ..., "height": ...}`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageSize {
+    /// Width in points
+    pub width: f64,
+    /// Height in points
+    pub height: f64,
+}
+
+/// Paper sizes known by name, in points (portrait)
+const PAPER_SIZES: [(&str, f64, f64); 8] = [
+    ("A3", 297.0 * 72.0 / 25.4, 420.0 * 72.0 / 25.4),
+    ("A4", 210.0 * 72.0 / 25.4, 297.0 * 72.0 / 25.4),
+    ("A5", 148.0 * 72.0 / 25.4, 210.0 * 72.0 / 25.4),
+    ("A6", 105.0 * 72.0 / 25.4, 148.0 * 72.0 / 25.4),
+    ("Letter", 612.0, 792.0),
+    ("Legal", 612.0, 1008.0),
+    ("Tabloid", 792.0, 1224.0),
+    ("Ledger", 1224.0, 792.0),
+];
+
+impl PageSize {
+    /// Look up a paper size by name, ignoring case
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        PAPER_SIZES
+            .iter()
+            .find(|(paper, _, _)| paper.eq_ignore_ascii_case(name.trim()))
+            .map(|&(_, width, height)| PageSize { width, height })
+            .ok_or_else(|| {
+                let names: Vec<&str> = PAPER_SIZES.iter().map(|(paper, _, _)| *paper).collect();
+                format!("unknown page size '{}'. Use one of {} or {{\"width\": ..., \"height\": ...}}", name, names.join(", "))
+            })
+    }
+
+    /// Width and height in points, turned to the orientation if one is given
+    pub fn oriented(&self, orientation: Option<Orientation>) -> (f64, f64) {
+        let (short, long) = (self.width.min(self.height), self.width.max(self.height));
+        match orientation {
+            None => (self.width, self.height),
+            Some(Orientation::Portrait) => (short, long),
+            Some(Orientation::Landscape) => (long, short),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PageSize {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum PageSizeValue {
+            Name(String),
+            Custom { width: Dimension, height: Dimension },
+        }
+
+        match PageSizeValue::deserialize(deserializer)? {
+            PageSizeValue::Name(name) => PageSize::from_name(&name).map_err(serde::de::Error::custom),
+            PageSizeValue::Custom { width, height } => {
+                if width.as_points() <= 0.0 || height.as_points() <= 0.0 {
+                    return Err(serde::de::Error::custom("page size must be positive"));
+                }
+                Ok(PageSize { width: width.as_points(), height: height.as_points() })
+            }
+        }
+    }
+}
+
+/// Page orientation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+/// Text rendering mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextMode {
+    /// Filled glyphs
+    #[default]
+    Fill,
+    /// Outlined glyphs
+    Stroke,
+    /// Filled glyphs with an outline
+    FillStroke,
+}
+
+/// Point of the field box a rotation turns around
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Anchor {
+    #[default]
+    Center,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// QR code error correction level
+///
+/// Higher levels survive more damage at the cost of a denser code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum EcLevel {
+    #[serde(alias = "l")]
+    L,
+    #[serde(alias = "m")]
+    M,
+    #[serde(alias = "q")]
+    Q,
+    #[serde(alias = "h")]
+    H,
+}
+
+/// How 2D symbols such as QR codes are drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderMode {
+    /// Filled rectangles in the content stream (crisp at any size)
+    #[default]
+    Vector,
+    /// Bitmap image XObject
+    Raster,
+}
+
+/// Horizontal alignment of text lines inside the field box
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Vertical alignment of a text block inside the field box
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VAlign {
+    #[default]
+    Top,
+    Middle,
+    Bottom,
+}
+
+/// Writing direction of text fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Left to right lines, stacked top to bottom
+    #[default]
+    Horizontal,
+    /// Top to bottom columns, stacked right to left (tategaki)
+    Vertical,
+}
+
+/// Where long text lines may be broken
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WrapMode {
+    /// Break between words (Unicode line breaking rules, which also allow
+    /// breaks between CJK characters)
+    Word,
+    /// Break between any two characters
+    Char,
+}
+
+/// How text that does not fit the field box is handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FitMode {
+    /// Reduce the font size until the text fits
+    Shrink,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct FieldSpec {
     pub x: Dimension,
     pub y: Dimension,
@@ -111,18 +387,265 @@ pub struct FieldSpec {
     pub output_type: String,
     #[serde(default)]
     pub font_size: Option<Dimension>,
+    /// QR, Micro QR or rMQR error correction level (defaults to M; L for Micro QR)
+    #[serde(default)]
+    pub ec_level: Option<EcLevel>,
+    /// Exact QR version (1-40) or Micro QR version (1-4); fails if the data does not fit
+    #[serde(default)]
+    pub version: Option<i16>,
+    /// Smallest QR or Micro QR version to use; larger versions are chosen as needed
+    #[serde(default)]
+    pub min_version: Option<i16>,
+    /// Quiet zone width in modules (defaults to 4)
+    #[serde(default)]
+    pub quiet_zone: Option<u32>,
+    /// Data Matrix size (`"RxC"`, e.g. `"16x16"`), Aztec size (`"compact-N"`
+    /// or `"full-N"` layers) or rMQR size (`"R7x43"`); the smallest fitting
+    /// size by default
+    #[serde(default)]
+    pub size: Option<String>,
+    /// Minimum Aztec error correction in percent of the data (defaults to 23)
+    #[serde(default)]
+    pub ec_percent: Option<u32>,
+    /// Number of PDF417 data columns (1-30)
+    #[serde(default)]
+    pub columns: Option<u32>,
+    /// Number of PDF417 rows (3-90)
+    #[serde(default)]
+    pub rows: Option<u32>,
+    /// PDF417 error correction level (0-8); defaults to the level
+    /// recommended for the data size
+    #[serde(default)]
+    pub security_level: Option<u8>,
+    /// Encode Data Matrix and Aztec values as GS1 data with FNC1
+    #[serde(default)]
+    pub gs1: Option<bool>,
+    /// 2D symbol rendering mode (defaults to vector)
+    #[serde(default)]
+    pub render: Option<RenderMode>,
+    /// Print the encoded text under 1D barcodes (defaults to true)
+    #[serde(default)]
+    pub human_readable: Option<bool>,
+    /// Font for text fields: a standard PDF font, a system font family or a
+    /// font file path (overrides `settings.font`)
+    #[serde(default)]
+    pub font: Option<String>,
+    /// Horizontal text alignment (defaults to left)
+    #[serde(default)]
+    pub align: Option<Align>,
+    /// Vertical text alignment (defaults to top)
+    #[serde(default)]
+    pub valign: Option<VAlign>,
+    /// Wrap text onto several lines to fit the box width (no wrapping by default)
+    #[serde(default)]
+    pub wrap: Option<WrapMode>,
+    /// Shrink text that does not fit the box
+    #[serde(default)]
+    pub fit: Option<FitMode>,
+    /// Writing direction (defaults to horizontal)
+    #[serde(default)]
+    pub direction: Option<Direction>,
+    /// Text fill colour (defaults to black)
+    #[serde(default)]
+    pub color: Option<ColorSpec>,
+    /// Opacity from 0 (transparent) to 1 (opaque)
+    #[serde(default)]
+    pub opacity: Option<f64>,
+    /// Extra space after every character
+    #[serde(default)]
+    pub char_spacing: Option<Dimension>,
+    /// Extra space after every space character
+    #[serde(default)]
+    pub word_spacing: Option<Dimension>,
+    /// Horizontal glyph scaling in percent (100 is normal width)
+    #[serde(default)]
+    pub horizontal_scale: Option<f64>,
+    /// Whether glyphs are filled, outlined or both (defaults to filled)
+    #[serde(default)]
+    pub text_mode: Option<TextMode>,
+    /// Outline colour (defaults to the fill colour)
+    #[serde(default)]
+    pub stroke_color: Option<ColorSpec>,
+    /// Outline width (defaults to 1 pt)
+    #[serde(default)]
+    pub stroke_width: Option<Dimension>,
+    /// Rotation in degrees, counterclockwise
+    #[serde(default)]
+    pub rotate: Option<f64>,
+    /// Point the rotation turns around (defaults to the box centre)
+    #[serde(default)]
+    pub anchor: Option<Anchor>,
+    /// Base page (1-based) the field is drawn on
+    #[serde(default)]
+    pub page: Option<u32>,
+}
+
+/// What the base page is used for in an N-up layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayoutBase {
+    /// The base page is the background of the whole sheet
+    #[default]
+    Sheet,
+    /// The base page is a template drawn into every filled cell
+    Cell,
+}
+
+/// Grid of cells for printing several records on one sheet
+///
+/// Cells are filled left to right, top to bottom. Field positions are
+/// relative to the top-left corner of their cell. Values left out are taken
+/// from the label sheet preset named in `settings.sheet`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LayoutSection {
+    #[serde(default)]
+    pub columns: Option<u32>,
+    #[serde(default)]
+    pub rows: Option<u32>,
+    #[serde(default)]
+    pub cell_width: Option<Dimension>,
+    #[serde(default)]
+    pub cell_height: Option<Dimension>,
+    /// Horizontal gap between cells (defaults to 0)
+    #[serde(default)]
+    pub gutter_x: Option<Dimension>,
+    /// Vertical gap between cells (defaults to 0)
+    #[serde(default)]
+    pub gutter_y: Option<Dimension>,
+    /// Distance from the left edge of the sheet to the first column (defaults to 0)
+    #[serde(default)]
+    pub margin_left: Option<Dimension>,
+    /// Distance from the top edge of the sheet to the first row (defaults to 0)
+    #[serde(default)]
+    pub margin_top: Option<Dimension>,
+    #[serde(default)]
+    pub base: LayoutBase,
+    /// Sheet width, required when the base page is a cell template
+    #[serde(default)]
+    pub sheet_width: Option<Dimension>,
+    /// Sheet height, required when the base page is a cell template
+    #[serde(default)]
+    pub sheet_height: Option<Dimension>,
+}
+
+/// Bleed and printer's marks around the trimmed page
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MarksSection {
+    /// How far the page extends past the trim edge (defaults to 0)
+    #[serde(default)]
+    pub bleed: Option<Dimension>,
+    /// Draw crop marks at the trim edges (defaults to true)
+    #[serde(default)]
+    pub crop_marks: Option<bool>,
+    /// Draw registration targets at the middle of each edge (defaults to false)
+    #[serde(default)]
+    pub registration_marks: Option<bool>,
+    /// Length of the crop marks (defaults to 5 mm)
+    #[serde(default)]
+    pub mark_length: Option<Dimension>,
+    /// Gap between the trim edge and the marks (defaults to the bleed, at least 3 mm)
+    #[serde(default)]
+    pub mark_offset: Option<Dimension>,
+    /// Line width of the marks (defaults to 0.25 pt)
+    #[serde(default)]
+    pub mark_width: Option<Dimension>,
+}
+
+/// Back side printed after every front page, for duplex printing
+#[derive(Debug, Default, Deserialize)]
+pub struct BackSection {
+    /// Field placements on the back, keyed by CSV column like `fields`
+    #[serde(default)]
+    pub fields: HashMap<String, FieldSpec>,
+    /// Base page (1-based) printed on the back; without it the back is blank
+    #[serde(default)]
+    pub page: Option<u32>,
+    /// Mirror the cells of an N-up layout horizontally, for long-edge flipping
+    #[serde(default)]
+    pub mirror: bool,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct PlaceConfig {
     pub fields: HashMap<String, FieldSpec>,
+    /// Back side for duplex printing
+    #[serde(default)]
+    pub back: Option<BackSection>,
     pub settings: SettingsSection,
+    /// Directory containing settings.json; relative paths are resolved against it
+    #[serde(skip)]
+    pub base_dir: PathBuf,
 }
 
-#[derive(Debug, Deserialize)]
+impl PlaceConfig {
+    /// Fields of the front and the back side
+    pub fn all_fields(&self) -> impl Iterator<Item = (&String, &FieldSpec)> {
+        self.fields.iter().chain(self.back.iter().flat_map(|back| back.fields.iter()))
+    }
+}
+
+/// Printer calibration applied to everything drawn over the base page
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CalibrationProfile {
+    /// Shift to the right (negative values shift to the left)
+    #[serde(default)]
+    pub offset_x: Option<Dimension>,
+    /// Shift downwards (negative values shift upwards)
+    #[serde(default)]
+    pub offset_y: Option<Dimension>,
+    /// Horizontal scale about the top-left corner of the page (1 = unscaled)
+    #[serde(default)]
+    pub scale_x: Option<f64>,
+    /// Vertical scale about the top-left corner of the page (1 = unscaled)
+    #[serde(default)]
+    pub scale_y: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
 pub struct SettingsSection {
     #[serde(default)]
     pub font: Option<String>,
+    /// CSV column holding the base page (1-based) each row is printed on;
+    /// without it every row gets all base pages
+    #[serde(default)]
+    pub template_page_column: Option<String>,
+    /// N-up layout; without it every row gets its own pages
+    #[serde(default)]
+    pub layout: Option<LayoutSection>,
+    /// Label sheet preset (e.g. "avery-5160") filling in the layout
+    #[serde(default)]
+    pub sheet: Option<String>,
+    /// Cell (1-based) the first row is printed in, to reuse partly used sheets
+    #[serde(default)]
+    pub start_at: Option<u32>,
+    /// Size of the blank pages generated when there is no base.pdf
+    #[serde(default)]
+    pub page_size: Option<PageSize>,
+    /// Orientation of `page_size` (defaults to the size as written)
+    #[serde(default)]
+    pub orientation: Option<Orientation>,
+    /// Bleed, crop marks and registration marks for print shops
+    #[serde(default)]
+    pub marks: Option<MarksSection>,
+    /// Default printer calibration (`offset_x`, `offset_y`, `scale_x`, `scale_y`)
+    #[serde(flatten)]
+    pub calibration: CalibrationProfile,
+    /// Calibration of individual printers, selected with `--printer`
+    #[serde(default)]
+    pub printers: HashMap<String, CalibrationProfile>,
+}
+
+impl SettingsSection {
+    /// Replace the default calibration with the profile of a printer
+    pub fn select_printer(&mut self, name: &str) -> Result<()> {
+        let profile = self.printers.get(name).cloned().ok_or_else(|| {
+            let mut names: Vec<&str> = self.printers.keys().map(String::as_str).collect();
+            names.sort();
+            anyhow!("Unknown printer '{}' (profiles in settings.json: {})", name, names.join(", "))
+        })?;
+        self.calibration = profile;
+        Ok(())
+    }
 }
 
 pub struct DataRow {
@@ -138,8 +661,9 @@ fn open_file_with_context(path: &Path, description: &str) -> Result<File> {
 pub fn load_settings_config(path: &Path) -> Result<PlaceConfig> {
     let file = open_file_with_context(path, "settings.json")?;
     let reader = BufReader::new(file);
-    let config: PlaceConfig = serde_json::from_reader(reader)
+    let mut config: PlaceConfig = serde_json::from_reader(reader)
         .with_context(|| "Failed to parse settings.json")?;
+    config.base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
     Ok(config)
 }
 
@@ -290,6 +814,321 @@ mod tests {
         assert_eq!(spec.w.as_points(), 72.0);                 // 1 inch
         assert_eq!(spec.h.as_points(), 50.0);                 // 50 pt
         assert!(spec.font_size.is_none());
+        assert!(spec.ec_level.is_none());
+        assert!(spec.version.is_none());
+        assert!(spec.quiet_zone.is_none());
+    }
+
+    #[test]
+    fn test_field_spec_with_qr_options() {
+        let json = json!({
+            "x": 0,
+            "y": 0,
+            "w": "20 mm",
+            "h": "20 mm",
+            "type": "QR",
+            "ec_level": "H",
+            "min_version": 3,
+            "quiet_zone": 2
+        });
+        let spec: FieldSpec = serde_json::from_value(json).unwrap();
+        assert_eq!(spec.ec_level, Some(EcLevel::H));
+        assert_eq!(spec.min_version, Some(3));
+        assert!(spec.version.is_none());
+        assert_eq!(spec.quiet_zone, Some(2));
+        assert!(spec.render.is_none());
+    }
+
+    #[test]
+    fn test_field_spec_with_render_mode() {
+        let json = json!({
+            "x": 0,
+            "y": 0,
+            "w": 10,
+            "h": 10,
+            "type": "QR",
+            "render": "raster"
+        });
+        let spec: FieldSpec = serde_json::from_value(json).unwrap();
+        assert_eq!(spec.render, Some(RenderMode::Raster));
+    }
+
+    #[test]
+    fn test_field_spec_with_font() {
+        let json = json!({
+            "x": 0,
+            "y": 0,
+            "w": 10,
+            "h": 10,
+            "type": "Text",
+            "font": "fonts/Brand-Regular.ttf"
+        });
+        let spec: FieldSpec = serde_json::from_value(json).unwrap();
+        assert_eq!(spec.font.as_deref(), Some("fonts/Brand-Regular.ttf"));
+    }
+
+    #[test]
+    fn test_field_spec_with_text_layout() {
+        let json = json!({
+            "x": 0,
+            "y": 0,
+            "w": 10,
+            "h": 10,
+            "type": "Text",
+            "align": "center",
+            "valign": "bottom",
+            "wrap": "word",
+            "fit": "shrink"
+        });
+        let spec: FieldSpec = serde_json::from_value(json).unwrap();
+        assert_eq!(spec.align, Some(Align::Center));
+        assert_eq!(spec.valign, Some(VAlign::Bottom));
+        assert_eq!(spec.wrap, Some(WrapMode::Word));
+        assert_eq!(spec.fit, Some(FitMode::Shrink));
+        assert_eq!(spec.direction, None);
+    }
+
+    #[test]
+    fn test_color_spec_parse() {
+        assert_eq!(ColorSpec::parse("#FF0000"), Ok(ColorSpec::Rgb(1.0, 0.0, 0.0)));
+        assert_eq!(ColorSpec::parse("#fff"), Ok(ColorSpec::Rgb(1.0, 1.0, 1.0)));
+        assert_eq!(
+            ColorSpec::parse("cmyk(0, 100%, 1, 0.2)"),
+            Ok(ColorSpec::Cmyk(0.0, 1.0, 1.0, 0.2))
+        );
+        assert_eq!(ColorSpec::parse("gray(50%)"), Ok(ColorSpec::Gray(0.5)));
+        assert!(ColorSpec::parse("#12345").is_err());
+        assert!(ColorSpec::parse("cmyk(0, 0, 0)").is_err());
+        assert!(ColorSpec::parse("gray(2)").is_err());
+        assert!(ColorSpec::parse("red").is_err());
+        assert_eq!(
+            ColorSpec::parse("Spot(PANTONE 485 C, 0, 95%, 100%, 0)"),
+            Ok(ColorSpec::Separation { name: "PANTONE 485 C".to_string(), tint: 1.0, alternate: [0.0, 0.95, 1.0, 0.0] })
+        );
+        assert_eq!(
+            ColorSpec::parse("spot(Gold, 0, 0.2, 0.6, 0.1, 50%)"),
+            Ok(ColorSpec::Separation { name: "Gold".to_string(), tint: 0.5, alternate: [0.0, 0.2, 0.6, 0.1] })
+        );
+        assert!(ColorSpec::parse("spot(, 0, 0, 0, 1)").is_err());
+        assert!(ColorSpec::parse("spot(Gold, 0, 0, 1)").is_err());
+    }
+
+    #[test]
+    fn test_field_spec_with_text_style() {
+        let json = json!({
+            "x": 0,
+            "y": 0,
+            "w": 10,
+            "h": 10,
+            "type": "Text",
+            "color": "#FFFFFF",
+            "opacity": 0.5,
+            "char_spacing": "0.5 mm",
+            "word_spacing": 2,
+            "horizontal_scale": 80,
+            "text_mode": "fill_stroke",
+            "stroke_color": "cmyk(0, 0, 0, 1)",
+            "stroke_width": 0.5
+        });
+        let spec: FieldSpec = serde_json::from_value(json).unwrap();
+        assert_eq!(spec.color, Some(ColorSpec::Rgb(1.0, 1.0, 1.0)));
+        assert_eq!(spec.opacity, Some(0.5));
+        assert!((spec.char_spacing.unwrap().as_points() - 1.417).abs() < 0.001);
+        assert_eq!(spec.word_spacing.unwrap().as_points(), 2.0);
+        assert_eq!(spec.horizontal_scale, Some(80.0));
+        assert_eq!(spec.text_mode, Some(TextMode::FillStroke));
+        assert_eq!(spec.stroke_color, Some(ColorSpec::Cmyk(0.0, 0.0, 0.0, 1.0)));
+        assert_eq!(spec.stroke_width.unwrap().as_points(), 0.5);
+    }
+
+    #[test]
+    fn test_field_spec_with_rotation() {
+        let json = json!({
+            "x": 0,
+            "y": 0,
+            "w": 10,
+            "h": 10,
+            "type": "QR",
+            "rotate": 90,
+            "anchor": "top-left"
+        });
+        let spec: FieldSpec = serde_json::from_value(json).unwrap();
+        assert_eq!(spec.rotate, Some(90.0));
+        assert_eq!(spec.anchor, Some(Anchor::TopLeft));
+    }
+
+    #[test]
+    fn test_place_config_with_pages() {
+        let json = json!({
+            "fields": {
+                "Notes": { "x": 0, "y": 0, "w": 10, "h": 10, "type": "Text", "page": 2 }
+            },
+            "settings": { "template_page_column": "Class" }
+        });
+        let config: PlaceConfig = serde_json::from_value(json).unwrap();
+        assert_eq!(config.fields["Notes"].page, Some(2));
+        assert_eq!(config.settings.template_page_column.as_deref(), Some("Class"));
+    }
+
+    #[test]
+    fn test_place_config_with_layout() {
+        let json = json!({
+            "fields": {},
+            "settings": {
+                "layout": {
+                    "columns": 3,
+                    "rows": 8,
+                    "cell_width": "70 mm",
+                    "cell_height": "37 mm",
+                    "margin_top": "0.5 mm",
+                    "base": "cell",
+                    "sheet_width": "210 mm",
+                    "sheet_height": "297 mm"
+                }
+            }
+        });
+        let config: PlaceConfig = serde_json::from_value(json).unwrap();
+        let layout = config.settings.layout.unwrap();
+        assert_eq!((layout.columns, layout.rows), (Some(3), Some(8)));
+        assert!((layout.cell_width.unwrap().as_points() - 198.425).abs() < 0.01);
+        assert!(layout.gutter_x.is_none());
+        assert_eq!(layout.base, LayoutBase::Cell);
+        assert!(layout.sheet_height.is_some());
+    }
+
+    #[test]
+    fn test_layout_base_defaults_to_sheet() {
+        let json = json!({ "columns": 2, "rows": 2, "cell_width": 100, "cell_height": 50 });
+        let layout: LayoutSection = serde_json::from_value(json).unwrap();
+        assert_eq!(layout.base, LayoutBase::Sheet);
+        assert!(layout.sheet_width.is_none());
+    }
+
+    #[test]
+    fn test_place_config_with_sheet_preset() {
+        let json = json!({
+            "fields": {},
+            "settings": { "sheet": "avery-5160", "start_at": 4 }
+        });
+        let config: PlaceConfig = serde_json::from_value(json).unwrap();
+        assert_eq!(config.settings.sheet.as_deref(), Some("avery-5160"));
+        assert_eq!(config.settings.start_at, Some(4));
+        assert!(config.settings.layout.is_none());
+    }
+
+    #[test]
+    fn test_page_size_by_name() {
+        let settings: SettingsSection = serde_json::from_value(json!({
+            "page_size": "a4",
+            "orientation": "landscape"
+        })).unwrap();
+        let page_size = settings.page_size.unwrap();
+        assert!((page_size.width - 595.276).abs() < 0.01);
+        assert!((page_size.height - 841.89).abs() < 0.01);
+        let (width, height) = page_size.oriented(settings.orientation);
+        assert!(width > height);
+    }
+
+    #[test]
+    fn test_custom_page_size() {
+        let page_size: PageSize = serde_json::from_value(json!({
+            "width": "100 mm",
+            "height": "50 mm"
+        })).unwrap();
+        assert!((page_size.width - 283.465).abs() < 0.01);
+        assert_eq!(page_size.oriented(None), (page_size.width, page_size.height));
+        assert_eq!(page_size.oriented(Some(Orientation::Portrait)), (page_size.height, page_size.width));
+    }
+
+    #[test]
+    fn test_invalid_page_size() {
+        let err = serde_json::from_value::<PageSize>(json!("A9")).unwrap_err();
+        assert!(err.to_string().contains("Letter"));
+        assert!(serde_json::from_value::<PageSize>(json!({ "width": 0, "height": 10 })).is_err());
+    }
+
+    #[test]
+    fn test_marks_section() {
+        let settings: SettingsSection = serde_json::from_value(json!({
+            "marks": { "bleed": "3 mm", "registration_marks": true }
+        })).unwrap();
+        let marks = settings.marks.unwrap();
+        assert!((marks.bleed.unwrap().as_points() - 8.504).abs() < 0.01);
+        assert_eq!(marks.registration_marks, Some(true));
+        assert!(marks.crop_marks.is_none());
+        assert!(marks.mark_length.is_none());
+    }
+
+    #[test]
+    fn test_place_config_with_back() {
+        let json = json!({
+            "fields": {
+                "Name": { "x": 0, "y": 0, "w": 10, "h": 10, "type": "Text" }
+            },
+            "back": {
+                "page": 2,
+                "mirror": true,
+                "fields": {
+                    "URL": { "x": 0, "y": 0, "w": 10, "h": 10, "type": "QR" }
+                }
+            },
+            "settings": {}
+        });
+        let config: PlaceConfig = serde_json::from_value(json).unwrap();
+        let back = config.back.as_ref().unwrap();
+        assert_eq!(back.page, Some(2));
+        assert!(back.mirror);
+        let mut names: Vec<&String> = config.all_fields().map(|(name, _)| name).collect();
+        names.sort();
+        assert_eq!(names, ["Name", "URL"]);
+    }
+
+    #[test]
+    fn test_calibration_settings() {
+        let mut settings: SettingsSection = serde_json::from_value(json!({
+            "offset_x": "1.5 mm",
+            "scale_y": 0.99,
+            "printers": {
+                "office": { "offset_y": "-2 mm" }
+            }
+        })).unwrap();
+        assert!((settings.calibration.offset_x.unwrap().as_points() - 4.252).abs() < 0.01);
+        assert_eq!(settings.calibration.scale_y, Some(0.99));
+
+        settings.select_printer("office").unwrap();
+        assert!(settings.calibration.offset_x.is_none());
+        assert!((settings.calibration.offset_y.unwrap().as_points() + 5.669).abs() < 0.01);
+
+        let err = settings.select_printer("lab").unwrap_err();
+        assert!(err.to_string().contains("office"));
+    }
+
+    #[test]
+    fn test_field_spec_vertical_direction() {
+        let json = json!({
+            "x": 0,
+            "y": 0,
+            "w": 10,
+            "h": 10,
+            "type": "Text",
+            "direction": "vertical"
+        });
+        let spec: FieldSpec = serde_json::from_value(json).unwrap();
+        assert_eq!(spec.direction, Some(Direction::Vertical));
+    }
+
+    #[test]
+    fn test_field_spec_invalid_ec_level() {
+        let json = json!({
+            "x": 0,
+            "y": 0,
+            "w": 10,
+            "h": 10,
+            "type": "QR",
+            "ec_level": "X"
+        });
+        let result: Result<FieldSpec, _> = serde_json::from_value(json);
+        assert!(result.is_err());
     }
 
     #[test]
@@ -2,32 +2,63 @@ mod config;
 mod pdf;
 
 use anyhow::{anyhow, Context, Result};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use lopdf::Document;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use config::{load_base_pdf, load_csv_data, load_settings_config};
-use pdf::create_output_pdf;
+use config::{load_base_pdf, load_csv_data, load_settings_config, SettingsSection};
+use pdf::{create_calibration_pdf, create_output_pdf};
 
 /// Generate QR codes and place them on a PDF template.
 #[derive(Parser, Debug)]
 #[command(name = "qr_code_print")]
 #[command(about = "Generate QR codes and place them on a PDF template.", long_about = None)]
 struct Args {
-    /// Target directory containing base.pdf, settings.json, and data.csv
+    /// Target directory containing settings.json, data.csv and (optionally) base.pdf
     /// output.pdf will be saved in this directory
-    #[arg(short, long, default_value = ".")]
+    #[arg(short, long, default_value = ".", global = true)]
     target_dir: PathBuf,
+
+    /// Printer profile from the `printers` section of settings.json
+    #[arg(short, long, global = true)]
+    printer: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write calibration.pdf, a ruler and grid test page for measuring
+    /// printer offsets and scale
+    Calibrate,
 }
 
-fn run(target_dir: PathBuf) -> Result<()> {
-    // Verify the target directory exists
+/// Verify the target directory exists
+fn check_target_dir(target_dir: &Path) -> Result<()> {
     if !target_dir.exists() {
         return Err(anyhow!("Target directory not found: {:?}", target_dir));
     }
     if !target_dir.is_dir() {
         return Err(anyhow!("Target path is not a directory: {:?}", target_dir));
     }
+    Ok(())
+}
+
+/// Load base.pdf if the target directory has one
+fn load_base_doc(base_pdf_path: &Path) -> Result<Option<Document>> {
+    if !base_pdf_path.exists() {
+        return Ok(None);
+    }
+    println!("Loading base.pdf...");
+    let base_pdf_bytes = load_base_pdf(base_pdf_path)?;
+    let base_doc = Document::load_mem(&base_pdf_bytes)
+        .with_context(|| "Failed to load base.pdf")?;
+    Ok(Some(base_doc))
+}
+
+fn run(target_dir: &Path, printer: Option<&str>) -> Result<()> {
+    check_target_dir(target_dir)?;
 
     // Define file paths
     let settings_json_path = target_dir.join("settings.json");
@@ -35,38 +66,78 @@ fn run(target_dir: PathBuf) -> Result<()> {
     let base_pdf_path = target_dir.join("base.pdf");
     let output_pdf_path = target_dir.join("output.pdf");
 
-    // Check if required files exist
-    for path in [&settings_json_path, &data_csv_path, &base_pdf_path].iter() {
+    // Check if required files exist; base.pdf is optional
+    for path in [&settings_json_path, &data_csv_path].iter() {
         if !path.exists() {
             return Err(anyhow!("Required file not found: {:?}", path));
         }
     }
 
     println!("Loading configuration from settings.json...");
-    let config = load_settings_config(&settings_json_path)?;
+    let mut config = load_settings_config(&settings_json_path)?;
+    if let Some(printer) = printer {
+        config.settings.select_printer(printer)?;
+        println!("Using calibration of printer '{}'", printer);
+    }
 
     println!("Loading data from data.csv...");
     let data_rows = load_csv_data(&data_csv_path)?;
     println!("Found {} rows in data.csv", data_rows.len());
 
-    println!("Loading base.pdf...");
-    let base_pdf_bytes = load_base_pdf(&base_pdf_path)?;
-    let base_doc = Document::load_mem(&base_pdf_bytes)
-        .with_context(|| "Failed to load base.pdf")?;
+    let base_doc = load_base_doc(&base_pdf_path)?;
+    if base_doc.is_none() {
+        println!("No base.pdf found, generating blank pages...");
+    }
 
     println!("Generating output.pdf...");
-    let mut output_doc = create_output_pdf(&base_doc, &data_rows, &config)?;
+    let mut output_doc = create_output_pdf(base_doc.as_ref(), &data_rows, &config)?;
 
     output_doc.save(&output_pdf_path)?;
-    println!("Successfully saved output.pdf with {} pages", data_rows.len());
+    println!("Successfully saved output.pdf with {} pages", output_doc.get_pages().len());
+
+    Ok(())
+}
+
+/// Write calibration.pdf, sized like base.pdf or `settings.page_size`
+///
+/// settings.json is optional here, so a printer can be calibrated before
+/// anything else is set up.
+fn calibrate(target_dir: &Path, printer: Option<&str>) -> Result<()> {
+    check_target_dir(target_dir)?;
+
+    let settings_json_path = target_dir.join("settings.json");
+    let calibration_pdf_path = target_dir.join("calibration.pdf");
+
+    let mut settings = if settings_json_path.exists() {
+        println!("Loading configuration from settings.json...");
+        load_settings_config(&settings_json_path)?.settings
+    } else {
+        SettingsSection::default()
+    };
+    if let Some(printer) = printer {
+        settings.select_printer(printer)?;
+        println!("Using calibration of printer '{}'", printer);
+    }
+
+    let base_doc = load_base_doc(&target_dir.join("base.pdf"))?;
+
+    println!("Generating calibration.pdf...");
+    let mut calibration_doc = create_calibration_pdf(base_doc.as_ref(), &settings)?;
+    calibration_doc.save(&calibration_pdf_path)?;
+    println!("Successfully saved calibration.pdf");
 
     Ok(())
 }
 
 fn main() {
     let args = Args::parse();
+    let printer = args.printer.as_deref();
 
-    if let Err(e) = run(args.target_dir) {
+    let result = match args.command {
+        Some(Command::Calibrate) => calibrate(&args.target_dir, printer),
+        None => run(&args.target_dir, printer),
+    };
+    if let Err(e) = result {
         eprintln!("Error: {}", e);
         for cause in e.chain().skip(1) {
             eprintln!("Caused by: {}", cause);
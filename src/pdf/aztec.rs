@@ -0,0 +1,547 @@
+//! Aztec Code encoding.
+//!
+//! This module handles:
+//! - Text encoding in the upper, lower, mixed, punctuation and digit modes,
+//!   with binary shift for other bytes and FLG(0) for GS1 FNC1
+//! - Compact and full-range symbol selection by data size and error correction
+//! - Bit stuffing, Reed-Solomon check words and the mode message
+//! - The bullseye, orientation marks, reference grid and data layers
+
+use anyhow::{anyhow, Result};
+use super::eci::symbol_bytes;
+use super::gs1::{element_string, GROUP_SEPARATOR};
+use super::reed_solomon::GaloisField;
+
+/// Default minimum share of error correction, in percent of the data
+const DEFAULT_EC_PERCENT: u32 = 23;
+
+/// Largest number of layers of compact and full-range symbols
+const MAX_COMPACT_LAYERS: usize = 4;
+const MAX_FULL_LAYERS: usize = 32;
+
+/// Compact symbols hold at most 64 data words
+const MAX_COMPACT_DATA_WORDS: usize = 64;
+
+/// Longest binary shift, in bytes
+const MAX_BINARY_SHIFT: usize = 2047 + 31;
+
+/// Characters of the punctuation mode from code 6 on
+const PUNCTUATION: &[u8] = b"!\"#$%&'()*+,-./:;<=>?[]{}";
+
+/// Text encoding modes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Upper,
+    Lower,
+    Mixed,
+    Punct,
+    Digit,
+}
+
+impl Mode {
+    /// Code of a byte in this mode, if it has one
+    fn code(self, b: u8) -> Option<u32> {
+        let b32 = b as u32;
+        match (self, b) {
+            (Mode::Upper | Mode::Lower | Mode::Mixed | Mode::Digit, b' ') => Some(1),
+            (Mode::Upper, b'A'..=b'Z') => Some(b32 - b'A' as u32 + 2),
+            (Mode::Lower, b'a'..=b'z') => Some(b32 - b'a' as u32 + 2),
+            (Mode::Mixed, 1..=13) => Some(b32 + 1),
+            (Mode::Mixed, 27..=31) => Some(b32 - 27 + 15),
+            (Mode::Mixed, b'@') => Some(20),
+            (Mode::Mixed, b'\\') => Some(21),
+            (Mode::Mixed, b'^') => Some(22),
+            (Mode::Mixed, b'_') => Some(23),
+            (Mode::Mixed, b'`') => Some(24),
+            (Mode::Mixed, b'|') => Some(25),
+            (Mode::Mixed, b'~') => Some(26),
+            (Mode::Mixed, 127) => Some(27),
+            (Mode::Punct, b'\r') => Some(1),
+            (Mode::Punct, _) => PUNCTUATION.iter().position(|&p| p == b).map(|i| i as u32 + 6),
+            (Mode::Digit, b'0'..=b'9') => Some(b32 - b'0' as u32 + 2),
+            (Mode::Digit, b',') => Some(12),
+            (Mode::Digit, b'.') => Some(13),
+            _ => None,
+        }
+    }
+
+    /// Bits per code
+    fn bits(self) -> usize {
+        if self == Mode::Digit { 4 } else { 5 }
+    }
+
+    /// Codes latching from this mode to another, as (code, bits)
+    fn latch(self, to: Mode) -> &'static [(u32, usize)] {
+        match (self, to) {
+            (Mode::Upper, Mode::Lower) | (Mode::Mixed, Mode::Lower) => &[(28, 5)],
+            (Mode::Upper, Mode::Mixed) | (Mode::Lower, Mode::Mixed) => &[(29, 5)],
+            (Mode::Upper, Mode::Digit) | (Mode::Lower, Mode::Digit) => &[(30, 5)],
+            (Mode::Lower, Mode::Upper) => &[(30, 5), (14, 4)],
+            (Mode::Mixed, Mode::Upper) => &[(29, 5)],
+            (Mode::Mixed, Mode::Digit) => &[(29, 5), (30, 5)],
+            (Mode::Digit, Mode::Upper) => &[(14, 4)],
+            (Mode::Digit, Mode::Lower) => &[(14, 4), (28, 5)],
+            (Mode::Digit, Mode::Mixed) => &[(14, 4), (29, 5)],
+            _ => &[],
+        }
+    }
+}
+
+/// Bits written most significant first
+#[derive(Default)]
+struct BitWriter {
+    bits: Vec<bool>,
+}
+
+impl BitWriter {
+    fn push(&mut self, value: u32, count: usize) {
+        for i in (0..count).rev() {
+            self.bits.push(value >> i & 1 == 1);
+        }
+    }
+}
+
+/// Whether a byte can be written in one of the text modes
+fn is_text(b: u8) -> bool {
+    [Mode::Upper, Mode::Lower, Mode::Mixed, Mode::Punct, Mode::Digit]
+        .iter()
+        .any(|mode| mode.code(b).is_some())
+}
+
+/// Encode data bytes as a bit stream
+///
+/// Characters are written in the current mode where possible; punctuation
+/// and single upper case letters use shifts, other text characters latch to
+/// the mode that has them, and remaining bytes use binary shift. With `gs1`,
+/// the data starts with FLG(0) and group separators become FLG(0). An `eci`
+/// is announced first, as FLG(n) followed by the n digits of its number.
+fn encode_bits(data: &[u8], gs1: bool, eci: Option<u32>) -> Vec<bool> {
+    let mut out = BitWriter::default();
+    let mut mode = Mode::Upper;
+    let flg0 = |out: &mut BitWriter, mode: Mode| {
+        // P/S, FLG(n) with n = 0
+        out.push(0, mode.bits());
+        out.push(0, 5);
+        out.push(0, 3);
+    };
+    if gs1 {
+        flg0(&mut out, mode);
+    }
+    if let Some(eci) = eci {
+        let digits = eci.to_string();
+        out.push(0, mode.bits());
+        out.push(0, 5);
+        out.push(digits.len() as u32, 3);
+        for digit in digits.bytes() {
+            out.push(Mode::Digit.code(digit).unwrap_or(0), 4);
+        }
+    }
+
+    let mut i = 0;
+    while i < data.len() {
+        let b = data[i];
+        if gs1 && b == GROUP_SEPARATOR {
+            flg0(&mut out, mode);
+            i += 1;
+            continue;
+        }
+        if let Some(code) = mode.code(b) {
+            out.push(code, mode.bits());
+            i += 1;
+            continue;
+        }
+        if let Some(code) = Mode::Punct.code(b) {
+            out.push(0, mode.bits());
+            out.push(code, 5);
+            i += 1;
+            continue;
+        }
+        let next_is_upper = data.get(i + 1).is_some_and(u8::is_ascii_uppercase);
+        if b.is_ascii_uppercase() && !next_is_upper && matches!(mode, Mode::Lower | Mode::Digit) {
+            // U/S
+            out.push(if mode == Mode::Digit { 15 } else { 28 }, mode.bits());
+            out.push(Mode::Upper.code(b).unwrap_or(0), 5);
+            i += 1;
+            continue;
+        }
+        if let Some(target) = [Mode::Upper, Mode::Lower, Mode::Mixed, Mode::Digit]
+            .into_iter()
+            .find(|m| m.code(b).is_some())
+        {
+            for &(code, bits) in mode.latch(target) {
+                out.push(code, bits);
+            }
+            mode = target;
+            continue;
+        }
+
+        // Binary shift for a run of bytes no text mode has
+        let run = data[i..].iter()
+            .take_while(|&&b| !is_text(b))
+            .count()
+            .min(MAX_BINARY_SHIFT);
+        if mode == Mode::Digit {
+            out.push(14, 4);
+            mode = Mode::Upper;
+        }
+        out.push(31, 5);
+        if run <= 31 {
+            out.push(run as u32, 5);
+        } else {
+            out.push(0, 5);
+            out.push((run - 31) as u32, 11);
+        }
+        for &byte in &data[i..i + run] {
+            out.push(byte as u32, 8);
+        }
+        i += run;
+    }
+    out.bits
+}
+
+/// Bits per codeword of a symbol with `layers` layers
+fn word_size(layers: usize) -> usize {
+    match layers {
+        1..=2 => 6,
+        3..=8 => 8,
+        9..=22 => 10,
+        _ => 12,
+    }
+}
+
+/// Galois field of codewords of `word_size` bits
+fn field(word_size: usize) -> GaloisField {
+    match word_size {
+        4 => GaloisField::new(4, 0x13),
+        6 => GaloisField::new(6, 0x43),
+        8 => GaloisField::new(8, 0x12D),
+        10 => GaloisField::new(10, 0x409),
+        _ => GaloisField::new(12, 0x1069),
+    }
+}
+
+/// Number of bits in the data layers
+fn total_bits_in_layers(layers: usize, compact: bool) -> usize {
+    ((if compact { 88 } else { 112 }) + 16 * layers) * layers
+}
+
+/// Split bits into codewords, stuffing a bit into words that would be all
+/// zeros or all ones (the last word is padded with ones)
+fn stuff_bits(bits: &[bool], word_size: usize) -> Vec<bool> {
+    let mut out = BitWriter::default();
+    let mask = (1u32 << word_size) - 2;
+    let mut i = 0;
+    while i < bits.len() {
+        let mut word = 0u32;
+        for j in 0..word_size {
+            if bits.get(i + j).is_none_or(|&bit| bit) {
+                word |= 1 << (word_size - 1 - j);
+            }
+        }
+        if word & mask == mask {
+            out.push(word & mask, word_size);
+            i += word_size - 1;
+        } else if word & mask == 0 {
+            out.push(word | 1, word_size);
+            i += word_size - 1;
+        } else {
+            out.push(word, word_size);
+            i += word_size;
+        }
+    }
+    out.bits
+}
+
+/// Data words followed by check words, filling `total_bits` (leading bits
+/// that do not make a whole word are zero)
+fn add_check_words(bits: &[bool], total_bits: usize, word_size: usize) -> Vec<bool> {
+    let words: Vec<u16> = bits.chunks(word_size)
+        .map(|chunk| chunk.iter().fold(0, |word, &bit| word << 1 | bit as u16))
+        .collect();
+    let total_words = total_bits / word_size;
+    let check = field(word_size).check_codewords(&words, total_words - words.len(), 1);
+
+    let mut out = BitWriter::default();
+    out.push(0, total_bits % word_size);
+    for word in words.iter().chain(&check) {
+        out.push(*word as u32, word_size);
+    }
+    out.bits
+}
+
+/// Mode message: the layer count and data word count with their check words
+fn mode_message(compact: bool, layers: usize, data_words: usize) -> Vec<bool> {
+    let mut out = BitWriter::default();
+    if compact {
+        out.push(layers as u32 - 1, 2);
+        out.push(data_words as u32 - 1, 6);
+        add_check_words(&out.bits, 28, 4)
+    } else {
+        out.push(layers as u32 - 1, 5);
+        out.push(data_words as u32 - 1, 11);
+        add_check_words(&out.bits, 40, 4)
+    }
+}
+
+/// Parse a symbol size: `"compact-N"` (1-4 layers) or `"full-N"` (1-32 layers)
+fn parse_size(name: &str) -> Result<(bool, usize)> {
+    let (kind, layers) = name.split_once('-')
+        .ok_or_else(|| anyhow!("Invalid Aztec size '{}': use \"compact-N\" or \"full-N\"", name))?;
+    let compact = match kind.trim().to_ascii_lowercase().as_str() {
+        "compact" => true,
+        "full" => false,
+        _ => return Err(anyhow!("Invalid Aztec size '{}': use \"compact-N\" or \"full-N\"", name)),
+    };
+    let max = if compact { MAX_COMPACT_LAYERS } else { MAX_FULL_LAYERS };
+    match layers.trim().parse::<usize>() {
+        Ok(layers) if (1..=max).contains(&layers) => Ok((compact, layers)),
+        _ => Err(anyhow!("Invalid Aztec size '{}': {} symbols have 1 to {} layers", name, kind, max)),
+    }
+}
+
+/// Symbol type, layer count and stuffed data bits for the encoded data
+fn choose_symbol(bits: &[bool], ec_bits: usize, size: Option<&str>) -> Result<(bool, usize, Vec<bool>)> {
+    let fits = |compact: bool, layers: usize, stuffed: &[bool]| {
+        let word_size = word_size(layers);
+        let total_bits = total_bits_in_layers(layers, compact);
+        let usable_bits = total_bits - total_bits % word_size;
+        stuffed.len() + ec_bits <= usable_bits
+            && !(compact && stuffed.len() > word_size * MAX_COMPACT_DATA_WORDS)
+    };
+
+    if let Some(name) = size {
+        let (compact, layers) = parse_size(name)?;
+        let stuffed = stuff_bits(bits, word_size(layers));
+        if !fits(compact, layers, &stuffed) {
+            return Err(anyhow!("Data for Aztec does not fit in size {}", name));
+        }
+        return Ok((compact, layers, stuffed));
+    }
+
+    // Compact symbols first, then full-range symbols from four layers
+    let candidates = (1..=MAX_COMPACT_LAYERS).map(|layers| (true, layers))
+        .chain((MAX_COMPACT_LAYERS..=MAX_FULL_LAYERS).map(|layers| (false, layers)));
+    for (compact, layers) in candidates {
+        if bits.len() + ec_bits > total_bits_in_layers(layers, compact) {
+            continue;
+        }
+        let stuffed = stuff_bits(bits, word_size(layers));
+        if fits(compact, layers, &stuffed) {
+            return Ok((compact, layers, stuffed));
+        }
+    }
+    Err(anyhow!("Data for Aztec is too long ({} bits)", bits.len()))
+}
+
+/// Square matrix of modules
+struct Matrix {
+    size: usize,
+    modules: Vec<bool>,
+}
+
+impl Matrix {
+    fn set(&mut self, x: usize, y: usize) {
+        self.modules[y * self.size + x] = true;
+    }
+
+    /// Bullseye rings around the centre, with the orientation marks at its corners
+    fn draw_bullseye(&mut self, center: usize, size: usize) {
+        for i in (0..size).step_by(2) {
+            for j in center - i..=center + i {
+                self.set(j, center - i);
+                self.set(j, center + i);
+                self.set(center - i, j);
+                self.set(center + i, j);
+            }
+        }
+        self.set(center - size, center - size);
+        self.set(center - size + 1, center - size);
+        self.set(center - size, center - size + 1);
+        self.set(center + size, center - size);
+        self.set(center + size, center - size + 1);
+        self.set(center + size, center + size - 1);
+    }
+
+    /// Mode message around the bullseye, clockwise from the top-left
+    fn draw_mode_message(&mut self, compact: bool, message: &[bool]) {
+        let center = self.size / 2;
+        if compact {
+            for i in 0..7 {
+                let offset = center - 3 + i;
+                if message[i] { self.set(offset, center - 5); }
+                if message[i + 7] { self.set(center + 5, offset); }
+                if message[20 - i] { self.set(offset, center + 5); }
+                if message[27 - i] { self.set(center - 5, offset); }
+            }
+        } else {
+            for i in 0..10 {
+                let offset = center - 5 + i + i / 5;
+                if message[i] { self.set(offset, center - 7); }
+                if message[i + 10] { self.set(center + 7, offset); }
+                if message[29 - i] { self.set(offset, center + 7); }
+                if message[39 - i] { self.set(center - 7, offset); }
+            }
+        }
+    }
+}
+
+/// Encode `data` as an Aztec symbol
+///
+/// Returns the width, height and row-major dark modules (top row first).
+/// `size` forces a symbol (`"compact-N"` or `"full-N"` layers) and
+/// `ec_percent` sets the minimum error correction (default 23%). With `gs1`,
+/// the data is GS1 `(AI)value` pairs.
+pub fn encode(data: &str, size: Option<&str>, ec_percent: Option<u32>, gs1: bool) -> Result<(usize, usize, Vec<bool>)> {
+    let ec_percent = ec_percent.unwrap_or(DEFAULT_EC_PERCENT);
+    if !(5..=95).contains(&ec_percent) {
+        return Err(anyhow!("Aztec error correction must be 5% to 95%, got {}%", ec_percent));
+    }
+    let (bytes, eci) = if gs1 { (element_string(data)?, None) } else { symbol_bytes(data) };
+    let bits = encode_bits(&bytes, gs1, eci);
+    let ec_bits = bits.len() * ec_percent as usize / 100 + 11;
+    let (compact, layers, stuffed) = choose_symbol(&bits, ec_bits, size)?;
+
+    let word_size = word_size(layers);
+    let total_bits = total_bits_in_layers(layers, compact);
+    let message = add_check_words(&stuffed, total_bits, word_size);
+    let mode_message = mode_message(compact, layers, stuffed.len() / word_size);
+
+    // Full-range symbols have a reference grid line every 16 modules from the
+    // centre, so data positions are mapped around it
+    let base_size = if compact { 11 } else { 14 } + layers * 4;
+    let mut alignment_map = vec![0; base_size];
+    let size = if compact {
+        for (i, value) in alignment_map.iter_mut().enumerate() {
+            *value = i;
+        }
+        base_size
+    } else {
+        let size = base_size + 1 + 2 * ((base_size / 2 - 1) / 15);
+        let (original_center, center) = (base_size / 2, size / 2);
+        for i in 0..original_center {
+            let offset = i + i / 15;
+            alignment_map[original_center - i - 1] = center - offset - 1;
+            alignment_map[original_center + i] = center + offset + 1;
+        }
+        size
+    };
+    let mut matrix = Matrix { size, modules: vec![false; size * size] };
+
+    // Data layers, from the outside in, each side as pairs of module rows
+    let mut row_offset = 0;
+    for i in 0..layers {
+        let row_size = (layers - i) * 4 + if compact { 9 } else { 12 };
+        for j in 0..row_size {
+            let column_offset = j * 2;
+            for k in 0..2 {
+                let bit = |n: usize| message[row_offset + n * row_size * 2 + column_offset + k];
+                let (near, far) = (i * 2 + k, base_size - 1 - i * 2 - k);
+                let (along, back) = (i * 2 + j, base_size - 1 - i * 2 - j);
+                if bit(0) { matrix.set(alignment_map[near], alignment_map[along]); }
+                if bit(1) { matrix.set(alignment_map[along], alignment_map[far]); }
+                if bit(2) { matrix.set(alignment_map[far], alignment_map[back]); }
+                if bit(3) { matrix.set(alignment_map[back], alignment_map[near]); }
+            }
+        }
+        row_offset += row_size * 8;
+    }
+
+    matrix.draw_mode_message(compact, &mode_message);
+    let center = size / 2;
+    if compact {
+        matrix.draw_bullseye(center, 5);
+    } else {
+        matrix.draw_bullseye(center, 7);
+        // Reference grid: alternating modules every 16 rows and columns
+        let mut j = 0;
+        for _ in (0..base_size / 2 - 1).step_by(15) {
+            for k in ((center & 1)..size).step_by(2) {
+                matrix.set(center - j, k);
+                matrix.set(center + j, k);
+                matrix.set(k, center - j);
+                matrix.set(k, center + j);
+            }
+            j += 16;
+        }
+    }
+    Ok((size, size, matrix.modules))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bits from a string of X (1) and . (0), ignoring spaces
+    fn bits(pattern: &str) -> Vec<bool> {
+        pattern.chars().filter(|c| *c != ' ').map(|c| c == 'X').collect()
+    }
+
+    #[test]
+    fn test_mode_message() {
+        assert_eq!(mode_message(true, 2, 29), bits(".X .XXX.. ...X XX.. ..X .XX. .XX.X"));
+        assert_eq!(mode_message(false, 21, 660), bits("X.X.. .X.X..X..XX .XXX ..X.. .XXX. .X... ..XXX"));
+    }
+
+    #[test]
+    fn test_stuff_bits() {
+        assert_eq!(stuff_bits(&bits(".X.X. X.X.X .X.X."), 5), bits(".X.X. X.X.X .X.X."));
+        assert_eq!(stuff_bits(&bits(".X.X. ..... .X.X"), 5), bits(".X.X. ....X ..X.X"));
+        assert_eq!(stuff_bits(&bits("XXXXX X"), 5), bits("XXXX. XXXX."));
+    }
+
+    #[test]
+    fn test_encode_bits() {
+        // Upper "A" (2), space (1), D/L, "1" (3)
+        assert_eq!(encode_bits(b"A 1", false, None), bits("...X. ....X XXXX. ..XX"));
+        // Lower latch, "a", P/S and "!" (6)
+        assert_eq!(encode_bits(b"a!", false, None), bits("XXX.. ...X. ..... ..XX."));
+        // U/S from lower case
+        assert_eq!(encode_bits(b"aB", false, None), bits("XXX.. ...X. XXX.. ...XX"));
+        // B/S with a length of 1 for "é" in Latin-1
+        assert_eq!(encode_bits(b"\xE9", false, None), bits("XXXXX ....X XXX.X..X"));
+        // ECI 26: P/S, FLG(2), digits "2" and "6"
+        assert_eq!(encode_bits(b"A", false, Some(26)), bits("..... ..... .X. .X.. X... ...X."));
+        // GS1: FLG(0) first and between values
+        assert_eq!(encode_bits(b"\x1d", true, None), bits("..... ..... ... ..... ..... ..."));
+    }
+
+    #[test]
+    fn test_check_words_fill_layers() {
+        let stuffed = stuff_bits(&encode_bits(b"AZTEC", false, None), 6);
+        let message = add_check_words(&stuffed, total_bits_in_layers(1, true), 6);
+        assert_eq!(message.len(), 104);
+        // 104 bits hold 17 words of 6 bits after 2 padding bits
+        assert!(!message[0] && !message[1]);
+    }
+
+    #[test]
+    fn test_symbol_sizes() {
+        let (width, height, modules) = encode("AZTEC", None, None, false).unwrap();
+        assert_eq!((width, height), (15, 15));
+        // Centre module and the first ring of the bullseye
+        assert!(modules[7 * 15 + 7]);
+        assert!(!modules[7 * 15 + 6]);
+        assert!(modules[7 * 15 + 5]);
+
+        let (width, _, _) = encode(&"A".repeat(200), None, None, false).unwrap();
+        assert!(width > 27);
+        let (width, _, _) = encode("AZTEC", Some("full-5"), None, false).unwrap();
+        // 14 + 5 * 4 modules plus one reference grid line on each side and the centre
+        assert_eq!(width, 37);
+        assert!(encode(&"A".repeat(100), Some("compact-1"), None, false).is_err());
+        assert!(encode("A", Some("compact-5"), None, false).is_err());
+        assert!(encode("A", None, Some(99), false).is_err());
+    }
+
+    #[test]
+    fn test_reference_grid() {
+        let (size, _, modules) = encode("AZTEC", Some("full-5"), None, false).unwrap();
+        let center = size / 2;
+        // Grid lines through the centre alternate outside the bullseye
+        for k in 0..size {
+            if k.abs_diff(center) > 7 + 2 {
+                assert_eq!(modules[center * size + k], k % 2 == center % 2, "column {}", k);
+            }
+        }
+    }
+}
@@ -0,0 +1,509 @@
+//! 1D barcode encoding.
+//!
+//! This module handles:
+//! - Code 128 (with automatic code set selection), Code 39, EAN-13, UPC-A and ITF-14
+//! - GTIN check digit calculation and validation
+//! - Bar patterns with the quiet zones each symbology requires
+
+use anyhow::{anyhow, Result};
+
+/// Bar and space widths of the Code 128 symbols 0-105, in modules
+const CODE128_PATTERNS: [&str; 106] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212", "221213",
+    "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221", "223211", "221132",
+    "221231", "213212", "223112", "312131", "311222", "321122", "321221", "312212", "322112", "322211",
+    "212123", "212321", "232121", "111323", "131123", "131321", "112313", "132113", "132311", "211313",
+    "231113", "231311", "112133", "112331", "132131", "113123", "113321", "133121", "313121", "211331",
+    "231131", "213113", "213311", "213131", "311123", "311321", "331121", "312113", "312311", "332111",
+    "314111", "221411", "431111", "111224", "111422", "121124", "121421", "141122", "141221", "112214",
+    "112412", "122114", "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111",
+    "111242", "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311", "113141",
+    "114131", "311141", "411131", "211412", "211214", "211232",
+];
+
+/// Code 128 stop pattern, including the final bar
+const CODE128_STOP: &str = "2331112";
+
+/// Code 128 start symbols
+const CODE128_START_A: u32 = 103;
+const CODE128_START_B: u32 = 104;
+const CODE128_START_C: u32 = 105;
+
+/// Code 128 symbols switching to another code set
+const CODE128_CODE_A: u32 = 101;
+const CODE128_CODE_B: u32 = 100;
+const CODE128_CODE_C: u32 = 99;
+
+/// Characters Code 39 can encode, in the order of `CODE39_PATTERNS`
+const CODE39_CHARS: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ-. $/+%*";
+
+/// Narrow (n) and wide (w) elements of each Code 39 character, starting with a bar
+const CODE39_PATTERNS: [&str; 44] = [
+    "nnnwwnwnn", "wnnwnnnnw", "nnwwnnnnw", "wnwwnnnnn", "nnnwwnnnw", "wnnwwnnnn", "nnwwwnnnn", "nnnwnnwnw",
+    "wnnwnnwnn", "nnwwnnwnn", "wnnnnwnnw", "nnwnnwnnw", "wnwnnwnnn", "nnnnwwnnw", "wnnnwwnnn", "nnwnwwnnn",
+    "nnnnnwwnw", "wnnnnwwnn", "nnwnnwwnn", "nnnnwwwnn", "wnnnnnnww", "nnwnnnnww", "wnwnnnnwn", "nnnnwnnww",
+    "wnnnwnnwn", "nnwnwnnwn", "nnnnnnwww", "wnnnnnwwn", "nnwnnnwwn", "nnnnwnwwn", "wwnnnnnnw", "nwwnnnnnw",
+    "wwwnnnnnn", "nwnnwnnnw", "wwnnwnnnn", "nwwnwnnnn", "nwnnnnwnw", "wwnnnnwnn", "nwwnnnwnn", "nwnwnwnnn",
+    "nwnwnnnwn", "nwnnnwnwn", "nnnwnwnwn", "nwnnwnwnn",
+];
+
+/// Width of wide Code 39 and ITF elements, in narrow modules
+const WIDE: u32 = 3;
+
+/// EAN/UPC left-hand odd parity (L) patterns of the digits 0-9
+const EAN_L_PATTERNS: [&str; 10] = [
+    "0001101", "0011001", "0010011", "0111101", "0100011",
+    "0110001", "0101111", "0111011", "0110111", "0001011",
+];
+
+/// Parity (L or G) of the six left-hand EAN-13 digits, by first digit
+const EAN_PARITY: [&str; 10] = [
+    "LLLLLL", "LLGLGG", "LLGGLG", "LLGGGL", "LGLLGG",
+    "LGGLLG", "LGGGLL", "LGLGLG", "LGLGGL", "LGGLGL",
+];
+
+/// Narrow (n) and wide (w) elements of the ITF digits 0-9
+const ITF_PATTERNS: [&str; 10] = [
+    "nnwwn", "wnnnw", "nwnnw", "wwnnn", "nnwnw",
+    "wnwnn", "nwwnn", "nnnww", "wnnwn", "nwnwn",
+];
+
+/// 1D barcode symbologies, selected by the field `type`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarcodeKind {
+    Code128,
+    Code39,
+    Ean13,
+    UpcA,
+    Itf14,
+}
+
+impl BarcodeKind {
+    /// Barcode kind for a field `type`, or `None` for other types
+    pub fn from_type(output_type: &str) -> Option<Self> {
+        match output_type {
+            "Code128" => Some(Self::Code128),
+            "Code39" => Some(Self::Code39),
+            "EAN13" => Some(Self::Ean13),
+            "UPCA" => Some(Self::UpcA),
+            "ITF14" => Some(Self::Itf14),
+            _ => None,
+        }
+    }
+
+    /// Minimum quiet zones (left, right) in modules
+    fn quiet_zone(self) -> (u32, u32) {
+        match self {
+            Self::Code128 | Self::Code39 | Self::Itf14 => (10, 10),
+            Self::Ean13 => (11, 7),
+            Self::UpcA => (9, 9),
+        }
+    }
+}
+
+/// Bars of an encoded 1D barcode
+#[derive(Debug, Clone, PartialEq)]
+pub struct Barcode {
+    /// Dark bars as (start, width), in modules from the left edge of the
+    /// left quiet zone
+    pub bars: Vec<(u32, u32)>,
+    /// Total width in modules, including both quiet zones
+    pub width: u32,
+    /// Human-readable text, including any computed check digit
+    pub text: String,
+}
+
+impl Barcode {
+    /// Encode `data`, surrounded by the symbology's quiet zones (or
+    /// `quiet_zone` modules on both sides)
+    pub fn encode(kind: BarcodeKind, data: &str, quiet_zone: Option<u32>) -> Result<Self> {
+        if data.is_empty() {
+            return Err(anyhow!("No data for {:?} barcode", kind));
+        }
+        let (modules, text) = match kind {
+            BarcodeKind::Code128 => (code128_modules(data)?, data.to_string()),
+            BarcodeKind::Code39 => (code39_modules(data)?, data.to_string()),
+            BarcodeKind::Ean13 => {
+                let digits = gtin_digits("EAN-13", data, 13)?;
+                (ean13_modules(&digits), digits)
+            }
+            BarcodeKind::UpcA => {
+                // UPC-A is an EAN-13 with a leading zero
+                let digits = gtin_digits("UPC-A", data, 12)?;
+                (ean13_modules(&format!("0{}", digits)), digits)
+            }
+            BarcodeKind::Itf14 => {
+                let digits = gtin_digits("ITF-14", data, 14)?;
+                (itf_modules(&digits), digits)
+            }
+        };
+        let (left, right) = quiet_zone.map_or(kind.quiet_zone(), |q| (q, q));
+        Ok(Self::from_modules(&modules, left, right, text))
+    }
+
+    /// Collect the runs of dark modules into bars
+    fn from_modules(modules: &[bool], left: u32, right: u32, text: String) -> Self {
+        let mut bars: Vec<(u32, u32)> = Vec::new();
+        for (i, &dark) in modules.iter().enumerate() {
+            if !dark {
+                continue;
+            }
+            let x = left + i as u32;
+            match bars.last_mut() {
+                Some((start, width)) if *start + *width == x => *width += 1,
+                _ => bars.push((x, 1)),
+            }
+        }
+        Self {
+            bars,
+            width: left + modules.len() as u32 + right,
+            text,
+        }
+    }
+}
+
+/// Append elements of the given widths, alternating dark and light, starting dark
+fn push_elements(modules: &mut Vec<bool>, widths: impl IntoIterator<Item = u32>) {
+    for (i, width) in widths.into_iter().enumerate() {
+        modules.extend(std::iter::repeat_n(i % 2 == 0, width as usize));
+    }
+}
+
+/// Widths of a pattern of width digits
+fn digit_widths(pattern: &str) -> impl Iterator<Item = u32> + '_ {
+    pattern.chars().map(|c| c.to_digit(10).unwrap_or(0))
+}
+
+/// Widths of a pattern of narrow (n) and wide (w) elements
+fn narrow_wide_widths(pattern: &str) -> impl Iterator<Item = u32> + '_ {
+    pattern.chars().map(|c| if c == 'w' { WIDE } else { 1 })
+}
+
+/// Code 128 code sets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CodeSet {
+    /// Upper case, digits and control characters
+    A,
+    /// Upper and lower case and digits
+    B,
+    /// Pairs of digits
+    C,
+}
+
+/// Code set A or B for the characters from `bytes`: A when a control
+/// character comes before any lower case character
+fn code128_alpha_set(bytes: &[u8]) -> CodeSet {
+    for &b in bytes {
+        if b < 32 {
+            return CodeSet::A;
+        }
+        if b >= 96 {
+            return CodeSet::B;
+        }
+    }
+    CodeSet::B
+}
+
+/// Value of an ASCII character in code set A or B
+fn code128_char_value(set: CodeSet, b: u8) -> u32 {
+    match set {
+        CodeSet::A if b < 32 => b as u32 + 64,
+        _ => b as u32 - 32,
+    }
+}
+
+/// Code 128 symbol values, from the start symbol to the check symbol
+///
+/// Runs of at least four digits (six in the middle of the data) use code
+/// set C; other characters use code set B, or A for control characters.
+fn code128_values(data: &str) -> Result<Vec<u32>> {
+    if let Some(c) = data.chars().find(|c| !c.is_ascii()) {
+        return Err(anyhow!("Code 128 cannot encode '{}' (ASCII only): {}", c, data));
+    }
+    let bytes = data.as_bytes();
+    let digit_run = |from: usize| bytes[from..].iter().take_while(|b| b.is_ascii_digit()).count();
+
+    let run = digit_run(0);
+    let mut set = if run >= 4 || (run >= 2 && run == bytes.len()) {
+        CodeSet::C
+    } else {
+        code128_alpha_set(bytes)
+    };
+    let mut values = vec![match set {
+        CodeSet::A => CODE128_START_A,
+        CodeSet::B => CODE128_START_B,
+        CodeSet::C => CODE128_START_C,
+    }];
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let run = digit_run(i);
+        if set == CodeSet::C {
+            if run >= 2 {
+                values.push(((bytes[i] - b'0') * 10 + bytes[i + 1] - b'0') as u32);
+                i += 2;
+                continue;
+            }
+            set = code128_alpha_set(&bytes[i..]);
+            values.push(if set == CodeSet::A { CODE128_CODE_A } else { CODE128_CODE_B });
+            continue;
+        }
+        if run >= 6 || (run >= 4 && i + run == bytes.len()) {
+            // An odd run keeps its first digit in the current code set
+            if run % 2 == 1 {
+                values.push(code128_char_value(set, bytes[i]));
+                i += 1;
+            }
+            set = CodeSet::C;
+            values.push(CODE128_CODE_C);
+            continue;
+        }
+        let b = bytes[i];
+        if set == CodeSet::B && b < 32 {
+            set = CodeSet::A;
+            values.push(CODE128_CODE_A);
+        } else if set == CodeSet::A && b >= 96 {
+            set = CodeSet::B;
+            values.push(CODE128_CODE_B);
+        }
+        values.push(code128_char_value(set, b));
+        i += 1;
+    }
+
+    let checksum = values.iter().enumerate()
+        .map(|(i, value)| i.max(1) as u32 * value)
+        .sum::<u32>() % 103;
+    values.push(checksum);
+    Ok(values)
+}
+
+fn code128_modules(data: &str) -> Result<Vec<bool>> {
+    let mut modules = Vec::new();
+    for value in code128_values(data)? {
+        push_elements(&mut modules, digit_widths(CODE128_PATTERNS[value as usize]));
+    }
+    push_elements(&mut modules, digit_widths(CODE128_STOP));
+    Ok(modules)
+}
+
+fn code39_modules(data: &str) -> Result<Vec<bool>> {
+    let mut indices = Vec::new();
+    for c in data.chars() {
+        match CODE39_CHARS.find(c) {
+            Some(index) if c != '*' => indices.push(index),
+            _ => {
+                return Err(anyhow!(
+                    "Code 39 cannot encode '{}' (digits, upper case letters, space and - . $ / + % only): {}",
+                    c, data
+                ));
+            }
+        }
+    }
+
+    // The data is framed by the * start and stop character
+    let star = CODE39_CHARS.len() - 1;
+    let mut modules = Vec::new();
+    for (i, index) in std::iter::once(star).chain(indices).chain(std::iter::once(star)).enumerate() {
+        if i > 0 {
+            // Narrow gap between characters
+            modules.push(false);
+        }
+        push_elements(&mut modules, narrow_wide_widths(CODE39_PATTERNS[index]));
+    }
+    Ok(modules)
+}
+
+/// GTIN check digit (EAN, UPC and ITF-14) of the digits before it
+pub fn gtin_check_digit(digits: &str) -> u32 {
+    let sum: u32 = digits.chars().rev()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { d * 3 } else { d })
+        .sum();
+    (10 - sum % 10) % 10
+}
+
+/// Digits of a GTIN of `length` digits, with the check digit appended when
+/// it is left out or validated when it is given
+fn gtin_digits(name: &str, data: &str, length: usize) -> Result<String> {
+    if !data.chars().all(|c| c.is_ascii_digit()) {
+        return Err(anyhow!("{} barcode needs digits only: {}", name, data));
+    }
+    if data.len() == length - 1 {
+        return Ok(format!("{}{}", data, gtin_check_digit(data)));
+    }
+    if data.len() != length {
+        return Err(anyhow!(
+            "{} barcode needs {} digits (or {} without the check digit), got {}: {}",
+            name, length, length - 1, data.len(), data
+        ));
+    }
+    let (payload, check) = data.split_at(length - 1);
+    let expected = gtin_check_digit(payload);
+    if check.parse::<u32>().ok() != Some(expected) {
+        return Err(anyhow!("Invalid {} check digit in {}: expected {}", name, data, expected));
+    }
+    Ok(data.to_string())
+}
+
+/// Modules of an EAN-13 symbol of 13 digits
+fn ean13_modules(digits: &str) -> Vec<bool> {
+    let digits: Vec<usize> = digits.chars().filter_map(|c| c.to_digit(10)).map(|d| d as usize).collect();
+    let bits = |pattern: &str, invert: bool, reverse: bool| -> Vec<bool> {
+        let mut bits: Vec<bool> = pattern.chars().map(|c| (c == '1') != invert).collect();
+        if reverse {
+            bits.reverse();
+        }
+        bits
+    };
+    let mut modules = bits("101", false, false);
+    for (i, &digit) in digits[1..7].iter().enumerate() {
+        // G patterns are the R patterns reversed
+        let even = EAN_PARITY[digits[0]].as_bytes()[i] == b'G';
+        modules.extend(bits(EAN_L_PATTERNS[digit], even, even));
+    }
+    modules.extend(bits("01010", false, false));
+    for &digit in &digits[7..] {
+        modules.extend(bits(EAN_L_PATTERNS[digit], true, false));
+    }
+    modules.extend(bits("101", false, false));
+    modules
+}
+
+/// Modules of an ITF symbol of an even number of digits
+fn itf_modules(digits: &str) -> Vec<bool> {
+    let digits: Vec<usize> = digits.chars().filter_map(|c| c.to_digit(10)).map(|d| d as usize).collect();
+    let mut widths = vec![1, 1, 1, 1];
+    for pair in digits.chunks(2) {
+        // The first digit of each pair is in the bars, the second in the spaces
+        let bars = narrow_wide_widths(ITF_PATTERNS[pair[0]]);
+        let spaces = narrow_wide_widths(ITF_PATTERNS[pair[1]]);
+        for (bar, space) in bars.zip(spaces) {
+            widths.extend([bar, space]);
+        }
+    }
+    widths.extend([WIDE, 1, 1]);
+    let mut modules = Vec::new();
+    push_elements(&mut modules, widths);
+    modules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Modules as a string of 1 (dark) and 0 (light), without quiet zones
+    fn module_string(barcode: &Barcode, left: u32) -> String {
+        let mut modules = vec!['0'; barcode.width as usize];
+        for &(start, width) in &barcode.bars {
+            for x in start..start + width {
+                modules[x as usize] = '1';
+            }
+        }
+        let end = barcode.bars.last().map_or(0, |(start, width)| start + width);
+        modules[left as usize..end as usize].iter().collect()
+    }
+
+    #[test]
+    fn test_patterns() {
+        for pattern in CODE128_PATTERNS {
+            assert_eq!(digit_widths(pattern).sum::<u32>(), 11, "{}", pattern);
+        }
+        for pattern in CODE39_PATTERNS {
+            assert_eq!(pattern.matches('w').count(), 3, "{}", pattern);
+        }
+        for pattern in ITF_PATTERNS {
+            assert_eq!(pattern.matches('w').count(), 2, "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn test_code128_code_sets() {
+        // Code set B with the check symbol (104 + 48 + 2*42 + 3*42 + ...) % 103
+        assert_eq!(code128_values("PJJ123C").unwrap(), [104, 48, 42, 42, 17, 18, 19, 35, 55]);
+        // Digits only use code set C
+        assert_eq!(code128_values("1234").unwrap(), [105, 12, 34, 82]);
+        // A trailing run of digits switches to code set C
+        let values = code128_values("AB12345").unwrap();
+        assert_eq!(&values[..6], [104, 33, 34, 17, 99, 23]);
+        // Control characters use code set A
+        assert_eq!(&code128_values("A\tb").unwrap()[..5], [103, 33, 73, 100, 66]);
+        assert!(code128_values("Grüße").is_err());
+    }
+
+    #[test]
+    fn test_code128_modules() {
+        let barcode = Barcode::encode(BarcodeKind::Code128, "1234", None).unwrap();
+        // Start, two symbols and the check symbol of 11 modules, the stop of 13
+        assert_eq!(barcode.width, 10 + 4 * 11 + 13 + 10);
+        assert_eq!(barcode.bars[0], (10, 2));
+        assert_eq!(barcode.text, "1234");
+    }
+
+    #[test]
+    fn test_code39() {
+        let barcode = Barcode::encode(BarcodeKind::Code39, "A1", None).unwrap();
+        // Four characters of 15 modules with three gaps
+        assert_eq!(barcode.width, 10 + 4 * 15 + 3 + 10);
+        // The * start character (narrow, wide, narrow, narrow, wide, ...), a gap and A
+        assert!(module_string(&barcode, 10).starts_with("10001011101110101"));
+        assert!(Barcode::encode(BarcodeKind::Code39, "abc", None).is_err());
+        assert!(Barcode::encode(BarcodeKind::Code39, "A*B", None).is_err());
+    }
+
+    #[test]
+    fn test_gtin_check_digits() {
+        assert_eq!(gtin_check_digit("400638133393"), 1);
+        assert_eq!(gtin_check_digit("03600029145"), 2);
+        assert_eq!(gtin_check_digit("1540014128876"), 3);
+        assert_eq!(gtin_digits("EAN-13", "400638133393", 13).unwrap(), "4006381333931");
+        assert_eq!(gtin_digits("EAN-13", "4006381333931", 13).unwrap(), "4006381333931");
+        let error = gtin_digits("EAN-13", "4006381333932", 13).unwrap_err().to_string();
+        assert!(error.contains("expected 1"), "{}", error);
+        assert!(gtin_digits("EAN-13", "40063813", 13).is_err());
+        assert!(gtin_digits("EAN-13", "40063813339A", 13).is_err());
+    }
+
+    #[test]
+    fn test_ean13() {
+        let barcode = Barcode::encode(BarcodeKind::Ean13, "400638133393", None).unwrap();
+        assert_eq!(barcode.width, 11 + 95 + 7);
+        assert_eq!(barcode.text, "4006381333931");
+        let modules = module_string(&barcode, 11);
+        // Start guard, then 0 with odd parity and 0 with even parity (first digit 4)
+        assert!(modules.starts_with("101"));
+        assert_eq!(&modules[3..17], "00011010100111");
+        // Centre guard and the first right-hand digit (3)
+        assert_eq!(&modules[45..57], "010101000010");
+    }
+
+    #[test]
+    fn test_upca() {
+        let barcode = Barcode::encode(BarcodeKind::UpcA, "03600029145", None).unwrap();
+        assert_eq!(barcode.text, "036000291452");
+        assert_eq!(barcode.width, 9 + 95 + 9);
+        // The implicit leading zero gives every left-hand digit odd parity
+        let modules = module_string(&barcode, 9);
+        assert_eq!(&modules[3..10], EAN_L_PATTERNS[0]);
+    }
+
+    #[test]
+    fn test_itf14() {
+        let barcode = Barcode::encode(BarcodeKind::Itf14, "1540014128876", None).unwrap();
+        assert_eq!(barcode.text, "15400141288763");
+        // Start and stop, and seven pairs of 2 * (3 narrow + 2 wide) elements
+        assert_eq!(barcode.width, 10 + 4 + 7 * 2 * (3 + 2 * WIDE) + (WIDE + 2) + 10);
+        let modules = module_string(&barcode, 10);
+        assert!(modules.starts_with("1010"));
+        assert!(modules.ends_with("11101"));
+    }
+
+    #[test]
+    fn test_quiet_zone_override() {
+        let barcode = Barcode::encode(BarcodeKind::Ean13, "4006381333931", Some(2)).unwrap();
+        assert_eq!(barcode.width, 2 + 95 + 2);
+        assert_eq!(barcode.bars[0], (2, 1));
+        assert!(Barcode::encode(BarcodeKind::Code128, "", None).is_err());
+    }
+}
@@ -0,0 +1,308 @@
+//! Printer calibration.
+//!
+//! This module handles:
+//! - Resolving printer offsets and scale correction into a transformation
+//! - Applying the calibration to overlay content
+//! - The ruler and grid test page used to measure a printer
+
+use anyhow::{anyhow, Result};
+use lopdf::{Dictionary, Document, Object};
+use crate::config::{CalibrationProfile, SettingsSection};
+use super::content::escape_pdf_string;
+use super::fonts::{create_font, StandardFont};
+use super::page::PageGeometry;
+
+/// Points per millimetre
+const MM: f64 = 72.0 / 25.4;
+
+/// Smallest and largest accepted scale correction
+const SCALE_RANGE: std::ops::RangeInclusive<f64> = 0.5..=2.0;
+
+/// Distance of the test page crosshairs from the page edges
+const CROSSHAIR_INSET: f64 = 20.0 * MM;
+
+/// Printer calibration in points, applied in display space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    /// Shift to the right
+    pub offset_x: f64,
+    /// Shift downwards
+    pub offset_y: f64,
+    pub scale_x: f64,
+    pub scale_y: f64,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self { offset_x: 0.0, offset_y: 0.0, scale_x: 1.0, scale_y: 1.0 }
+    }
+}
+
+impl Calibration {
+    /// Resolve and validate a calibration profile from settings.json
+    pub fn from_profile(profile: &CalibrationProfile) -> Result<Self> {
+        let calibration = Self {
+            offset_x: profile.offset_x.map(|v| v.as_points()).unwrap_or(0.0),
+            offset_y: profile.offset_y.map(|v| v.as_points()).unwrap_or(0.0),
+            scale_x: profile.scale_x.unwrap_or(1.0),
+            scale_y: profile.scale_y.unwrap_or(1.0),
+        };
+        for scale in [calibration.scale_x, calibration.scale_y] {
+            if !SCALE_RANGE.contains(&scale) {
+                return Err(anyhow!(
+                    "Calibration scale {} is out of range ({} to {})",
+                    scale, SCALE_RANGE.start(), SCALE_RANGE.end()
+                ));
+            }
+        }
+        Ok(calibration)
+    }
+
+    /// Whether the calibration leaves content unchanged
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Matrix applying the calibration in the display space of a page
+    /// `page_height` tall
+    ///
+    /// Scaling is about the top-left corner of the page, where field
+    /// coordinates start; the offsets are added after scaling.
+    pub fn matrix(&self, page_height: f64) -> [f64; 6] {
+        [
+            self.scale_x,
+            0.0,
+            0.0,
+            self.scale_y,
+            self.offset_x,
+            page_height * (1.0 - self.scale_y) - self.offset_y,
+        ]
+    }
+
+    /// Wrap display-space content so it is drawn with the calibration applied
+    pub fn apply(&self, content: Vec<u8>, page_height: f64) -> Vec<u8> {
+        if self.is_identity() || content.is_empty() {
+            return content;
+        }
+        let matrix: Vec<String> = self.matrix(page_height).iter().map(|v| format!("{}", v)).collect();
+        let mut calibrated = format!("q {} cm ", matrix.join(" ")).into_bytes();
+        calibrated.extend(content);
+        calibrated.extend_from_slice(b"Q ");
+        calibrated
+    }
+}
+
+/// Ruler ticks along the top and left edges, every millimetre
+fn ruler_operators(width: f64, height: f64) -> String {
+    let mut ops = String::new();
+    let tick = |mm: usize| match mm {
+        mm if mm % 10 == 0 => 5.0 * MM,
+        mm if mm % 5 == 0 => 3.0 * MM,
+        _ => 1.5 * MM,
+    };
+    for mm in 1..=(width / MM) as usize {
+        let x = mm as f64 * MM;
+        ops.push_str(&format!("{} {} m {} {} l ", x, height, x, height - tick(mm)));
+    }
+    for mm in 1..=(height / MM) as usize {
+        let y = height - mm as f64 * MM;
+        ops.push_str(&format!("0 {} m {} {} l ", y, tick(mm), y));
+    }
+    ops
+}
+
+/// Grid lines every 10 mm, measured from the top-left corner
+fn grid_operators(width: f64, height: f64) -> String {
+    let mut ops = String::new();
+    for cm in 1..=(width / (10.0 * MM)) as usize {
+        let x = cm as f64 * 10.0 * MM;
+        ops.push_str(&format!("{} 0 m {} {} l ", x, x, height));
+    }
+    for cm in 1..=(height / (10.0 * MM)) as usize {
+        let y = height - cm as f64 * 10.0 * MM;
+        ops.push_str(&format!("0 {} m {} {} l ", y, width, y));
+    }
+    ops
+}
+
+/// Crosshair with a circle centred on a point
+fn crosshair_operators(x: f64, y: f64) -> String {
+    let arm = 4.0 * MM;
+    let r = 2.0 * MM;
+    // A circle approximated by four Bézier curves
+    let k = r * 0.552_284_75;
+    format!(
+        "{} {} m {} {} l {} {} m {} {} l \
+         {} {} m {} {} {} {} {} {} c {} {} {} {} {} {} c {} {} {} {} {} {} c {} {} {} {} {} {} c ",
+        x - arm, y, x + arm, y, x, y - arm, x, y + arm,
+        x + r, y,
+        x + r, y + k, x + k, y + r, x, y + r,
+        x - k, y + r, x - r, y + k, x - r, y,
+        x - r, y - k, x - k, y - r, x, y - r,
+        x + k, y - r, x + r, y - k, x + r, y,
+    )
+}
+
+/// Lines of text `size` points high, starting at `(x, y)` and going down
+fn text_operators(font: &str, size: f64, x: f64, y: f64, lines: &[String]) -> String {
+    let mut ops = format!("BT /{} {} Tf {} TL {} {} Td ", font, size, size * 1.4, x, y);
+    for line in lines {
+        ops.push_str(&format!("({}) Tj T* ", escape_pdf_string(line)));
+    }
+    ops.push_str("ET ");
+    ops
+}
+
+/// Size of the calibration page: the first page of base.pdf, else
+/// `settings.page_size`, else A4
+fn calibration_page_size(base_doc: Option<&Document>, settings: &SettingsSection) -> Result<(f64, f64)> {
+    if let Some(base_doc) = base_doc {
+        let page_id = *base_doc.get_pages().get(&1)
+            .ok_or_else(|| anyhow!("No pages in base.pdf"))?;
+        let geometry = PageGeometry::from_page(base_doc, page_id)?;
+        return Ok((geometry.width(), geometry.height()));
+    }
+    Ok(match &settings.page_size {
+        Some(page_size) => page_size.oriented(settings.orientation),
+        None => (210.0 * MM, 297.0 * MM),
+    })
+}
+
+/// Create a test page for measuring a printer's offset and scale
+///
+/// The page has millimetre rulers along the top and left edges, a 10 mm grid
+/// and crosshairs 20 mm in from each corner, drawn with the calibration in
+/// `settings` applied, so printing it shows the remaining error.
+pub fn create_calibration_pdf(base_doc: Option<&Document>, settings: &SettingsSection) -> Result<Document> {
+    let calibration = Calibration::from_profile(&settings.calibration)?;
+    let (width, height) = calibration_page_size(base_doc, settings)?;
+
+    let mut doc = Document::with_version("1.5");
+    let (font_id, font_name) = create_font(&mut doc, StandardFont::Helvetica)?;
+
+    let mut content = format!("q 0.2 w 0.75 G {}S Q ", grid_operators(width, height));
+    content.push_str(&format!("q 0.4 w 0 G {}S Q ", ruler_operators(width, height)));
+    let inset = CROSSHAIR_INSET;
+    let crosshairs: String = [(inset, height - inset), (width - inset, height - inset), (inset, inset), (width - inset, inset)]
+        .iter()
+        .map(|&(x, y)| crosshair_operators(x, y))
+        .collect();
+    content.push_str(&format!("q 0.4 w 0 G {}S Q ", crosshairs));
+
+    let lines = [
+        "Printer calibration page".to_string(),
+        "Print at actual size (100%, no fit to page), then measure from the paper edges.".to_string(),
+        format!(
+            "Current calibration: offset_x {:.2} mm, offset_y {:.2} mm, scale_x {}, scale_y {}",
+            calibration.offset_x / MM, calibration.offset_y / MM, calibration.scale_x, calibration.scale_y
+        ),
+        "The crosshair centres should be 20 mm from the nearest left/right and top/bottom edges.".to_string(),
+        "offset_x += 20 mm - measured distance from the left edge to the top-left crosshair".to_string(),
+        "offset_y += 20 mm - measured distance from the top edge to the top-left crosshair".to_string(),
+        format!(
+            "scale_x *= {:.1} mm / measured distance between the top crosshairs",
+            (width - 2.0 * inset) / MM
+        ),
+        format!(
+            "scale_y *= {:.1} mm / measured distance between the left crosshairs",
+            (height - 2.0 * inset) / MM
+        ),
+    ];
+    content.push_str(&text_operators(&font_name, 8.0, 30.0 * MM, height / 2.0 + 20.0 * MM, &lines));
+    let content = calibration.apply(content.into_bytes(), height);
+
+    let pages_id = doc.new_object_id();
+    let content_id = doc.add_object(lopdf::Stream::new(Dictionary::new(), content));
+    let mut fonts = Dictionary::new();
+    fonts.set(font_name.clone(), Object::Reference(font_id));
+    let mut resources = Dictionary::new();
+    resources.set("Font", fonts);
+
+    let media_box: Vec<Object> = [0.0, 0.0, width, height].iter().map(|v| Object::Real(*v as f32)).collect();
+    let mut page = Dictionary::new();
+    page.set("Type", "Page");
+    page.set("Parent", pages_id);
+    page.set("MediaBox", media_box);
+    page.set("Resources", resources);
+    page.set("Contents", content_id);
+    let page_id = doc.add_object(page);
+
+    let mut pages = Dictionary::new();
+    pages.set("Type", "Pages");
+    pages.set("Kids", vec![Object::Reference(page_id)]);
+    pages.set("Count", 1);
+    doc.objects.insert(pages_id, Object::Dictionary(pages));
+
+    let mut catalog = Dictionary::new();
+    catalog.set("Type", "Catalog");
+    catalog.set("Pages", pages_id);
+    let catalog_id = doc.add_object(catalog);
+    doc.trailer.set("Root", catalog_id);
+    Ok(doc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Dimension;
+
+    /// Apply a matrix to a point
+    fn transform(m: [f64; 6], x: f64, y: f64) -> (f64, f64) {
+        (m[0] * x + m[2] * y + m[4], m[1] * x + m[3] * y + m[5])
+    }
+
+    #[test]
+    fn test_matrix_scales_about_top_left() {
+        let calibration = Calibration { offset_x: 5.0, offset_y: 10.0, scale_x: 1.1, scale_y: 0.9 };
+        let matrix = calibration.matrix(800.0);
+        // The top-left corner only moves by the offsets (10 pt down)
+        assert_eq!(transform(matrix, 0.0, 800.0), (5.0, 790.0));
+        // A point 100 pt right of and 200 pt below the corner is scaled
+        let (x, y) = transform(matrix, 100.0, 600.0);
+        assert!((x - 115.0).abs() < 1e-9);
+        assert!((y - (800.0 - 180.0 - 10.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_apply() {
+        let identity = Calibration::default();
+        assert!(identity.is_identity());
+        assert_eq!(identity.apply(b"x ".to_vec(), 800.0), b"x ");
+
+        let shifted = Calibration { offset_x: 2.0, ..Calibration::default() };
+        assert_eq!(shifted.apply(b"x ".to_vec(), 800.0), b"q 1 0 0 1 2 0 cm x Q ");
+        assert!(shifted.apply(Vec::new(), 800.0).is_empty());
+    }
+
+    #[test]
+    fn test_from_profile() {
+        let profile = CalibrationProfile {
+            offset_y: Some(Dimension(-3.0)),
+            scale_x: Some(1.01),
+            ..Default::default()
+        };
+        let calibration = Calibration::from_profile(&profile).unwrap();
+        assert_eq!(calibration, Calibration { offset_x: 0.0, offset_y: -3.0, scale_x: 1.01, scale_y: 1.0 });
+
+        let profile = CalibrationProfile { scale_y: Some(0.0), ..Default::default() };
+        assert!(Calibration::from_profile(&profile).is_err());
+    }
+
+    #[test]
+    fn test_calibration_pdf() {
+        let settings = SettingsSection {
+            calibration: CalibrationProfile { offset_x: Some(Dimension(1.0)), ..Default::default() },
+            ..Default::default()
+        };
+        let doc = create_calibration_pdf(None, &settings).unwrap();
+
+        let pages = doc.get_pages();
+        assert_eq!(pages.len(), 1);
+        let page_id = *pages.get(&1).unwrap();
+        let geometry = PageGeometry::from_page(&doc, page_id).unwrap();
+        assert!((geometry.width() - 595.276).abs() < 0.01);
+        let content = String::from_utf8(doc.get_page_content(page_id).unwrap()).unwrap();
+        assert!(content.starts_with("q 1 0 0 1 1 0 cm "));
+        assert!(content.contains("(Printer calibration page) Tj"));
+    }
+}
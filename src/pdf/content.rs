@@ -1,133 +1,524 @@
-//! PDF content stream generation for QR codes and text.
+//! PDF content stream generation for QR codes, barcodes and text.
 //!
 //! This module provides:
-//! - QR code generation and embedding
-//! - Text rendering with standard and CID fonts
+//! - QR, Micro QR, rMQR, Data Matrix and Aztec symbol generation and embedding
+//! - 1D barcodes drawn as vector bars with a human-readable line
+//! - Text rendering with standard and embedded fonts
 //! - PDF content stream building
-//! - String encoding for PDF (ASCII and UTF-16BE)
+//! - Crop and registration marks
+//! - String encoding for PDF (escaped literals and glyph IDs)
 
 use anyhow::{anyhow, Context, Result};
-use crate::config::FieldSpec;
+use crate::config::{Align, Anchor, ColorSpec, Dimension, Direction, EcLevel, FieldSpec, FitMode, RenderMode, TextMode, VAlign};
+use super::aztec;
+use super::barcode::{Barcode, BarcodeKind};
+use super::datamatrix;
+use super::fonts::PdfFont;
+use super::layout::{layout_text, TextBox};
+use super::marks::PrintMarks;
+use super::pdf417;
+use super::rmqr;
 use image::{ImageBuffer, Luma};
-use lopdf::{Dictionary, Document, Object, Stream};
-use qrcode::QrCode;
+use lopdf::{dictionary, Dictionary, Document, Object, Stream};
+use qrcode::types::QrError;
+use qrcode::{Color, QrCode, Version};
+use std::collections::{HashMap, HashSet};
 use std::io::Write;
+use std::rc::Rc;
 
-/// QR code size constant
-const QR_SIZE: u32 = 200;
+/// Smallest size of the longer side of raster symbol images, in pixels
+const MIN_RASTER_SIZE: usize = 200;
+
+/// Default quiet zone around QR codes, in modules (as required by ISO/IEC 18004)
+const DEFAULT_QUIET_ZONE: u32 = 4;
+
+/// Default quiet zone around Micro QR and rMQR codes, in modules (as required
+/// by ISO/IEC 18004 and ISO/IEC 23941)
+const DEFAULT_MICRO_QUIET_ZONE: u32 = 2;
+
+/// Default quiet zone around Data Matrix symbols, in modules
+const DEFAULT_DATAMATRIX_QUIET_ZONE: u32 = 1;
+
+/// Default quiet zone around PDF417 symbols, in modules (as required by
+/// ISO/IEC 15438)
+const DEFAULT_PDF417_QUIET_ZONE: u32 = 2;
+
+/// Largest QR code version
+const MAX_QR_VERSION: i16 = 40;
+
+/// Largest Micro QR code version (M4)
+const MAX_MICRO_QR_VERSION: i16 = 4;
+
+/// Default size of the human-readable line under barcodes, as a fraction of
+/// the field height
+const BARCODE_TEXT_SIZE: f64 = 0.15;
+
+/// Height of the human-readable line relative to its font size
+const BARCODE_TEXT_LEADING: f64 = 1.2;
+
+/// Distance from the top of an em square to the baseline, as a fraction of
+/// the font size (the PDF default vertical origin for CID fonts)
+const VERTICAL_BASELINE: f64 = 0.88;
+
+/// Cache key for a generated symbol XObject: the encoded value plus every
+/// parameter that affects how it is drawn
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct XObjectKey {
+    output_type: String,
+    value: String,
+    ec_level: Option<EcLevel>,
+    version: Option<i16>,
+    min_version: Option<i16>,
+    quiet_zone: Option<u32>,
+    size: Option<String>,
+    ec_percent: Option<u32>,
+    columns: Option<u32>,
+    rows: Option<u32>,
+    security_level: Option<u8>,
+    gs1: bool,
+    render: RenderMode,
+}
+
+impl XObjectKey {
+    /// Build the key for a field value
+    pub fn new(value: &str, spec: &FieldSpec) -> Self {
+        Self {
+            output_type: spec.output_type.clone(),
+            value: value.to_string(),
+            ec_level: spec.ec_level,
+            version: spec.version,
+            min_version: spec.min_version,
+            quiet_zone: spec.quiet_zone,
+            size: spec.size.clone(),
+            ec_percent: spec.ec_percent,
+            columns: spec.columns,
+            rows: spec.rows,
+            security_level: spec.security_level,
+            gs1: spec.gs1.unwrap_or(false),
+            render: spec.render.unwrap_or_default(),
+        }
+    }
+}
+
+/// XObjects already added to the output document, shared across pages
+#[derive(Debug, Default)]
+pub struct XObjectCache {
+    entries: HashMap<XObjectKey, (u32, u16)>,
+    /// Width and height in modules of the 2D symbol each XObject draws
+    grid_sizes: HashMap<(u32, u16), (usize, usize)>,
+}
+
+impl XObjectCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Look up the object ID of a previously generated XObject
+    pub fn get(&self, key: &XObjectKey) -> Option<(u32, u16)> {
+        self.entries.get(key).copied()
+    }
+
+    /// Remember the object ID of a generated XObject
+    pub fn insert(&mut self, key: XObjectKey, id: (u32, u16)) {
+        self.entries.insert(key, id);
+    }
+
+    /// Width and height in modules of the 2D symbol drawn by an XObject
+    pub fn grid_size(&self, id: (u32, u16)) -> Option<(usize, usize)> {
+        self.grid_sizes.get(&id).copied()
+    }
+
+    /// Remember the module grid size of a 2D symbol XObject
+    pub fn set_grid_size(&mut self, id: (u32, u16), size: (usize, usize)) {
+        self.grid_sizes.insert(id, size);
+    }
+}
+
+/// Fonts available to text fields
+#[derive(Debug, Clone)]
+pub struct TextFonts {
+    /// Font for fields without a `font` setting
+    pub default: PdfFont,
+    /// Fonts for fields with a `font` setting, keyed by that name
+    pub named: HashMap<String, PdfFont>,
+    /// CJK-capable font for text the selected font cannot draw
+    pub fallback: Option<PdfFont>,
+    /// Top-to-bottom variants of embedded fonts used by vertical fields,
+    /// keyed by the object ID of the horizontal font
+    pub vertical: HashMap<(u32, u16), PdfFont>,
+}
+
+impl TextFonts {
+    /// Font configured for a field, ignoring whether it can draw the text
+    pub fn configured(&self, spec: &FieldSpec) -> &PdfFont {
+        spec.font
+            .as_ref()
+            .and_then(|name| self.named.get(name))
+            .unwrap_or(&self.default)
+    }
+
+    /// Font to draw `text` with: the configured font, or the fallback
+    /// font when the configured one lacks some of the characters
+    pub fn select(&self, spec: &FieldSpec, text: &str) -> &PdfFont {
+        let font = self.configured(spec);
+        if font.can_render(text) {
+            return font;
+        }
+        self.fallback.as_ref().unwrap_or(font)
+    }
+
+    /// Font to draw `text` with in the field's writing direction
+    ///
+    /// Vertical fields use the `Identity-V` variant of the selected font when
+    /// it has one.
+    pub fn select_for_direction(&self, spec: &FieldSpec, text: &str) -> &PdfFont {
+        let font = self.select(spec, text);
+        if spec.direction == Some(Direction::Vertical) {
+            return self.vertical.get(&font.id).unwrap_or(font);
+        }
+        font
+    }
+}
 
 /// Builder for generating PDF content streams and associated XObjects
 pub struct ContentBuilder {
     pub content_parts: Vec<String>,
     pub xobjects: Dictionary,
-    font_name: String,
-    cid_font_name: Option<String>,
+    pub ext_gstates: Dictionary,
+    pub color_spaces: Dictionary,
+    /// Resource name of the Separation colour space of each colorant
+    separations: HashMap<String, String>,
+    fonts: Rc<TextFonts>,
+    /// Resource names the base page already uses
+    reserved_names: Rc<HashSet<String>>,
 }
 
 impl ContentBuilder {
-    /// Create a new ContentBuilder with the given font name
-    pub fn new(font_name: String) -> Self {
+    /// Create a new ContentBuilder drawing text with the given fonts
+    pub fn new(fonts: Rc<TextFonts>) -> Self {
         Self {
             content_parts: Vec::new(),
             xobjects: Dictionary::new(),
-            font_name,
-            cid_font_name: None,
+            ext_gstates: Dictionary::new(),
+            color_spaces: Dictionary::new(),
+            separations: HashMap::new(),
+            fonts,
+            reserved_names: Rc::default(),
         }
     }
 
-    /// Create a new ContentBuilder with CID font support
-    pub fn new_with_cid_font(font_name: String, cid_font_name: String) -> Self {
-        Self {
-            content_parts: Vec::new(),
-            xobjects: Dictionary::new(),
-            font_name,
-            cid_font_name: Some(cid_font_name),
+    /// Keep generated XObject and graphics state names clear of `names`,
+    /// the resources of the page the content is drawn on
+    pub fn reserve_names(&mut self, names: Rc<HashSet<String>>) {
+        self.reserved_names = names;
+    }
+
+    /// Resource name `base`, with a numeric suffix if the page already uses it
+    ///
+    /// The same `base` always gives the same name, so resources shared by
+    /// several fields are added once.
+    pub fn resource_name(&self, base: String) -> String {
+        let mut name = base.clone();
+        let mut suffix = 2;
+        while self.reserved_names.contains(&name) {
+            name = format!("{}-{}", base, suffix);
+            suffix += 1;
         }
+        name
     }
 
-    /// Add a QR code field to the content
-    pub fn add_qr_code(
+    /// Add a QR code, Micro QR, rMQR, Data Matrix, Aztec or PDF417 field to
+    /// the content
+    ///
+    /// The symbol is scaled to fit the field box with square modules and
+    /// centred in it. It is drawn through an XObject taken from `cache`, so
+    /// identical symbols on different pages share a single object.
+    pub fn add_matrix_code(
         &mut self,
         value: &str,
         spec: &FieldSpec,
         page_height: f64,
         doc: &mut Document,
+        cache: &mut XObjectCache,
     ) -> Result<()> {
-        // Generate QR code image
-        let qr_img = generate_qr_code(value, QR_SIZE, QR_SIZE)?;
+        let key = XObjectKey::new(value, spec);
+        let xobject_id = match cache.get(&key) {
+            Some(id) => id,
+            None => {
+                let grid = symbol_module_grid(value, spec)?;
+                let id = match key.render {
+                    RenderMode::Vector => create_vector_xobject(&grid, doc),
+                    RenderMode::Raster => create_raster_xobject(&grid, doc)?,
+                };
+                cache.insert(key.clone(), id);
+                cache.set_grid_size(id, (grid.width, grid.height));
+                id
+            }
+        };
+        let (grid_width, grid_height) = cache.grid_size(xobject_id).unwrap_or((1, 1));
 
-        // Convert grayscale image to raw bytes (8-bit per pixel)
-        let raw_bytes: Vec<u8> = qr_img.pixels().map(|pixel| pixel[0]).collect();
+        let xobject_name = self.resource_name(match key.render {
+            RenderMode::Vector => format!("Fm{}", xobject_id.0),
+            RenderMode::Raster => format!("Im{}", xobject_id.0),
+        });
+        self.xobjects.set(xobject_name.clone(), Object::Reference(xobject_id));
+
+        // Calculate PDF coordinates (flip Y axis)
+        let x = spec.x.as_points();
+        let y = page_height - spec.y.as_points() - spec.h.as_points();
+        let w = spec.w.as_points();
+        let h = spec.h.as_points();
 
-        // Compress the image data
-        let compressed_bytes = compress_data(&raw_bytes)?;
+        // Keep the modules square: scale the symbol to the largest size that
+        // fits the box and centre it there
+        let module_size = (w / grid_width as f64).min(h / grid_height as f64);
+        let symbol_w = module_size * grid_width as f64;
+        let symbol_h = module_size * grid_height as f64;
+        let x = x + (w - symbol_w) / 2.0;
+        let y = y + (h - symbol_h) / 2.0;
 
-        // Create image XObject
-        let mut img_dict = Dictionary::new();
-        img_dict.set("Type", "XObject");
-        img_dict.set("Subtype", "Image");
-        img_dict.set("Width", QR_SIZE as i64);
-        img_dict.set("Height", QR_SIZE as i64);
-        img_dict.set("ColorSpace", "DeviceGray");
-        img_dict.set("BitsPerComponent", 8_i64);
-        img_dict.set("Filter", "FlateDecode");
+        // Both XObject kinds occupy the unit square, so one matrix places them
+        self.content_parts.push(format!(
+            "q {} 0 0 {} {} {} cm /{} Do Q ",
+            symbol_w, symbol_h, x, y, xobject_name
+        ));
 
-        let img_stream = Stream::new(img_dict, compressed_bytes);
-        let img_id = doc.add_object(img_stream);
+        Ok(())
+    }
 
-        let img_name = format!("Im{}", img_id.0);
-        self.xobjects.set(img_name.clone(), Object::Reference(img_id));
+    /// Add a 1D barcode field to the content
+    ///
+    /// The bars fill the width of the field box and its height above the
+    /// human-readable line. Like QR codes, they are drawn through an XObject
+    /// shared by identical barcodes.
+    pub fn add_barcode(
+        &mut self,
+        kind: BarcodeKind,
+        value: &str,
+        spec: &FieldSpec,
+        page_height: f64,
+        doc: &mut Document,
+        cache: &mut XObjectCache,
+    ) -> Result<()> {
+        let barcode = Barcode::encode(kind, value, spec.quiet_zone)?;
+        let key = XObjectKey::new(value, spec);
+        let xobject_id = match cache.get(&key) {
+            Some(id) => id,
+            None => {
+                let id = create_bars_xobject(&barcode, doc);
+                cache.insert(key, id);
+                id
+            }
+        };
+        let xobject_name = self.resource_name(format!("Fm{}", xobject_id.0));
+        self.xobjects.set(xobject_name.clone(), Object::Reference(xobject_id));
 
-        // Calculate PDF coordinates (flip Y axis)
-        let x = spec.x.as_points();
-        let y = page_height - spec.y.as_points() - spec.h.as_points();
         let w = spec.w.as_points();
         let h = spec.h.as_points();
+        let font_size = spec.font_size.map_or(h * BARCODE_TEXT_SIZE, |d| d.as_points());
+        let human_readable = spec.human_readable.unwrap_or(true);
+        let text_height = if human_readable { font_size * BARCODE_TEXT_LEADING } else { 0.0 };
+        let bar_height = h - text_height;
+        if bar_height <= 0.0 {
+            return Err(anyhow!(
+                "Barcode field is {} pt high, too small for a {} pt human-readable line",
+                h, font_size
+            ));
+        }
 
-        // Add content stream commands for drawing the image
+        // Bars at the top of the box (flip Y axis)
+        let x = spec.x.as_points();
+        let y = page_height - spec.y.as_points() - bar_height;
         self.content_parts.push(format!(
             "q {} 0 0 {} {} {} cm /{} Do Q ",
-            w, h, x, y, img_name
+            w, bar_height, x, y, xobject_name
         ));
 
+        // The text line is centred under the bars, shrunk to the box width if needed
+        if human_readable {
+            let text_spec = FieldSpec {
+                output_type: "Text".to_string(),
+                y: Dimension(spec.y.as_points() + bar_height),
+                h: Dimension(text_height),
+                font_size: Some(Dimension(font_size)),
+                align: Some(Align::Center),
+                valign: Some(VAlign::Bottom),
+                wrap: None,
+                fit: Some(FitMode::Shrink),
+                direction: None,
+                rotate: None,
+                ..spec.clone()
+            };
+            self.add_text(&barcode.text, &text_spec, page_height);
+        }
         Ok(())
     }
 
     /// Add a text field to the content
+    ///
+    /// The text is laid out inside the field box (wrapping, alignment and
+    /// shrinking as configured) and each line gets its own `Tj`.
     pub fn add_text(&mut self, value: &str, spec: &FieldSpec, page_height: f64) {
-        let x = spec.x.as_points();
-        let y = page_height - spec.y.as_points();
+        let bbox = TextBox {
+            x: spec.x.as_points(),
+            top: page_height - spec.y.as_points(),
+            width: spec.w.as_points(),
+            height: spec.h.as_points(),
+        };
         let font_size = spec.font_size
             .map(|d| d.as_points())
-            .unwrap_or_else(|| spec.h.as_points().min(spec.w.as_points() * 0.5));
-
-        // Check if the text requires CID font (non-ASCII)
-        let needs_cid = value.chars().any(|c| c > '\u{7F}');
-
-        if needs_cid {
-            if let Some(cid_font_name) = &self.cid_font_name {
-                // Use CID font with hex encoding for non-ASCII text
-                let hex_value = encode_cid_text(value);
-                self.content_parts.push(format!(
-                    "q BT 0 g /{} {} Tf {} {} Td <{}> Tj ET Q ",
-                    cid_font_name, font_size, x, y - font_size, hex_value
-                ));
+            .unwrap_or_else(|| bbox.height.min(bbox.width * 0.5));
+
+        let fonts = Rc::clone(&self.fonts);
+        let font = fonts.select_for_direction(spec, value);
+        let vertical = spec.direction == Some(Direction::Vertical);
+        let char_spacing = spec.char_spacing.map_or(0.0, |d| d.as_points());
+        let word_spacing = spec.word_spacing.map_or(0.0, |d| d.as_points());
+        let scale = spec.horizontal_scale.unwrap_or(100.0) / 100.0;
+
+        // Advance along the line, including spacing (horizontal scaling only
+        // applies to horizontal writing)
+        let measure = |text: &str, size: f64| {
+            let spacing = char_spacing * text.chars().count() as f64
+                + word_spacing * text.matches(' ').count() as f64;
+            if vertical {
+                font.text_height(text) / 1000.0 * size + spacing
             } else {
-                // Fallback to regular font (may not display correctly)
-                let escaped_value = escape_pdf_string(value);
-                self.content_parts.push(format!(
-                    "q BT 0 g /{} {} Tf {} {} Td ({}) Tj ET Q ",
-                    self.font_name, font_size, x, y - font_size, escaped_value
-                ));
+                (font.text_width(text) / 1000.0 * size + spacing) * scale
             }
-        } else {
-            // Use regular font with escaped text for ASCII-only text
-            let escaped_value = escape_pdf_string(value);
-            self.content_parts.push(format!(
-                "q BT 0 g /{} {} Tf {} {} Td ({}) Tj ET Q ",
-                self.font_name, font_size, x, y - font_size, escaped_value
+        };
+        let layout = layout_text(value, &bbox, font_size, spec, &measure);
+        let size = layout.font_size;
+
+        // Runs of text drawn with one Tj each, positioned at their start point
+        let mut runs = Vec::new();
+        for line in layout.lines {
+            if vertical && !font.is_vertical() {
+                // Fonts without a vertical mode draw each character upright,
+                // centred in the column, one em (plus spacing) below the previous one
+                let mut offset = 0.0;
+                for c in line.text.chars() {
+                    let glyph = c.to_string();
+                    let x = line.x - font.text_width(&glyph) / 1000.0 * size * scale / 2.0;
+                    let y = line.y - offset - size * VERTICAL_BASELINE;
+                    offset += measure(&glyph, size);
+                    runs.push((x, y, glyph));
+                }
+            } else {
+                runs.push((line.x, line.y, line.text));
+            }
+        }
+
+        let mut ops = String::from("q ");
+        if let Some(opacity) = spec.opacity {
+            ops.push_str(&format!("/{} gs ", self.add_opacity_state(opacity)));
+        }
+        ops.push_str("BT ");
+        let fill_color = spec.color.clone().unwrap_or(ColorSpec::Gray(0.0));
+        ops.push_str(&self.color_operator(&fill_color, false));
+        let text_mode = spec.text_mode.unwrap_or_default();
+        if text_mode != TextMode::Fill {
+            let stroke_color = spec.stroke_color.clone().unwrap_or(fill_color);
+            let render_mode = if text_mode == TextMode::Stroke { 1 } else { 2 };
+            ops.push_str(&format!(
+                "{}{} w {} Tr ",
+                self.color_operator(&stroke_color, true),
+                spec.stroke_width.map_or(1.0, |d| d.as_points()),
+                render_mode
             ));
         }
+        ops.push_str(&format!("/{} {} Tf ", font.resource_name, size));
+        if char_spacing != 0.0 {
+            ops.push_str(&format!("{} Tc ", char_spacing));
+        }
+        // Tw only applies to the single-byte space of simple fonts; composite
+        // fonts get explicit adjustments in a TJ array instead
+        if word_spacing != 0.0 && !font.is_composite() {
+            ops.push_str(&format!("{} Tw ", word_spacing));
+        }
+        if scale != 1.0 {
+            ops.push_str(&format!("{} Tz ", scale * 100.0));
+        }
+
+        // Td moves relative to the start of the previous run
+        let (mut run_x, mut run_y) = (0.0, 0.0);
+        for (x, y, text) in runs {
+            ops.push_str(&format!("{} {} Td ", x - run_x, y - run_y));
+            if word_spacing != 0.0 && font.is_composite() && text.contains(' ') {
+                // TJ adjustments are in thousandths of an em, against the writing direction
+                let adjustment = word_spacing * 1000.0 / size;
+                let adjustment = if font.is_vertical() { adjustment } else { -adjustment };
+                let parts: Vec<String> = text
+                    .split_inclusive(' ')
+                    .map(|part| font.encode_text(part))
+                    .collect();
+                ops.push_str(&format!("[{}] TJ ", parts.join(&format!(" {} ", adjustment))));
+            } else {
+                ops.push_str(&format!("{} Tj ", font.encode_text(&text)));
+            }
+            (run_x, run_y) = (x, y);
+        }
+        ops.push_str("ET Q ");
+        self.content_parts.push(ops);
+    }
+
+    /// Colour operators setting the fill (or, with `stroke`, the stroke) colour
+    ///
+    /// Spot colours register a Separation colour space for their colorant.
+    fn color_operator(&mut self, color: &ColorSpec, stroke: bool) -> String {
+        let (components, operator) = match color {
+            ColorSpec::Gray(v) => (vec![*v], "g"),
+            ColorSpec::Rgb(r, g, b) => (vec![*r, *g, *b], "rg"),
+            ColorSpec::Cmyk(c, m, y, k) => (vec![*c, *m, *y, *k], "k"),
+            ColorSpec::Separation { name, tint, alternate } => {
+                let space = self.add_separation(name, alternate);
+                let (set_space, operator) = if stroke { ("CS", "SCN") } else { ("cs", "scn") };
+                return format!("/{} {} {} {} ", space, set_space, color_component(*tint), operator);
+            }
+        };
+        let operator = if stroke { operator.to_uppercase() } else { operator.to_string() };
+        let components: Vec<String> = components.into_iter().map(color_component).collect();
+        format!("{} {} ", components.join(" "), operator)
+    }
+
+    /// Register a Separation colour space for a colorant, returning its resource name
+    ///
+    /// Tints map linearly from white to the CMYK `alternate` on devices
+    /// without the colorant.
+    fn add_separation(&mut self, name: &str, alternate: &[f64; 4]) -> String {
+        if let Some(space) = self.separations.get(name) {
+            return space.clone();
+        }
+        let space = self.resource_name(format!("CS{}", self.separations.len() + 1));
+        let tint_transform = dictionary! {
+            "FunctionType" => 2,
+            "Domain" => vec![0.into(), 1.into()],
+            "C0" => vec![0.into(), 0.into(), 0.into(), 0.into()],
+            "C1" => alternate.iter().map(|&v| Object::Real(v as f32)).collect::<Vec<_>>(),
+            "N" => 1,
+        };
+        self.color_spaces.set(space.clone(), vec![
+            Object::Name(b"Separation".to_vec()),
+            Object::Name(name.as_bytes().to_vec()),
+            Object::Name(b"DeviceCMYK".to_vec()),
+            Object::Dictionary(tint_transform),
+        ]);
+        self.separations.insert(name.to_string(), space.clone());
+        space
+    }
+
+    /// Register an ExtGState setting fill and stroke opacity, returning its resource name
+    fn add_opacity_state(&mut self, opacity: f64) -> String {
+        let opacity = opacity.clamp(0.0, 1.0);
+        let name = self.resource_name(format!("GS{}", (opacity * 1000.0).round() as i64));
+        if !self.ext_gstates.has(name.as_bytes()) {
+            let mut state = Dictionary::new();
+            state.set("Type", "ExtGState");
+            state.set("ca", opacity as f32);
+            state.set("CA", opacity as f32);
+            self.ext_gstates.set(name.clone(), Object::Dictionary(state));
+        }
+        name
     }
 
     /// Add a field based on its type
@@ -138,31 +529,111 @@ impl ContentBuilder {
         spec: &FieldSpec,
         page_height: f64,
         doc: &mut Document,
+        cache: &mut XObjectCache,
     ) -> Result<()> {
+        let first_part = self.content_parts.len();
         match spec.output_type.as_str() {
-            "QR" => {
-                self.add_qr_code(value, spec, page_height, doc)?;
+            "QR" | "MicroQR" | "rMQR" | "DataMatrix" | "Aztec" | "PDF417" => {
+                self.add_matrix_code(value, spec, page_height, doc, cache)?;
             }
             "Text" => {
                 self.add_text(value, spec, page_height);
             }
-            _ => {
-                return Err(anyhow!("Unknown output type: {}", spec.output_type));
-            }
+            other => match BarcodeKind::from_type(other) {
+                Some(kind) => self.add_barcode(kind, value, spec, page_height, doc, cache)?,
+                None => return Err(anyhow!("Unknown output type: {}", spec.output_type)),
+            },
+        }
+
+        // Rotate everything the field drew about its anchor
+        if let Some(matrix) = rotation_matrix(spec, page_height) {
+            let drawn = self.content_parts.split_off(first_part).join("");
+            let matrix: Vec<String> = matrix.iter().map(|v| format!("{}", v)).collect();
+            self.content_parts.push(format!("q {} cm {}Q ", matrix.join(" "), drawn));
         }
         Ok(())
     }
 
+    /// Add crop marks and registration targets around a trimmed area of
+    /// `width` by `height` in display space
+    ///
+    /// `cuts_x` and `cuts_y` are the cut positions from the bottom-left corner
+    /// of the trimmed area (see `PrintMarks::crop_lines`). Marks use
+    /// registration black, 100% of every process ink.
+    pub fn add_print_marks(&mut self, marks: &PrintMarks, width: f64, height: f64, cuts_x: &[f64], cuts_y: &[f64]) {
+        if !marks.draws_marks() {
+            return;
+        }
+        let mut ops = format!("q {} w 1 1 1 1 K ", marks.line_width);
+        for [x0, y0, x1, y1] in marks.crop_lines(width, height, cuts_x, cuts_y) {
+            ops.push_str(&format!("{} {} m {} {} l ", x0, y0, x1, y1));
+        }
+        let radius = marks.length * REGISTRATION_RADIUS;
+        let half = marks.length / 2.0;
+        for (cx, cy) in marks.registration_centres(width, height) {
+            ops.push_str(&circle_path(cx, cy, radius));
+            ops.push_str(&format!(
+                "{} {} m {} {} l {} {} m {} {} l ",
+                cx - half, cy, cx + half, cy, cx, cy - half, cx, cy + half
+            ));
+        }
+        ops.push_str("S Q ");
+        self.content_parts.push(ops);
+    }
+
     /// Build the final content bytes
     pub fn build_content_bytes(&self) -> Vec<u8> {
         self.content_parts.join("").as_bytes().to_vec()
     }
 }
 
-impl Default for ContentBuilder {
-    fn default() -> Self {
-        Self::new("F1".to_string())
-    }
+/// Radius of a registration target's circle relative to the mark length
+const REGISTRATION_RADIUS: f64 = 0.3;
+
+/// Bézier control point distance for a quarter circle of radius 1
+const CIRCLE_KAPPA: f64 = 0.552_284_75;
+
+/// Closed path of a circle made of four Bézier curves
+fn circle_path(cx: f64, cy: f64, r: f64) -> String {
+    let k = r * CIRCLE_KAPPA;
+    format!(
+        "{} {} m {} {} {} {} {} {} c {} {} {} {} {} {} c {} {} {} {} {} {} c {} {} {} {} {} {} c h ",
+        cx + r, cy,
+        cx + r, cy + k, cx + k, cy + r, cx, cy + r,
+        cx - k, cy + r, cx - r, cy + k, cx - r, cy,
+        cx - r, cy - k, cx - k, cy - r, cx, cy - r,
+        cx + k, cy - r, cx + r, cy - k, cx + r, cy,
+    )
+}
+
+/// Transformation matrix rotating a field about its anchor, in PDF coordinates
+///
+/// Returns `None` for fields without rotation. Values are rounded so that
+/// right angles give exact matrices.
+pub fn rotation_matrix(spec: &FieldSpec, page_height: f64) -> Option<[f64; 6]> {
+    let degrees = spec.rotate.filter(|d| d % 360.0 != 0.0)?;
+    let left = spec.x.as_points();
+    let right = left + spec.w.as_points();
+    let top = page_height - spec.y.as_points();
+    let bottom = top - spec.h.as_points();
+    let (ax, ay) = match spec.anchor.unwrap_or_default() {
+        Anchor::Center => ((left + right) / 2.0, (top + bottom) / 2.0),
+        Anchor::TopLeft => (left, top),
+        Anchor::TopRight => (right, top),
+        Anchor::BottomLeft => (left, bottom),
+        Anchor::BottomRight => (right, bottom),
+    };
+
+    // Translate the anchor to the origin, rotate, and translate back
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let matrix = [cos, sin, -sin, cos, ax - cos * ax + sin * ay, ay - sin * ax - cos * ay];
+    // Adding 0.0 turns -0 into 0
+    Some(matrix.map(|v| (v * 1e6).round() / 1e6 + 0.0))
+}
+
+/// Colour component rounded for the content stream
+fn color_component(value: f64) -> String {
+    format!("{}", (value * 1000.0).round() / 1000.0)
 }
 
 /// Escape special characters in PDF strings
@@ -182,40 +653,6 @@ pub fn escape_pdf_string(s: &str) -> String {
     result
 }
 
-/// Encode text for CID font (Identity-H encoding)
-///
-/// Converts text to UTF-16BE and returns hex representation
-pub fn encode_cid_text(s: &str) -> String {
-    let mut utf16be: Vec<u8> = Vec::new();
-
-    // Add BOM (Byte Order Mark) for UTF-16BE if needed
-    // Actually for Identity-H, we don't need BOM, just the raw UTF-16BE bytes
-
-    for c in s.chars() {
-        let code = c as u32;
-        if code <= 0xFFFF {
-            // BMP character - directly encode as UTF-16BE
-            utf16be.push((code >> 8) as u8);
-            utf16be.push((code & 0xFF) as u8);
-        } else {
-            // Supplementary character - needs surrogate pair
-            let code = code - 0x10000;
-            let high_surrogate = 0xD800 + ((code >> 10) & 0x3FF);
-            let low_surrogate = 0xDC00 + (code & 0x3FF);
-            utf16be.push((high_surrogate >> 8) as u8);
-            utf16be.push((high_surrogate & 0xFF) as u8);
-            utf16be.push((low_surrogate >> 8) as u8);
-            utf16be.push((low_surrogate & 0xFF) as u8);
-        }
-    }
-
-    // Convert to hex string
-    utf16be.iter()
-        .map(|b| format!("{:02X}", b))
-        .collect::<Vec<_>>()
-        .join("")
-}
-
 /// Compress data using zlib/flate2
 pub fn compress_data(data: &[u8]) -> Result<Vec<u8>> {
     let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
@@ -223,31 +660,342 @@ pub fn compress_data(data: &[u8]) -> Result<Vec<u8>> {
     Ok(encoder.finish()?)
 }
 
-/// Generate a QR code as an image buffer
-pub fn generate_qr_code(data: &str, width: u32, height: u32) -> Result<ImageBuffer<Luma<u8>, Vec<u8>>> {
-    let qr_code = QrCode::new(data)
-        .with_context(|| format!("Failed to generate QR code for data: {}", data))?;
-
-    // Render QR code with light=255 (white) and dark=0 (black)
-    let img = qr_code
-        .render::<Luma<u8>>()
-        .light_color(Luma([255u8]))
-        .dark_color(Luma([0u8]))
-        .build();
-
-    // Scale the image to the requested size
-    let scaled = image::imageops::resize(
-        &img,
-        width,
-        height,
-        image::imageops::FilterType::Nearest,
+/// Convert the configured error correction level to the qrcode crate's type
+fn to_qr_ec_level(level: EcLevel) -> qrcode::EcLevel {
+    match level {
+        EcLevel::L => qrcode::EcLevel::L,
+        EcLevel::M => qrcode::EcLevel::M,
+        EcLevel::Q => qrcode::EcLevel::Q,
+        EcLevel::H => qrcode::EcLevel::H,
+    }
+}
+
+/// Check that a QR version number is within 1..=40
+fn check_qr_version(version: i16) -> Result<()> {
+    if !(1..=MAX_QR_VERSION).contains(&version) {
+        return Err(anyhow!(
+            "Invalid QR version {}: must be between 1 and {}",
+            version, MAX_QR_VERSION
+        ));
+    }
+    Ok(())
+}
+
+/// Build a QR code using the error correction level and version from the field spec
+///
+/// - `version` forces an exact version and fails if the data does not fit
+/// - `min_version` picks the smallest version at or above the given one
+/// - otherwise the smallest version that fits is chosen automatically
+pub fn build_qr_code(data: &str, spec: &FieldSpec) -> Result<QrCode> {
+    let ec_level = to_qr_ec_level(spec.ec_level.unwrap_or(EcLevel::M));
+
+    match (spec.version, spec.min_version) {
+        (Some(_), Some(_)) => Err(anyhow!(
+            "QR field cannot specify both 'version' and 'min_version'"
+        )),
+        (Some(version), None) => {
+            check_qr_version(version)?;
+            QrCode::with_version(data, Version::Normal(version), ec_level).map_err(|e| match e {
+                QrError::DataTooLong => anyhow!(
+                    "Data for QR code does not fit in version {} with error correction level {:?} ({} bytes): {}",
+                    version, ec_level, data.len(), data
+                ),
+                e => anyhow!("Failed to generate QR code for data: {}: {}", data, e),
+            })
+        }
+        (None, Some(min_version)) => {
+            check_qr_version(min_version)?;
+            for version in min_version..=MAX_QR_VERSION {
+                match QrCode::with_version(data, Version::Normal(version), ec_level) {
+                    Ok(code) => return Ok(code),
+                    Err(QrError::DataTooLong) => continue,
+                    Err(e) => {
+                        return Err(anyhow!("Failed to generate QR code for data: {}: {}", data, e));
+                    }
+                }
+            }
+            Err(anyhow!(
+                "Data for QR code does not fit in versions {} to {} with error correction level {:?} ({} bytes): {}",
+                min_version, MAX_QR_VERSION, ec_level, data.len(), data
+            ))
+        }
+        (None, None) => QrCode::with_error_correction_level(data, ec_level)
+            .with_context(|| format!("Failed to generate QR code for data: {}", data)),
+    }
+}
+
+/// Build a Micro QR code using the error correction level and version from the field spec
+///
+/// The error correction level defaults to L, the only level of version M1.
+/// - `version` (1-4 for M1-M4) forces an exact version and fails if the data does not fit
+/// - `min_version` picks the smallest version at or above the given one
+/// - otherwise the smallest version that fits is chosen automatically
+pub fn build_micro_qr_code(data: &str, spec: &FieldSpec) -> Result<QrCode> {
+    let ec_level = spec.ec_level.unwrap_or(EcLevel::L);
+    if ec_level == EcLevel::H {
+        return Err(anyhow!("Micro QR codes support error correction levels L, M and Q, not H"));
+    }
+    let (first, last) = match (spec.version, spec.min_version) {
+        (Some(_), Some(_)) => {
+            return Err(anyhow!("Micro QR field cannot specify both 'version' and 'min_version'"));
+        }
+        (Some(version), None) => (version, version),
+        (None, Some(min_version)) => (min_version, MAX_MICRO_QR_VERSION),
+        (None, None) => (1, MAX_MICRO_QR_VERSION),
+    };
+    if !(1..=MAX_MICRO_QR_VERSION).contains(&first) {
+        return Err(anyhow!(
+            "Invalid Micro QR version {}: must be between 1 and {}",
+            first, MAX_MICRO_QR_VERSION
+        ));
+    }
+
+    for version in first..=last {
+        // Smaller versions lack some error correction levels and encoding modes
+        match QrCode::with_version(data, Version::Micro(version), to_qr_ec_level(ec_level)) {
+            Ok(code) => return Ok(code),
+            Err(QrError::DataTooLong | QrError::InvalidVersion | QrError::UnsupportedCharacterSet) => continue,
+            Err(e) => {
+                return Err(anyhow!("Failed to generate Micro QR code for data: {}: {}", data, e));
+            }
+        }
+    }
+    Err(anyhow!(
+        "Data for Micro QR code does not fit in versions M{} to M{} with error correction level {:?} ({} bytes): {}",
+        first, last, ec_level, data.len(), data
+    ))
+}
+
+/// Dark/light module matrix of a 2D symbol, including its quiet zone
+///
+/// Row 0 is the top row of the symbol.
+pub struct ModuleGrid {
+    pub width: usize,
+    pub height: usize,
+    modules: Vec<bool>,
+}
+
+impl ModuleGrid {
+    /// Create a grid from row-major dark flags, surrounded by `quiet_zone` light modules
+    pub fn with_quiet_zone(width: usize, height: usize, dark: &[bool], quiet_zone: usize) -> Self {
+        let full_width = width + 2 * quiet_zone;
+        let full_height = height + 2 * quiet_zone;
+        let mut modules = vec![false; full_width * full_height];
+        for y in 0..height {
+            for x in 0..width {
+                modules[(y + quiet_zone) * full_width + x + quiet_zone] = dark[y * width + x];
+            }
+        }
+        Self {
+            width: full_width,
+            height: full_height,
+            modules,
+        }
+    }
+
+    /// Whether the module at (x, y) is dark
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.width + x]
+    }
+
+    /// Build path operators drawing the symbol in module units
+    ///
+    /// The light background is filled first, then each horizontal run of dark
+    /// modules becomes one rectangle. The symbol occupies (0, 0)-(width, height)
+    /// with the origin at the bottom-left, so callers scale it with a `cm` matrix.
+    pub fn to_path_operators(&self) -> String {
+        let mut ops = format!("1 g 0 0 {} {} re f 0 g ", self.width, self.height);
+        for y in 0..self.height {
+            let pdf_y = self.height - y - 1;
+            let mut x = 0;
+            while x < self.width {
+                if !self.is_dark(x, y) {
+                    x += 1;
+                    continue;
+                }
+                let start = x;
+                while x < self.width && self.is_dark(x, y) {
+                    x += 1;
+                }
+                ops.push_str(&format!("{} {} {} 1 re ", start, pdf_y, x - start));
+            }
+        }
+        ops.push_str("f ");
+        ops
+    }
+
+    /// Render the grid as a grayscale image with `module_pixels` square
+    /// pixels per module, light=255 (white) and dark=0 (black)
+    pub fn to_image(&self, module_pixels: usize) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+        let (width, height) = (self.width * module_pixels, self.height * module_pixels);
+        ImageBuffer::from_fn(width as u32, height as u32, |x, y| {
+            if self.is_dark(x as usize / module_pixels, y as usize / module_pixels) {
+                Luma([0u8])
+            } else {
+                Luma([255u8])
+            }
+        })
+    }
+}
+
+/// Add a Form XObject drawing the grid as vector paths in the unit square
+fn create_vector_xobject(grid: &ModuleGrid, doc: &mut Document) -> (u32, u16) {
+    let mut form_dict = Dictionary::new();
+    form_dict.set("Type", "XObject");
+    form_dict.set("Subtype", "Form");
+    form_dict.set("BBox", vec![0.into(), 0.into(), 1.into(), 1.into()]);
+
+    // Scale module units down to the unit square
+    let content = format!(
+        "{} 0 0 {} 0 0 cm {}",
+        1.0 / grid.width as f64,
+        1.0 / grid.height as f64,
+        grid.to_path_operators()
     );
-    Ok(scaled)
+
+    doc.add_object(Stream::new(form_dict, content.into_bytes()))
+}
+
+/// Add a Form XObject drawing the bars of a barcode in the unit square
+fn create_bars_xobject(barcode: &Barcode, doc: &mut Document) -> (u32, u16) {
+    let mut form_dict = Dictionary::new();
+    form_dict.set("Type", "XObject");
+    form_dict.set("Subtype", "Form");
+    form_dict.set("BBox", vec![0.into(), 0.into(), 1.into(), 1.into()]);
+
+    // Scale modules down to the unit square; the quiet zones stay light
+    let mut content = format!("{} 0 0 1 0 0 cm 1 g 0 0 {} 1 re f 0 g ", 1.0 / barcode.width as f64, barcode.width);
+    for (start, width) in &barcode.bars {
+        content.push_str(&format!("{} 0 {} 1 re ", start, width));
+    }
+    content.push_str("f ");
+
+    doc.add_object(Stream::new(form_dict, content.into_bytes()))
+}
+
+/// Add an Image XObject with the grid rasterized to a bitmap
+///
+/// Every module is the same whole number of pixels, enough for the longer
+/// side to be at least MIN_RASTER_SIZE pixels.
+fn create_raster_xobject(grid: &ModuleGrid, doc: &mut Document) -> Result<(u32, u16)> {
+    let module_pixels = MIN_RASTER_SIZE.div_ceil(grid.width.max(grid.height)).max(1);
+    let qr_img = grid.to_image(module_pixels);
+
+    // Convert grayscale image to raw bytes (8-bit per pixel)
+    let raw_bytes: Vec<u8> = qr_img.pixels().map(|pixel| pixel[0]).collect();
+
+    // Compress the image data
+    let compressed_bytes = compress_data(&raw_bytes)?;
+
+    // Create image XObject
+    let mut img_dict = Dictionary::new();
+    img_dict.set("Type", "XObject");
+    img_dict.set("Subtype", "Image");
+    img_dict.set("Width", qr_img.width() as i64);
+    img_dict.set("Height", qr_img.height() as i64);
+    img_dict.set("ColorSpace", "DeviceGray");
+    img_dict.set("BitsPerComponent", 8_i64);
+    img_dict.set("Filter", "FlateDecode");
+
+    let img_stream = Stream::new(img_dict, compressed_bytes);
+    Ok(doc.add_object(img_stream))
+}
+
+/// Text a field draws with its font: the value of text fields, or the
+/// human-readable line of 1D barcodes
+pub fn field_text(spec: &FieldSpec, value: &str) -> Option<String> {
+    if spec.output_type == "Text" {
+        return Some(value.to_string());
+    }
+    let kind = BarcodeKind::from_type(&spec.output_type)?;
+    if !spec.human_readable.unwrap_or(true) {
+        return None;
+    }
+    Barcode::encode(kind, value, spec.quiet_zone).ok().map(|barcode| barcode.text)
+}
+
+/// Build the module grid for a QR, Micro QR, rMQR, Data Matrix or Aztec
+/// field, including its quiet zone
+pub fn symbol_module_grid(data: &str, spec: &FieldSpec) -> Result<ModuleGrid> {
+    let gs1 = spec.gs1.unwrap_or(false);
+    let (width, height, dark, default_quiet_zone) = match spec.output_type.as_str() {
+        kind @ ("QR" | "MicroQR" | "rMQR" | "PDF417") if gs1 => {
+            return Err(anyhow!("GS1 mode is not supported for {} codes; use DataMatrix or Aztec", kind));
+        }
+        "QR" => return qr_module_grid(data, spec),
+        "MicroQR" => {
+            let code = build_micro_qr_code(data, spec)?;
+            let quiet_zone = spec.quiet_zone.unwrap_or(DEFAULT_MICRO_QUIET_ZONE) as usize;
+            return Ok(code_module_grid(&code, quiet_zone));
+        }
+        "rMQR" => {
+            let (width, height, dark) = rmqr::encode(data, spec.size.as_deref(), spec.ec_level.unwrap_or(EcLevel::M))
+                .with_context(|| format!("Failed to generate rMQR code for data: {}", data))?;
+            (width, height, dark, DEFAULT_MICRO_QUIET_ZONE)
+        }
+        "DataMatrix" => {
+            let (width, height, dark) = datamatrix::encode(data, spec.size.as_deref(), gs1)
+                .with_context(|| format!("Failed to generate Data Matrix for data: {}", data))?;
+            (width, height, dark, DEFAULT_DATAMATRIX_QUIET_ZONE)
+        }
+        "Aztec" => {
+            // The bullseye makes a quiet zone unnecessary
+            let (width, height, dark) = aztec::encode(data, spec.size.as_deref(), spec.ec_percent, gs1)
+                .with_context(|| format!("Failed to generate Aztec code for data: {}", data))?;
+            (width, height, dark, 0)
+        }
+        "PDF417" => {
+            let columns = spec.columns.map(|c| c as usize);
+            let rows = spec.rows.map(|r| r as usize);
+            let (width, height, dark) = pdf417::encode(data, columns, rows, spec.security_level)
+                .with_context(|| format!("Failed to generate PDF417 symbol for data: {}", data))?;
+            (width, height, dark, DEFAULT_PDF417_QUIET_ZONE)
+        }
+        other => return Err(anyhow!("{} is not a 2D symbol type", other)),
+    };
+    let quiet_zone = spec.quiet_zone.unwrap_or(default_quiet_zone) as usize;
+    Ok(ModuleGrid::with_quiet_zone(width, height, &dark, quiet_zone))
+}
+
+/// Build the module grid for a QR code field, including its quiet zone
+pub fn qr_module_grid(data: &str, spec: &FieldSpec) -> Result<ModuleGrid> {
+    let qr_code = build_qr_code(data, spec)?;
+    let quiet_zone = spec.quiet_zone.unwrap_or(DEFAULT_QUIET_ZONE) as usize;
+    Ok(code_module_grid(&qr_code, quiet_zone))
+}
+
+/// Module grid of a QR or Micro QR code surrounded by `quiet_zone` modules
+fn code_module_grid(code: &QrCode, quiet_zone: usize) -> ModuleGrid {
+    let dark: Vec<bool> = code
+        .to_colors()
+        .into_iter()
+        .map(|color| color == Color::Dark)
+        .collect();
+    ModuleGrid::with_quiet_zone(code.width(), code.width(), &dark, quiet_zone)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{Align, WrapMode};
+    use crate::pdf::fonts::{PdfFontKind, StandardFont};
+
+    fn standard_font(name: &str, id: u32) -> PdfFont {
+        PdfFont {
+            id: (id, 0),
+            resource_name: name.to_string(),
+            kind: PdfFontKind::Standard(StandardFont::from_name(name).unwrap_or(StandardFont::Helvetica)),
+        }
+    }
+
+    fn test_fonts() -> Rc<TextFonts> {
+        Rc::new(TextFonts {
+            default: standard_font("F1", 1),
+            named: HashMap::new(),
+            fallback: None,
+            vertical: HashMap::new(),
+        })
+    }
 
     #[test]
     fn test_escape_pdf_string() {
@@ -259,21 +1007,21 @@ mod tests {
 
     #[test]
     fn test_content_builder_new() {
-        let builder = ContentBuilder::new("F1".to_string());
+        let builder = ContentBuilder::new(test_fonts());
         assert!(builder.content_parts.is_empty());
         assert!(builder.xobjects.is_empty());
     }
 
     #[test]
     fn test_content_builder_add_text() {
-        let mut builder = ContentBuilder::new("F1".to_string());
+        let mut builder = ContentBuilder::new(test_fonts());
         let spec = FieldSpec {
             x: crate::config::Dimension(100.0),
             y: crate::config::Dimension(200.0),
             w: crate::config::Dimension(50.0),
             h: crate::config::Dimension(12.0),
             output_type: "Text".to_string(),
-            font_size: None,
+            ..Default::default()
         };
 
         builder.add_text("Hello", &spec, 800.0);
@@ -282,4 +1030,585 @@ mod tests {
         assert!(builder.content_parts[0].contains("Hello"));
         assert!(builder.xobjects.is_empty());
     }
+
+    #[test]
+    fn test_content_builder_add_text_wrapped() {
+        let mut builder = ContentBuilder::new(test_fonts());
+        let spec = FieldSpec {
+            x: crate::config::Dimension(100.0),
+            y: crate::config::Dimension(200.0),
+            w: crate::config::Dimension(40.0),
+            h: crate::config::Dimension(30.0),
+            output_type: "Text".to_string(),
+            font_size: Some(crate::config::Dimension(10.0)),
+            align: Some(Align::Right),
+            wrap: Some(WrapMode::Word),
+            ..Default::default()
+        };
+
+        // "Hello" is 22.78pt wide in 10pt Helvetica, so each word gets a line
+        builder.add_text("Hello Hello", &spec, 800.0);
+
+        let ops = &builder.content_parts[0];
+        assert_eq!(ops.matches(" Tj ").count(), 2);
+        assert!(ops.starts_with("q BT 0 g /F1 10 Tf 117.22 590 Td (Hello) Tj 0 -12 Td (Hello) Tj"));
+    }
+
+    #[test]
+    fn test_content_builder_add_text_vertical_standard_font() {
+        let mut builder = ContentBuilder::new(test_fonts());
+        let spec = FieldSpec {
+            x: crate::config::Dimension(100.0),
+            y: crate::config::Dimension(200.0),
+            w: crate::config::Dimension(20.0),
+            h: crate::config::Dimension(40.0),
+            output_type: "Text".to_string(),
+            font_size: Some(crate::config::Dimension(10.0)),
+            direction: Some(Direction::Vertical),
+            ..Default::default()
+        };
+
+        // Standard fonts stack upright characters centred in the column
+        builder.add_text("II", &spec, 800.0);
+
+        let ops = &builder.content_parts[0];
+        assert!(ops.starts_with("q BT 0 g /F1 10 Tf 113.61 591.2 Td (I) Tj 0 -10 Td (I) Tj"));
+    }
+
+    #[test]
+    fn test_resource_names_avoid_reserved_names() {
+        let mut builder = ContentBuilder::new(test_fonts());
+        builder.reserve_names(Rc::new(HashSet::from(["GS500".to_string(), "GS500-2".to_string()])));
+        let spec = FieldSpec {
+            w: crate::config::Dimension(100.0),
+            h: crate::config::Dimension(20.0),
+            output_type: "Text".to_string(),
+            opacity: Some(0.5),
+            ..Default::default()
+        };
+
+        builder.add_text("A", &spec, 800.0);
+        builder.add_text("B", &spec, 800.0);
+
+        assert!(builder.content_parts.iter().all(|ops| ops.starts_with("q /GS500-3 gs ")));
+        assert_eq!(builder.ext_gstates.len(), 1);
+        assert_eq!(builder.resource_name("Fm7".to_string()), "Fm7");
+    }
+
+    #[test]
+    fn test_content_builder_add_text_styled() {
+        let mut builder = ContentBuilder::new(test_fonts());
+        let spec = FieldSpec {
+            w: crate::config::Dimension(100.0),
+            h: crate::config::Dimension(20.0),
+            output_type: "Text".to_string(),
+            font_size: Some(crate::config::Dimension(10.0)),
+            color: Some(ColorSpec::Rgb(1.0, 1.0, 1.0)),
+            opacity: Some(0.5),
+            text_mode: Some(TextMode::FillStroke),
+            stroke_color: Some(ColorSpec::Cmyk(0.0, 0.0, 0.0, 1.0)),
+            stroke_width: Some(crate::config::Dimension(0.25)),
+            char_spacing: Some(crate::config::Dimension(1.0)),
+            word_spacing: Some(crate::config::Dimension(2.0)),
+            horizontal_scale: Some(80.0),
+            ..Default::default()
+        };
+
+        builder.add_text("A B", &spec, 800.0);
+
+        assert!(builder.content_parts[0].starts_with(
+            "q /GS500 gs BT 1 1 1 rg 0 0 0 1 K 0.25 w 2 Tr /F1 10 Tf 1 Tc 2 Tw 80 Tz 0 790 Td (A B) Tj"
+        ));
+        let state = builder.ext_gstates.get(b"GS500").unwrap().as_dict().unwrap();
+        assert_eq!(state.get(b"ca").unwrap().as_float().unwrap(), 0.5);
+    }
+
+    #[test]
+    fn test_content_builder_word_spacing_composite_font() {
+        let fonts = Rc::new(TextFonts {
+            default: PdfFont {
+                id: (1, 0),
+                resource_name: "F1".to_string(),
+                kind: PdfFontKind::Embedded(Rc::new(crate::pdf::fonts::FontProgram::new(Vec::new()))),
+            },
+            named: HashMap::new(),
+            fallback: None,
+            vertical: HashMap::new(),
+        });
+        let mut builder = ContentBuilder::new(fonts);
+        let spec = FieldSpec {
+            w: crate::config::Dimension(100.0),
+            h: crate::config::Dimension(20.0),
+            output_type: "Text".to_string(),
+            font_size: Some(crate::config::Dimension(10.0)),
+            word_spacing: Some(crate::config::Dimension(2.0)),
+            ..Default::default()
+        };
+
+        // Composite fonts ignore Tw, so spaces get TJ adjustments
+        builder.add_text("A B", &spec, 800.0);
+
+        assert!(!builder.content_parts[0].contains("Tw"));
+        assert!(builder.content_parts[0].contains("[<00000000> -200 <0000>] TJ"));
+    }
+
+    #[test]
+    fn test_rotation_matrix() {
+        let mut spec = FieldSpec {
+            x: crate::config::Dimension(100.0),
+            y: crate::config::Dimension(100.0),
+            w: crate::config::Dimension(40.0),
+            h: crate::config::Dimension(20.0),
+            output_type: "Text".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(rotation_matrix(&spec, 800.0), None);
+
+        // 90 degrees about the centre (120, 690)
+        spec.rotate = Some(90.0);
+        assert_eq!(rotation_matrix(&spec, 800.0), Some([0.0, 1.0, -1.0, 0.0, 810.0, 570.0]));
+
+        // 180 degrees about the top-left corner (100, 700)
+        spec.rotate = Some(180.0);
+        spec.anchor = Some(Anchor::TopLeft);
+        assert_eq!(rotation_matrix(&spec, 800.0), Some([-1.0, 0.0, 0.0, -1.0, 200.0, 1400.0]));
+
+        spec.rotate = Some(360.0);
+        assert_eq!(rotation_matrix(&spec, 800.0), None);
+    }
+
+    #[test]
+    fn test_add_field_rotated() {
+        let mut doc = Document::with_version("1.5");
+        let mut cache = XObjectCache::new();
+        let mut builder = ContentBuilder::new(test_fonts());
+        let spec = FieldSpec {
+            w: crate::config::Dimension(40.0),
+            h: crate::config::Dimension(20.0),
+            output_type: "Text".to_string(),
+            rotate: Some(90.0),
+            anchor: Some(Anchor::TopLeft),
+            ..Default::default()
+        };
+
+        builder.add_field("ID", "A1", &spec, 800.0, &mut doc, &mut cache).unwrap();
+
+        assert_eq!(builder.content_parts.len(), 1);
+        let ops = &builder.content_parts[0];
+        assert!(ops.starts_with("q 0 1 -1 0 800 800 cm q BT "));
+        assert!(ops.ends_with("ET Q Q "));
+    }
+
+    #[test]
+    fn test_color_operator() {
+        let mut builder = ContentBuilder::new(test_fonts());
+        assert_eq!(builder.color_operator(&ColorSpec::Gray(0.5), false), "0.5 g ");
+        assert_eq!(builder.color_operator(&ColorSpec::Rgb(1.0, 0.0, 0.2), true), "1 0 0.2 RG ");
+        assert_eq!(builder.color_operator(&ColorSpec::Cmyk(0.0, 1.0, 1.0, 0.0), false), "0 1 1 0 k ");
+        assert!(builder.color_spaces.is_empty());
+    }
+
+    #[test]
+    fn test_separation_color() {
+        let mut builder = ContentBuilder::new(test_fonts());
+        builder.reserve_names(Rc::new(HashSet::from(["CS1".to_string()])));
+        let gold = ColorSpec::Separation { name: "Gold".to_string(), tint: 0.5, alternate: [0.0, 0.2, 0.6, 0.1] };
+        let full_gold = ColorSpec::Separation { name: "Gold".to_string(), tint: 1.0, alternate: [0.0, 0.2, 0.6, 0.1] };
+
+        assert_eq!(builder.color_operator(&gold, false), "/CS1-2 cs 0.5 scn ");
+        assert_eq!(builder.color_operator(&full_gold, true), "/CS1-2 CS 1 SCN ");
+
+        assert_eq!(builder.color_spaces.len(), 1);
+        let space = builder.color_spaces.get(b"CS1-2").unwrap().as_array().unwrap();
+        assert_eq!(space[0].as_name().unwrap(), b"Separation");
+        assert_eq!(space[1].as_name().unwrap(), b"Gold");
+        assert_eq!(space[2].as_name().unwrap(), b"DeviceCMYK");
+        let tint_transform = space[3].as_dict().unwrap();
+        assert_eq!(tint_transform.get(b"C1").unwrap().as_array().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_add_print_marks() {
+        let marks = PrintMarks {
+            bleed: 0.0,
+            crop: true,
+            registration: true,
+            length: 10.0,
+            offset: 5.0,
+            line_width: 0.25,
+        };
+        let mut builder = ContentBuilder::new(test_fonts());
+        builder.add_print_marks(&marks, 100.0, 50.0, &[0.0, 100.0], &[0.0, 50.0]);
+
+        let content = builder.content_parts.join("");
+        assert!(content.starts_with("q 0.25 w 1 1 1 1 K "));
+        assert!(content.contains("0 -5 m 0 -15 l "));
+        assert!(content.contains("105 0 m 115 0 l "));
+        // Registration target above the top edge, centred 10 pt beyond the trim
+        assert!(content.contains("53 60 m "));
+        assert!(content.contains("45 60 m 55 60 l 50 55 m 50 65 l "));
+        assert!(content.ends_with("S Q "));
+
+        let mut builder = ContentBuilder::new(test_fonts());
+        builder.add_print_marks(&PrintMarks { crop: false, registration: false, ..marks }, 100.0, 50.0, &[], &[]);
+        assert!(builder.content_parts.is_empty());
+    }
+
+    #[test]
+    fn test_content_builder_add_text_with_field_font() {
+        let mut named = HashMap::new();
+        named.insert("Courier".to_string(), standard_font("Courier", 2));
+        let fonts = Rc::new(TextFonts {
+            default: standard_font("Helvetica", 1),
+            named,
+            fallback: Some(PdfFont {
+                id: (3, 0),
+                resource_name: "CJK".to_string(),
+                kind: PdfFontKind::Embedded(Rc::new(crate::pdf::fonts::FontProgram::from_glyphs(&[('あ', 0x0123)]))),
+            }),
+            vertical: HashMap::new(),
+        });
+        let mut builder = ContentBuilder::new(fonts);
+        let spec = FieldSpec {
+            w: crate::config::Dimension(50.0),
+            h: crate::config::Dimension(12.0),
+            output_type: "Text".to_string(),
+            font: Some("Courier".to_string()),
+            ..Default::default()
+        };
+
+        builder.add_text("ID-1", &spec, 800.0);
+        assert!(builder.content_parts[0].contains("/Courier 12 Tf"));
+        assert!(builder.content_parts[0].contains("(ID-1) Tj"));
+
+        // Characters the standard font cannot draw go to the fallback font
+        builder.add_text("あ", &spec, 800.0);
+        assert!(builder.content_parts[1].contains("/CJK 12 Tf"));
+        // Encoded as its glyph ID rather than its UTF-16 code
+        assert!(builder.content_parts[1].contains("<0123> Tj"));
+    }
+
+    #[test]
+    fn test_build_qr_code_with_version_and_ec_level() {
+        let spec = FieldSpec {
+            output_type: "QR".to_string(),
+            ec_level: Some(EcLevel::H),
+            version: Some(5),
+            ..Default::default()
+        };
+        let code = build_qr_code("https://example.com", &spec).unwrap();
+        assert_eq!(code.version(), Version::Normal(5));
+        assert_eq!(code.error_correction_level(), qrcode::EcLevel::H);
+    }
+
+    #[test]
+    fn test_build_qr_code_min_version() {
+        let spec = FieldSpec {
+            output_type: "QR".to_string(),
+            min_version: Some(3),
+            ..Default::default()
+        };
+        let code = build_qr_code("A", &spec).unwrap();
+        assert_eq!(code.version(), Version::Normal(3));
+    }
+
+    #[test]
+    fn test_build_qr_code_data_too_long_for_version() {
+        let spec = FieldSpec {
+            output_type: "QR".to_string(),
+            ec_level: Some(EcLevel::H),
+            version: Some(1),
+            ..Default::default()
+        };
+        let err = build_qr_code("https://example.com/a/very/long/path", &spec)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("does not fit in version 1"));
+    }
+
+    #[test]
+    fn test_qr_module_grid_quiet_zone() {
+        let spec = FieldSpec {
+            output_type: "QR".to_string(),
+            version: Some(1),
+            quiet_zone: Some(2),
+            ..Default::default()
+        };
+        // Version 1 is 21 modules wide, plus 2 modules of quiet zone per side
+        let img = qr_module_grid("A", &spec).unwrap().to_image(2);
+        assert_eq!(img.dimensions(), (50, 50));
+        assert_eq!(img.get_pixel(3, 3)[0], 255);
+        assert_eq!(img.get_pixel(4, 4)[0], 0);
+    }
+
+    #[test]
+    fn test_module_grid_path_operators_merge_runs() {
+        // Two rows: "##." and ".##"
+        let grid = ModuleGrid::with_quiet_zone(3, 2, &[true, true, false, false, true, true], 0);
+        assert_eq!(
+            grid.to_path_operators(),
+            "1 g 0 0 3 2 re f 0 g 0 1 2 1 re 1 0 2 1 re f "
+        );
+    }
+
+    #[test]
+    fn test_add_qr_code_vector_by_default() {
+        let mut builder = ContentBuilder::new(test_fonts());
+        let mut doc = Document::with_version("1.5");
+        let mut cache = XObjectCache::new();
+        let spec = FieldSpec {
+            w: crate::config::Dimension(58.0),
+            h: crate::config::Dimension(58.0),
+            output_type: "QR".to_string(),
+            version: Some(1),
+            ..Default::default()
+        };
+
+        builder.add_matrix_code("A", &spec, 800.0, &mut doc, &mut cache).unwrap();
+
+        assert_eq!(builder.content_parts[0], "q 58 0 0 58 0 742 cm /Fm1 Do Q ");
+        let form = doc.get_object((1, 0)).unwrap().as_stream().unwrap();
+        assert_eq!(form.dict.get(b"Subtype").unwrap().as_name().unwrap(), b"Form");
+        // 21 modules + 2 * 4 quiet zone = 29 modules
+        let content = String::from_utf8(form.content.clone()).unwrap();
+        assert!(content.contains("1 g 0 0 29 29 re f 0 g "));
+    }
+
+    #[test]
+    fn test_add_matrix_code_keeps_modules_square() {
+        let mut doc = Document::with_version("1.5");
+        let mut cache = XObjectCache::new();
+        let spec = FieldSpec {
+            w: crate::config::Dimension(86.0),
+            h: crate::config::Dimension(86.0),
+            output_type: "rMQR".to_string(),
+            size: Some("R7x43".to_string()),
+            quiet_zone: Some(0),
+            ..Default::default()
+        };
+
+        // A 43x7 grid in a square box: 2pt modules, centred vertically
+        let mut builder = ContentBuilder::new(test_fonts());
+        builder.add_matrix_code("123", &spec, 800.0, &mut doc, &mut cache).unwrap();
+        assert_eq!(builder.content_parts[0], "q 86 0 0 14 0 750 cm /Fm1 Do Q ");
+
+        // The cached XObject is placed the same way
+        let mut builder = ContentBuilder::new(test_fonts());
+        builder.add_matrix_code("123", &spec, 800.0, &mut doc, &mut cache).unwrap();
+        assert_eq!(builder.content_parts[0], "q 86 0 0 14 0 750 cm /Fm1 Do Q ");
+    }
+
+    #[test]
+    fn test_add_qr_code_raster() {
+        let mut builder = ContentBuilder::new(test_fonts());
+        let mut doc = Document::with_version("1.5");
+        let mut cache = XObjectCache::new();
+        let spec = FieldSpec {
+            w: crate::config::Dimension(50.0),
+            h: crate::config::Dimension(50.0),
+            output_type: "QR".to_string(),
+            render: Some(RenderMode::Raster),
+            ..Default::default()
+        };
+
+        builder.add_matrix_code("A", &spec, 800.0, &mut doc, &mut cache).unwrap();
+
+        assert!(builder.xobjects.has(b"Im1"));
+        assert!(builder.content_parts[0].contains("/Im1 Do Q "));
+    }
+
+    #[test]
+    fn test_raster_image_is_a_multiple_of_the_grid() {
+        let mut doc = Document::with_version("1.5");
+        let image_size = |doc: &Document, id| {
+            let dict = &doc.get_object(id).unwrap().as_stream().unwrap().dict;
+            (dict.get(b"Width").unwrap().as_i64().unwrap(), dict.get(b"Height").unwrap().as_i64().unwrap())
+        };
+
+        // 29 modules at 7 pixels each
+        let qr = ModuleGrid::with_quiet_zone(21, 21, &[false; 21 * 21], 4);
+        let id = create_raster_xobject(&qr, &mut doc).unwrap();
+        assert_eq!(image_size(&doc, id), (203, 203));
+
+        // A 47x11 rMQR grid keeps its proportions at 5 pixels per module
+        let rmqr = ModuleGrid::with_quiet_zone(43, 7, &[false; 43 * 7], 2);
+        let id = create_raster_xobject(&rmqr, &mut doc).unwrap();
+        assert_eq!(image_size(&doc, id), (235, 55));
+    }
+
+    #[test]
+    fn test_add_qr_code_reuses_cached_xobject() {
+        let mut doc = Document::with_version("1.5");
+        let mut cache = XObjectCache::new();
+        let spec = FieldSpec {
+            w: crate::config::Dimension(50.0),
+            h: crate::config::Dimension(50.0),
+            output_type: "QR".to_string(),
+            ..Default::default()
+        };
+
+        // Same value on two pages shares one object
+        let mut page1 = ContentBuilder::new(test_fonts());
+        page1.add_matrix_code("https://example.com/support", &spec, 800.0, &mut doc, &mut cache).unwrap();
+        let mut page2 = ContentBuilder::new(test_fonts());
+        page2.add_matrix_code("https://example.com/support", &spec, 800.0, &mut doc, &mut cache).unwrap();
+        assert_eq!(doc.objects.len(), 1);
+        assert_eq!(page1.xobjects, page2.xobjects);
+
+        // Different render parameters produce a separate object
+        let high_ec = FieldSpec {
+            ec_level: Some(EcLevel::H),
+            ..spec.clone()
+        };
+        page2.add_matrix_code("https://example.com/support", &high_ec, 800.0, &mut doc, &mut cache).unwrap();
+        assert_eq!(doc.objects.len(), 2);
+    }
+
+    #[test]
+    fn test_add_datamatrix_and_aztec() {
+        let mut builder = ContentBuilder::new(test_fonts());
+        let mut doc = Document::with_version("1.5");
+        let mut cache = XObjectCache::new();
+        let spec = FieldSpec {
+            w: crate::config::Dimension(40.0),
+            h: crate::config::Dimension(40.0),
+            output_type: "DataMatrix".to_string(),
+            ..Default::default()
+        };
+
+        builder.add_field("Serial", "123456", &spec, 800.0, &mut doc, &mut cache).unwrap();
+        // 10x10 symbol with a one-module quiet zone
+        let form = doc.get_object((1, 0)).unwrap().as_stream().unwrap();
+        let content = String::from_utf8(form.content.clone()).unwrap();
+        assert!(content.contains("1 g 0 0 12 12 re f 0 g "));
+
+        let aztec = FieldSpec { output_type: "Aztec".to_string(), ..spec.clone() };
+        builder.add_field("Serial", "123456", &aztec, 800.0, &mut doc, &mut cache).unwrap();
+        // Compact single-layer symbol without a quiet zone
+        let form = doc.get_object((2, 0)).unwrap().as_stream().unwrap();
+        let content = String::from_utf8(form.content.clone()).unwrap();
+        assert!(content.contains("1 g 0 0 15 15 re f 0 g "));
+        assert_eq!(builder.content_parts.len(), 2);
+    }
+
+    #[test]
+    fn test_symbol_options() {
+        let spec = FieldSpec {
+            output_type: "DataMatrix".to_string(),
+            size: Some("8x32".to_string()),
+            quiet_zone: Some(0),
+            ..Default::default()
+        };
+        let grid = symbol_module_grid("123456", &spec).unwrap();
+        assert_eq!((grid.width, grid.height), (32, 8));
+
+        let gs1 = FieldSpec { gs1: Some(true), size: None, ..spec.clone() };
+        assert!(symbol_module_grid("(01)09501101530003", &gs1).is_ok());
+        let gs1_qr = FieldSpec { output_type: "QR".to_string(), ..gs1 };
+        assert!(symbol_module_grid("(01)09501101530003", &gs1_qr).is_err());
+
+        let too_small = FieldSpec { size: Some("10x10".to_string()), ..spec };
+        assert!(symbol_module_grid("12345678901234567890", &too_small).is_err());
+    }
+
+    #[test]
+    fn test_micro_qr_and_rmqr() {
+        let spec = FieldSpec {
+            output_type: "MicroQR".to_string(),
+            ..Default::default()
+        };
+        // Five digits fit M1 (11 modules) with the 2-module quiet zone
+        let grid = symbol_module_grid("12345", &spec).unwrap();
+        assert_eq!((grid.width, grid.height), (15, 15));
+        // Letters need M2 or larger
+        let grid = symbol_module_grid("AB12", &spec).unwrap();
+        assert_eq!(grid.width, 13 + 4);
+
+        let high = FieldSpec { ec_level: Some(EcLevel::H), ..spec.clone() };
+        assert!(symbol_module_grid("12345", &high).is_err());
+        let too_long = FieldSpec { version: Some(1), ..spec.clone() };
+        assert!(symbol_module_grid("123456", &too_long).is_err());
+        assert!(symbol_module_grid(&"A".repeat(40), &spec).is_err());
+
+        let rmqr = FieldSpec { output_type: "rMQR".to_string(), ..spec };
+        let grid = symbol_module_grid("PN-0042", &rmqr).unwrap();
+        assert_eq!((grid.width, grid.height), (43 + 4, 7 + 4));
+        let sized = FieldSpec { size: Some("R11x27".to_string()), ..rmqr };
+        let grid = symbol_module_grid("PN-0042", &sized).unwrap();
+        assert_eq!((grid.width, grid.height), (27 + 4, 11 + 4));
+    }
+
+    #[test]
+    fn test_pdf417() {
+        let spec = FieldSpec {
+            output_type: "PDF417".to_string(),
+            columns: Some(1),
+            security_level: Some(1),
+            ..Default::default()
+        };
+        // Nine codewords in one column, each row three modules high
+        let grid = symbol_module_grid("PDF417", &spec).unwrap();
+        assert_eq!((grid.width, grid.height), (86 + 4, 27 + 4));
+
+        let rows = FieldSpec { columns: None, rows: Some(3), ..spec.clone() };
+        let grid = symbol_module_grid("PDF417", &rows).unwrap();
+        assert_eq!((grid.width, grid.height), (17 * 3 + 69 + 4, 9 + 4));
+
+        let gs1 = FieldSpec { gs1: Some(true), ..spec.clone() };
+        assert!(symbol_module_grid("(01)09501101530003", &gs1).is_err());
+        let too_high = FieldSpec { security_level: Some(9), ..spec.clone() };
+        assert!(symbol_module_grid("PDF417", &too_high).is_err());
+
+        let mut doc = Document::with_version("1.5");
+        let mut cache = XObjectCache::new();
+        let mut builder = ContentBuilder::new(test_fonts());
+        builder.add_field("Lot", "PDF417", &spec, 800.0, &mut doc, &mut cache).unwrap();
+        assert_eq!(doc.objects.len(), 1);
+    }
+
+    #[test]
+    fn test_add_barcode_with_text() {
+        let mut builder = ContentBuilder::new(test_fonts());
+        let mut doc = Document::with_version("1.5");
+        let mut cache = XObjectCache::new();
+        let spec = FieldSpec {
+            x: crate::config::Dimension(10.0),
+            y: crate::config::Dimension(20.0),
+            w: crate::config::Dimension(113.0),
+            h: crate::config::Dimension(60.0),
+            output_type: "EAN13".to_string(),
+            font_size: Some(crate::config::Dimension(10.0)),
+            ..Default::default()
+        };
+
+        builder.add_field("GTIN", "400638133393", &spec, 800.0, &mut doc, &mut cache).unwrap();
+
+        // Bars above a 12 pt text line, then the digits with the check digit
+        assert_eq!(builder.content_parts[0], "q 113 0 0 48 10 732 cm /Fm1 Do Q ");
+        assert!(builder.content_parts[1].contains("(4006381333931) Tj"));
+        // 11 + 95 + 7 modules, starting with the start guard after the quiet zone
+        let form = doc.get_object((1, 0)).unwrap().as_stream().unwrap();
+        let content = String::from_utf8(form.content.clone()).unwrap();
+        assert!(content.contains("1 g 0 0 113 1 re f 0 g 11 0 1 1 re 13 0 1 1 re "));
+    }
+
+    #[test]
+    fn test_add_barcode_without_text() {
+        let mut builder = ContentBuilder::new(test_fonts());
+        let mut doc = Document::with_version("1.5");
+        let mut cache = XObjectCache::new();
+        let spec = FieldSpec {
+            w: crate::config::Dimension(100.0),
+            h: crate::config::Dimension(30.0),
+            output_type: "Code128".to_string(),
+            human_readable: Some(false),
+            ..Default::default()
+        };
+
+        builder.add_field("SKU", "ABC-123", &spec, 800.0, &mut doc, &mut cache).unwrap();
+        assert_eq!(builder.content_parts, ["q 100 0 0 30 0 770 cm /Fm1 Do Q "]);
+
+        let invalid = FieldSpec { output_type: "UPCA".to_string(), ..spec.clone() };
+        assert!(builder.add_field("SKU", "ABC-123", &invalid, 800.0, &mut doc, &mut cache).is_err());
+    }
 }
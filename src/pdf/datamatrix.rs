@@ -0,0 +1,453 @@
+//! Data Matrix (ECC 200) encoding.
+//!
+//! This module handles:
+//! - ASCII encodation with digit pairs, extended characters and GS1 FNC1
+//! - Latin-1 data, or UTF-8 behind an ECI designator for other text
+//! - Symbol size selection (square sizes, or any size given by name)
+//! - Reed-Solomon error correction with interleaved blocks
+//! - Module placement and the finder and alignment patterns of each data region
+
+use anyhow::{anyhow, Result};
+use super::eci::symbol_bytes;
+use super::gs1::{element_string, GROUP_SEPARATOR};
+use super::reed_solomon::GaloisField;
+
+/// Pad codeword following the data
+const PAD: u16 = 129;
+
+/// FNC1, marking GS1 data in the first position and separating variable-length values
+const FNC1: u16 = 232;
+
+/// Upper shift, for the next character in the range 128-255
+const UPPER_SHIFT: u16 = 235;
+
+/// ECI designator, followed by the ECI number plus 1 (for ECIs up to 126)
+const ECI: u16 = 241;
+
+/// Geometry and error correction of a symbol size
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SymbolSize {
+    rows: usize,
+    columns: usize,
+    /// Size of each data region, without its finder and alignment pattern
+    region_rows: usize,
+    region_columns: usize,
+    data_codewords: usize,
+    check_codewords: usize,
+    /// Number of interleaved error correction blocks
+    blocks: usize,
+}
+
+const fn size(
+    (rows, columns): (usize, usize),
+    (region_rows, region_columns): (usize, usize),
+    (data_codewords, check_codewords): (usize, usize),
+    blocks: usize,
+) -> SymbolSize {
+    SymbolSize { rows, columns, region_rows, region_columns, data_codewords, check_codewords, blocks }
+}
+
+/// ECC 200 symbol sizes, squares first, in order of capacity
+const SYMBOL_SIZES: [SymbolSize; 30] = [
+    size((10, 10), (8, 8), (3, 5), 1),
+    size((12, 12), (10, 10), (5, 7), 1),
+    size((14, 14), (12, 12), (8, 10), 1),
+    size((16, 16), (14, 14), (12, 12), 1),
+    size((18, 18), (16, 16), (18, 14), 1),
+    size((20, 20), (18, 18), (22, 18), 1),
+    size((22, 22), (20, 20), (30, 20), 1),
+    size((24, 24), (22, 22), (36, 24), 1),
+    size((26, 26), (24, 24), (44, 28), 1),
+    size((32, 32), (14, 14), (62, 36), 1),
+    size((36, 36), (16, 16), (86, 42), 1),
+    size((40, 40), (18, 18), (114, 48), 1),
+    size((44, 44), (20, 20), (144, 56), 1),
+    size((48, 48), (22, 22), (174, 68), 1),
+    size((52, 52), (24, 24), (204, 84), 2),
+    size((64, 64), (14, 14), (280, 112), 2),
+    size((72, 72), (16, 16), (368, 144), 4),
+    size((80, 80), (18, 18), (456, 192), 4),
+    size((88, 88), (20, 20), (576, 224), 4),
+    size((96, 96), (22, 22), (696, 272), 4),
+    size((104, 104), (24, 24), (816, 336), 6),
+    size((120, 120), (18, 18), (1050, 408), 6),
+    size((132, 132), (20, 20), (1304, 496), 8),
+    size((144, 144), (22, 22), (1558, 620), 10),
+    size((8, 18), (6, 16), (5, 7), 1),
+    size((8, 32), (6, 14), (10, 11), 1),
+    size((12, 26), (10, 24), (16, 14), 1),
+    size((12, 36), (10, 16), (22, 18), 1),
+    size((16, 36), (14, 16), (32, 24), 1),
+    size((16, 48), (14, 22), (49, 28), 1),
+];
+
+impl SymbolSize {
+    fn is_square(&self) -> bool {
+        self.rows == self.columns
+    }
+
+    /// Size of the mapping matrix holding the codeword modules
+    fn mapping_size(&self) -> (usize, usize) {
+        let regions_down = self.rows / (self.region_rows + 2);
+        let regions_across = self.columns / (self.region_columns + 2);
+        (regions_down * self.region_rows, regions_across * self.region_columns)
+    }
+}
+
+/// Look up a symbol size written as `"rowsxcolumns"`, e.g. `"16x48"`
+fn find_size(name: &str) -> Result<SymbolSize> {
+    let parsed = name.split_once(['x', 'X'])
+        .and_then(|(rows, columns)| Some((rows.trim().parse().ok()?, columns.trim().parse().ok()?)));
+    SYMBOL_SIZES.iter()
+        .find(|size| parsed == Some((size.rows, size.columns)))
+        .copied()
+        .ok_or_else(|| {
+            let names: Vec<String> = SYMBOL_SIZES.iter().map(|s| format!("{}x{}", s.rows, s.columns)).collect();
+            anyhow!("Unknown Data Matrix size '{}' (sizes: {})", name, names.join(", "))
+        })
+}
+
+/// ASCII encodation of `data`
+///
+/// Pairs of digits share a codeword and bytes above 127 use an upper shift.
+/// GS1 data starts with FNC1 and uses FNC1 as the group separator.
+fn encode_ascii(data: &[u8], gs1: bool) -> Vec<u16> {
+    let mut codewords = Vec::new();
+    if gs1 {
+        codewords.push(FNC1);
+    }
+    let mut i = 0;
+    while i < data.len() {
+        let b = data[i];
+        if gs1 && b == GROUP_SEPARATOR {
+            codewords.push(FNC1);
+        } else if b.is_ascii_digit() && data.get(i + 1).is_some_and(u8::is_ascii_digit) {
+            codewords.push(130 + ((b - b'0') * 10 + data[i + 1] - b'0') as u16);
+            i += 1;
+        } else if b < 128 {
+            codewords.push(b as u16 + 1);
+        } else {
+            codewords.push(UPPER_SHIFT);
+            codewords.push(b as u16 - 127);
+        }
+        i += 1;
+    }
+    codewords
+}
+
+/// Data codewords for `data`, with an ECI designator first when the text
+/// does not fit in Latin-1
+fn data_codewords(data: &str, gs1: bool) -> Result<Vec<u16>> {
+    let (bytes, eci) = if gs1 { (element_string(data)?, None) } else { symbol_bytes(data) };
+    let mut codewords = match eci {
+        Some(eci) => vec![ECI, eci as u16 + 1],
+        None => Vec::new(),
+    };
+    codewords.extend(encode_ascii(&bytes, gs1));
+    Ok(codewords)
+}
+
+/// Fill the data codewords up to the capacity of the symbol
+///
+/// The first pad is 129; later pads are scrambled by their position.
+fn pad_codewords(codewords: &mut Vec<u16>, capacity: usize) {
+    if codewords.len() < capacity {
+        codewords.push(PAD);
+    }
+    while codewords.len() < capacity {
+        let position = codewords.len() + 1;
+        let mut pad = PAD as usize + (149 * position) % 253 + 1;
+        if pad > 254 {
+            pad -= 254;
+        }
+        codewords.push(pad as u16);
+    }
+}
+
+/// Data codewords followed by the interleaved error correction codewords
+fn add_check_codewords(data: &[u16], size: &SymbolSize) -> Vec<u16> {
+    let field = GaloisField::new(8, 0x12D);
+    let per_block = size.check_codewords / size.blocks;
+    let mut codewords = data.to_vec();
+    codewords.resize(size.data_codewords + size.check_codewords, 0);
+    for block in 0..size.blocks {
+        let block_data: Vec<u16> = data.iter().skip(block).step_by(size.blocks).copied().collect();
+        let check = field.check_codewords(&block_data, per_block, 1);
+        for (i, value) in check.into_iter().enumerate() {
+            codewords[size.data_codewords + block + i * size.blocks] = value;
+        }
+    }
+    codewords
+}
+
+/// Module placement of the codewords in the mapping matrix
+///
+/// Each cell holds `codeword * 8 + bit` (bit 0 is the most significant),
+/// `FIXED_DARK` or `FIXED_LIGHT`, following the ECC 200 placement algorithm.
+struct Placement {
+    rows: usize,
+    columns: usize,
+    cells: Vec<Option<usize>>,
+}
+
+/// Placement cells not holding a codeword bit
+const FIXED_DARK: usize = usize::MAX;
+const FIXED_LIGHT: usize = usize::MAX - 1;
+
+impl Placement {
+    fn new(rows: usize, columns: usize) -> Self {
+        let mut placement = Self { rows, columns, cells: vec![None; rows * columns] };
+        placement.place_all();
+        placement
+    }
+
+    fn module(&mut self, mut row: isize, mut column: isize, codeword: usize, bit: usize) {
+        let (rows, columns) = (self.rows as isize, self.columns as isize);
+        if row < 0 {
+            row += rows;
+            column += 4 - ((rows + 4) % 8);
+        }
+        if column < 0 {
+            column += columns;
+            row += 4 - ((columns + 4) % 8);
+        }
+        self.cells[row as usize * self.columns + column as usize] = Some(codeword * 8 + bit);
+    }
+
+    /// The standard L-shaped arrangement of a codeword ending at (row, column)
+    fn utah(&mut self, row: isize, column: isize, codeword: usize) {
+        let offsets = [(-2, -2), (-2, -1), (-1, -2), (-1, -1), (-1, 0), (0, -2), (0, -1), (0, 0)];
+        for (bit, (dr, dc)) in offsets.into_iter().enumerate() {
+            self.module(row + dr, column + dc, codeword, bit);
+        }
+    }
+
+    /// Codewords wrapping around the corners of the matrix
+    fn corner(&mut self, positions: [(isize, isize); 8], codeword: usize) {
+        for (bit, (row, column)) in positions.into_iter().enumerate() {
+            self.module(row, column, codeword, bit);
+        }
+    }
+
+    fn is_free(&self, row: isize, column: isize) -> bool {
+        row >= 0 && column >= 0 && (row as usize) < self.rows && (column as usize) < self.columns
+            && self.cells[row as usize * self.columns + column as usize].is_none()
+    }
+
+    fn place_all(&mut self) {
+        let (rows, columns) = (self.rows as isize, self.columns as isize);
+        let mut codeword = 0;
+        let (mut row, mut column) = (4isize, 0isize);
+        loop {
+            if row == rows && column == 0 {
+                self.corner([(rows - 1, 0), (rows - 1, 1), (rows - 1, 2), (0, columns - 2),
+                    (0, columns - 1), (1, columns - 1), (2, columns - 1), (3, columns - 1)], codeword);
+                codeword += 1;
+            }
+            if row == rows - 2 && column == 0 && columns % 4 != 0 {
+                self.corner([(rows - 3, 0), (rows - 2, 0), (rows - 1, 0), (0, columns - 4),
+                    (0, columns - 3), (0, columns - 2), (0, columns - 1), (1, columns - 1)], codeword);
+                codeword += 1;
+            }
+            if row == rows - 2 && column == 0 && columns % 8 == 4 {
+                self.corner([(rows - 3, 0), (rows - 2, 0), (rows - 1, 0), (0, columns - 2),
+                    (0, columns - 1), (1, columns - 1), (2, columns - 1), (3, columns - 1)], codeword);
+                codeword += 1;
+            }
+            if row == rows + 4 && column == 2 && columns % 8 == 0 {
+                self.corner([(rows - 1, 0), (rows - 1, columns - 1), (0, columns - 3), (0, columns - 2),
+                    (0, columns - 1), (1, columns - 3), (1, columns - 2), (1, columns - 1)], codeword);
+                codeword += 1;
+            }
+
+            // Sweep up and to the right
+            loop {
+                if row < rows && column >= 0 && self.is_free(row, column) {
+                    self.utah(row, column, codeword);
+                    codeword += 1;
+                }
+                row -= 2;
+                column += 2;
+                if !(row >= 0 && column < columns) {
+                    break;
+                }
+            }
+            row += 1;
+            column += 3;
+
+            // Sweep down and to the left
+            loop {
+                if row >= 0 && column < columns && self.is_free(row, column) {
+                    self.utah(row, column, codeword);
+                    codeword += 1;
+                }
+                row += 2;
+                column -= 2;
+                if !(row < rows && column >= 0) {
+                    break;
+                }
+            }
+            row += 3;
+            column += 1;
+
+            if !(row < rows || column < columns) {
+                break;
+            }
+        }
+
+        // Untouched modules in the bottom-right corner get a fixed pattern
+        let last = self.rows * self.columns - 1;
+        if self.cells[last].is_none() {
+            self.cells[last] = Some(FIXED_DARK);
+            self.cells[last - self.columns - 1] = Some(FIXED_DARK);
+            self.cells[last - 1] = Some(FIXED_LIGHT);
+            self.cells[last - self.columns] = Some(FIXED_LIGHT);
+        }
+    }
+
+    /// Whether the module at (row, column) is dark for the given codewords
+    fn is_dark(&self, row: usize, column: usize, codewords: &[u16]) -> bool {
+        match self.cells[row * self.columns + column] {
+            Some(FIXED_DARK) => true,
+            Some(FIXED_LIGHT) | None => false,
+            Some(cell) => codewords[cell / 8] & (0x80 >> (cell % 8)) != 0,
+        }
+    }
+}
+
+/// Encode `data` as a Data Matrix symbol
+///
+/// Returns the width, height and row-major dark modules (top row first),
+/// without a quiet zone. Without `size`, the smallest square symbol that
+/// holds the data is used. With `gs1`, the data is GS1 `(AI)value` pairs.
+pub fn encode(data: &str, size: Option<&str>, gs1: bool) -> Result<(usize, usize, Vec<bool>)> {
+    let mut codewords = data_codewords(data, gs1)?;
+
+    let symbol = match size {
+        Some(name) => {
+            let symbol = find_size(name)?;
+            if codewords.len() > symbol.data_codewords {
+                return Err(anyhow!(
+                    "Data for Data Matrix does not fit in size {}x{} ({} of {} codewords): {}",
+                    symbol.rows, symbol.columns, codewords.len(), symbol.data_codewords, data
+                ));
+            }
+            symbol
+        }
+        None => SYMBOL_SIZES.iter()
+            .filter(|size| size.is_square())
+            .find(|size| size.data_codewords >= codewords.len())
+            .copied()
+            .ok_or_else(|| anyhow!("Data for Data Matrix is too long ({} codewords): {}", codewords.len(), data))?,
+    };
+    pad_codewords(&mut codewords, symbol.data_codewords);
+    let codewords = add_check_codewords(&codewords, &symbol);
+
+    let (mapping_rows, mapping_columns) = symbol.mapping_size();
+    let placement = Placement::new(mapping_rows, mapping_columns);
+
+    // Every data region has a solid L on its left and bottom edges and
+    // alternating modules on its top and right edges
+    let (region_height, region_width) = (symbol.region_rows + 2, symbol.region_columns + 2);
+    let mut modules = vec![false; symbol.rows * symbol.columns];
+    for row in 0..symbol.rows {
+        for column in 0..symbol.columns {
+            let (r, c) = (row % region_height, column % region_width);
+            modules[row * symbol.columns + column] = if r == region_height - 1 || c == 0 {
+                true
+            } else if r == 0 {
+                c % 2 == 0
+            } else if c == region_width - 1 {
+                r % 2 == 1
+            } else {
+                let mapping_row = row / region_height * symbol.region_rows + r - 1;
+                let mapping_column = column / region_width * symbol.region_columns + c - 1;
+                placement.is_dark(mapping_row, mapping_column, &codewords)
+            };
+        }
+    }
+    Ok((symbol.columns, symbol.rows, modules))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbol_sizes() {
+        for size in SYMBOL_SIZES {
+            let (rows, columns) = size.mapping_size();
+            assert_eq!(rows * columns / 8, size.data_codewords + size.check_codewords, "{:?}", size);
+            assert_eq!(size.check_codewords % size.blocks, 0, "{:?}", size);
+        }
+    }
+
+    #[test]
+    fn test_encode_ascii() {
+        assert_eq!(encode_ascii(b"123456", false), [142, 164, 186]);
+        assert_eq!(encode_ascii(b"A1b", false), [66, 50, 99]);
+        assert_eq!(encode_ascii(b"\xE9", false), [235, 106]);
+        assert_eq!(encode_ascii(b"10AB\x1d21", true), [232, 140, 66, 67, 232, 151]);
+    }
+
+    #[test]
+    fn test_data_codewords() {
+        assert_eq!(data_codewords("é", false).unwrap(), [235, 106]);
+        // "€" is not in Latin-1, so the UTF-8 bytes follow ECI 26
+        assert_eq!(data_codewords("€", false).unwrap(), [241, 27, 235, 99, 235, 3, 235, 45]);
+        assert_eq!(data_codewords("(10)AB", true).unwrap(), [232, 140, 66, 67]);
+    }
+
+    #[test]
+    fn test_check_codewords() {
+        // The "123456" example of ISO/IEC 16022
+        let size = find_size("10x10").unwrap();
+        assert_eq!(add_check_codewords(&[142, 164, 186], &size), [142, 164, 186, 114, 25, 5, 88, 102]);
+    }
+
+    #[test]
+    fn test_pad_codewords() {
+        let mut codewords = vec![66];
+        pad_codewords(&mut codewords, 5);
+        assert_eq!(codewords, [66, 129, 70, 220, 115]);
+    }
+
+    #[test]
+    fn test_placement_covers_every_bit() {
+        for size in SYMBOL_SIZES {
+            let (rows, columns) = size.mapping_size();
+            let placement = Placement::new(rows, columns);
+            let mut bits: Vec<usize> = placement.cells.iter()
+                .filter_map(|cell| cell.filter(|&cell| cell < FIXED_LIGHT))
+                .collect();
+            bits.sort();
+            let total = (size.data_codewords + size.check_codewords) * 8;
+            assert_eq!(bits, (0..total).collect::<Vec<_>>(), "{:?}", size);
+        }
+    }
+
+    #[test]
+    fn test_finder_pattern() {
+        let (width, height, modules) = encode("123456", None, false).unwrap();
+        assert_eq!((width, height), (10, 10));
+        let row = |r: usize| -> String {
+            modules[r * width..(r + 1) * width].iter().map(|&d| if d { '1' } else { '0' }).collect()
+        };
+        assert_eq!(row(0), "1010101010");
+        assert_eq!(row(9), "1111111111");
+        assert!((0..10).all(|r| modules[r * width]));
+        assert!((0..10).all(|r| modules[r * width + 9] == (r % 2 == 1)));
+    }
+
+    #[test]
+    fn test_sizes() {
+        let (width, height, _) = encode("ABCDEFGHIJ", Some("16x48"), false).unwrap();
+        assert_eq!((width, height), (48, 16));
+        // Sixteen digits make eight codewords, the capacity of 14x14
+        assert_eq!(encode(&"9".repeat(16), None, false).unwrap().0, 14);
+        assert_eq!(encode(&"9".repeat(18), None, false).unwrap().0, 16);
+        assert!(encode("ABCDEFGHIJ", Some("10x10"), false).is_err());
+        assert!(encode("A", Some("11x11"), false).is_err());
+        assert!(encode(&"A".repeat(1600), None, false).is_err());
+    }
+}
@@ -3,80 +3,306 @@
 //! This module handles:
 //! - Creating output PDFs from base templates
 //! - Adding QR codes and text to pages
-//! - Font selection and embedding (standard and CID fonts)
+//! - Font selection and embedding (standard, TrueType/OpenType and CID fonts)
 //! - Page cloning and resource management
+//! - N-up sheets holding several data rows
 
 use anyhow::{anyhow, Context, Result};
-use lopdf::{Dictionary, Document, Object};
-use crate::config::{DataRow, PlaceConfig};
-use super::content::ContentBuilder;
-use super::resources::update_page_resources_with_fonts;
-use super::fonts::{create_font, StandardFont, find_cid_font, embed_cid_font};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::rc::Rc;
+use crate::config::{DataRow, Direction, FieldSpec, LayoutBase, PlaceConfig};
+use super::content::{field_text, ContentBuilder, TextFonts, XObjectCache};
+use super::resources::{resource_names, update_page_resources_with_fonts};
+use super::fonts::FontRegistry;
+use super::imposition::{create_template_xobject, SheetLayout};
+use super::calibration::Calibration;
+use super::marks::PrintMarks;
+use super::page::{inherited_attribute, PageGeometry};
 
-/// Check if the text requires CID font (non-ASCII characters)
-fn needs_cid_font(text: &str) -> bool {
-    // Check for any characters outside ASCII range
-    text.chars().any(|c| c > '\u{7F}')
+/// Font for fields without their own `font` setting
+const DEFAULT_FONT: &str = "Helvetica";
+
+/// Add every font needed by the text fields to the document
+///
+/// Fonts named in settings.json are embedded once. A CJK-capable fallback
+/// font is added only when some text cannot be drawn with its configured font.
+fn prepare_text_fonts(
+    doc: &mut Document,
+    registry: &mut FontRegistry,
+    data_rows: &[DataRow],
+    config: &PlaceConfig,
+) -> Result<TextFonts> {
+    // settings.font is the default font; fall back to Helvetica if it is not installed
+    let default = match config.settings.font.as_deref() {
+        Some(name) => match registry.get_or_add(doc, name)? {
+            Some(font) => font,
+            None => {
+                eprintln!("Warning: font '{}' not found, using {}", name, DEFAULT_FONT);
+                registry.get_or_add(doc, DEFAULT_FONT)?
+                    .ok_or_else(|| anyhow!("Standard font {} unavailable", DEFAULT_FONT))?
+            }
+        },
+        None => registry.get_or_add(doc, DEFAULT_FONT)?
+            .ok_or_else(|| anyhow!("Standard font {} unavailable", DEFAULT_FONT))?,
+    };
+
+    let mut named = HashMap::new();
+    for (field_name, spec) in config.all_fields() {
+        if let Some(name) = &spec.font
+            && !named.contains_key(name) {
+                let font = registry.get_or_add(doc, name)?
+                    .ok_or_else(|| anyhow!("Font '{}' for field '{}' not found", name, field_name))?;
+                named.insert(name.clone(), font);
+            }
+    }
+
+    let mut fonts = TextFonts {
+        default,
+        named,
+        fallback: None,
+        vertical: HashMap::new(),
+    };
+
+    // Add a CID font if some text cannot be drawn with its configured font
+    let needs_fallback = data_rows.iter().any(|row| {
+        config.all_fields().any(|(field_name, spec)| {
+            row.data.get(field_name)
+                .and_then(|value| field_text(spec, value))
+                .is_some_and(|text| !fonts.configured(spec).can_render(&text))
+        })
+    });
+    if needs_fallback {
+        let fallback = registry.get_or_add_cid_fallback(doc, config.settings.font.as_deref())
+            .with_context(|| "Failed to embed CID font")?;
+        if fallback.is_none() {
+            return Err(anyhow!(
+                "Non-ASCII characters detected in data, but no suitable CID font found on the system.\n\
+                Please install a CJK font package (e.g., fonts-noto-cjk on Debian/Ubuntu, \
+                noto-fonts-cjk on Arch, or similar packages on other distributions)."
+            ));
+        }
+        fonts.fallback = fallback;
+    }
+
+    // Vertical fields draw with Identity-V variants of their embedded fonts
+    for (_, spec) in config.all_fields() {
+        if spec.output_type != "Text" || spec.direction != Some(Direction::Vertical) {
+            continue;
+        }
+        let candidates = [Some(fonts.configured(spec).clone()), fonts.fallback.clone()];
+        for font in candidates.into_iter().flatten() {
+            if !fonts.vertical.contains_key(&font.id)
+                && let Some(vertical) = registry.get_or_add_vertical(doc, &font) {
+                    fonts.vertical.insert(font.id, vertical);
+                }
+        }
+    }
+
+    // Embed the font programs, subset to the characters each font draws
+    // (vertical variants share the program of their horizontal font)
+    registry.embed_programs(doc, &used_chars(config, data_rows, &fonts))?;
+
+    Ok(fonts)
 }
 
-/// Collect all text from data rows to check if CID font is needed
-fn should_use_cid_font(data_rows: &[DataRow], config: &PlaceConfig) -> bool {
+/// Characters drawn with each embedded font across all rows, keyed by the
+/// font's object ID
+///
+/// Standard fonts are not embedded, so their characters are left out.
+fn used_chars(config: &PlaceConfig, data_rows: &[DataRow], fonts: &TextFonts) -> HashMap<ObjectId, BTreeSet<char>> {
+    let mut used_chars: HashMap<ObjectId, BTreeSet<char>> = HashMap::new();
     for row in data_rows {
-        for field_name in config.fields.keys() {
-            if let Some(value) = row.data.get(field_name)
-                && needs_cid_font(value) {
-                    return true;
+        for (field_name, spec) in config.all_fields() {
+            if let Some(text) = row.data.get(field_name).and_then(|value| field_text(spec, value)) {
+                let font = fonts.select(spec, &text);
+                if font.is_composite() {
+                    used_chars.entry(font.id).or_default().extend(text.chars());
                 }
+            }
         }
     }
-    false
+    used_chars
 }
 
-/// Font references for use in content generation
+/// Fonts shared by all generated pages
 struct FontRefs {
-    regular_id: (u32, u16),
-    regular_name: String,
-    cid_id: Option<(u32, u16)>,
-    cid_name: Option<String>,
+    text_fonts: Rc<TextFonts>,
+    resources: Dictionary,
+    /// Resource names used by the base pages, which generated names avoid
+    reserved_names: Rc<HashSet<String>>,
 }
 
 impl FontRefs {
     /// Create a ContentBuilder with the appropriate fonts configured
     fn create_content_builder(&self) -> ContentBuilder {
-        if let Some(ref cid_name) = self.cid_name {
-            ContentBuilder::new_with_cid_font(self.regular_name.clone(), cid_name.clone())
-        } else {
-            ContentBuilder::new(self.regular_name.clone())
+        let mut builder = ContentBuilder::new(Rc::clone(&self.text_fonts));
+        builder.reserve_names(Rc::clone(&self.reserved_names));
+        builder
+    }
+}
+
+/// Printer settings applied to every generated page
+struct PrintSetup {
+    marks: Option<PrintMarks>,
+    calibration: Calibration,
+}
+
+impl PrintSetup {
+    /// Map display-space overlay content onto a page, with the printer
+    /// calibration applied
+    fn place_overlay(&self, geometry: &PageGeometry, content: Vec<u8>) -> Vec<u8> {
+        geometry.place_content(self.calibration.apply(content, geometry.height()))
+    }
+}
+
+/// A page of base.pdf used as a template for output pages
+#[derive(Clone)]
+struct BasePage {
+    /// 1-based page number in base.pdf
+    number: u32,
+    /// Page dictionary with inherited attributes copied in, so clones do not
+    /// depend on their position in the page tree
+    dict: Dictionary,
+    geometry: PageGeometry,
+}
+
+/// Page attributes a page can inherit from its ancestors in the page tree
+const INHERITABLE_ATTRIBUTES: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// Read every page of the base document
+fn load_base_pages(base_doc: &Document) -> Result<Vec<BasePage>> {
+    let mut base_pages = Vec::new();
+    for (number, page_id) in base_doc.get_pages() {
+        let mut dict = base_doc.get_dictionary(page_id)?.clone();
+        for key in INHERITABLE_ATTRIBUTES {
+            if !dict.has(key)
+                && let Some(value) = inherited_attribute(base_doc, &dict, key) {
+                    dict.set(key.to_vec(), value.clone());
+                }
         }
+        let geometry = PageGeometry::from_page(base_doc, page_id)
+            .with_context(|| format!("Failed to read the geometry of base page {}", number))?;
+        base_pages.push(BasePage { number, dict, geometry });
+    }
+    if base_pages.is_empty() {
+        return Err(anyhow!("No pages in base.pdf"));
+    }
+    Ok(base_pages)
+}
+
+/// Base pages (1-based numbers) printed as front pages
+///
+/// This is every page except the back page, unless base.pdf has only one page.
+fn front_pages(config: &PlaceConfig, page_count: usize) -> Vec<u32> {
+    let back_page = config.back.as_ref().and_then(|back| back.page).filter(|_| page_count > 1);
+    (1..=page_count as u32).filter(|page| Some(*page) != back_page).collect()
+}
+
+/// Base pages (1-based numbers) printed for a row
+///
+/// Without `template_page_column` every row gets all front pages; otherwise
+/// the column names the single base page for the row, which must be a front
+/// page.
+fn pages_for_row(row_index: usize, row: &DataRow, config: &PlaceConfig, page_count: usize) -> Result<Vec<u32>> {
+    let Some(column) = &config.settings.template_page_column else {
+        return Ok(front_pages(config, page_count));
+    };
+    let value = row.data.get(column)
+        .ok_or_else(|| anyhow!("Row {}: missing template page column '{}'", row_index + 1, column))?;
+    let page: u32 = value.trim().parse()
+        .map_err(|_| anyhow!("Row {}: template page '{}' is not a page number", row_index + 1, value))?;
+    if page == 0 || page as usize > page_count {
+        return Err(anyhow!(
+            "Row {}: template page {} does not exist (base.pdf has {} pages)",
+            row_index + 1, page, page_count
+        ));
+    }
+    if !front_pages(config, page_count).contains(&page) {
+        return Err(anyhow!("Row {}: template page {} is the back page", row_index + 1, page));
     }
+    Ok(vec![page])
 }
 
-/// Populate a ContentBuilder with fields from a data row
+/// Whether a field is drawn on a base page
+///
+/// Fields without a `page` go on the first front page, or on whichever page
+/// a row selects through `template_page_column`.
+fn field_on_page(field_page: Option<u32>, page_number: u32, first_front_page: u32, config: &PlaceConfig) -> bool {
+    match field_page {
+        Some(page) => page == page_number,
+        None => config.settings.template_page_column.is_some() || page_number == first_front_page,
+    }
+}
+
+/// Side of a printed sheet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Front,
+    Back,
+}
+
+/// Fields drawn on a side printed from base page `page_number`
+///
+/// Front fields are chosen by their `page`; every back field is drawn on the back.
+fn fields_for_side(
+    config: &PlaceConfig,
+    side: Side,
+    page_number: u32,
+    page_count: usize,
+) -> Vec<(&String, &FieldSpec)> {
+    match side {
+        Side::Front => {
+            let first_front_page = front_pages(config, page_count).first().copied().unwrap_or(1);
+            config.fields.iter()
+                .filter(|(_, spec)| field_on_page(spec.page, page_number, first_front_page, config))
+                .collect()
+        }
+        Side::Back => config.back.iter().flat_map(|back| back.fields.iter()).collect(),
+    }
+}
+
+/// Blank page the size and orientation of `geometry`, for backs without a base page
+fn blank_page_like(geometry: &PageGeometry) -> BasePage {
+    let media_box: Vec<Object> = geometry.visible_box.iter().map(|v| Object::Real(*v as f32)).collect();
+    let mut dict = Dictionary::new();
+    dict.set("Type", "Page");
+    dict.set("MediaBox", media_box);
+    dict.set("Rotate", geometry.rotate);
+    BasePage { number: 0, dict, geometry: *geometry }
+}
+
+/// Populate a ContentBuilder with the given fields of a data row
 fn populate_content_builder(
     builder: &mut ContentBuilder,
     row: &DataRow,
-    config: &PlaceConfig,
+    fields: &[(&String, &FieldSpec)],
     page_height: f64,
     doc: &mut Document,
+    cache: &mut XObjectCache,
 ) -> Result<()> {
-    for (field_name, field_spec) in &config.fields {
-        let value = row.data.get(field_name).map(|s| s.as_str()).unwrap_or("");
-        builder.add_field(field_name, value, field_spec, page_height, doc)?;
+    for (field_name, field_spec) in fields {
+        let value = row.data.get(*field_name).map(|s| s.as_str()).unwrap_or("");
+        builder.add_field(field_name, value, field_spec, page_height, doc, cache)?;
     }
     Ok(())
 }
 
-/// Create a single page with content for a given data row
+/// Create a single page with `fields` of a data row drawn on a copy of `base_page`
 fn create_page_for_row(
     output_doc: &mut Document,
-    base_page: &Dictionary,
+    base_page: &BasePage,
+    fields: &[(&String, &FieldSpec)],
     row: &DataRow,
-    config: &PlaceConfig,
-    page_height: f64,
     fonts: &FontRefs,
+    print: &PrintSetup,
+    cache: &mut XObjectCache,
 ) -> Result<(u32, u16)> {
-    // Clone the base page for this row
-    let page_dict = base_page.clone();
+    // Clone the base page for this row, enlarged for bleed and marks
+    let mut page_dict = base_page.dict.clone();
+    if let Some(marks) = &print.marks {
+        marks.set_page_boxes(&mut page_dict, base_page.geometry.visible_box);
+    }
 
     // Add the cloned page to the document
     let page_id = output_doc.add_object(Object::Dictionary(page_dict));
@@ -85,153 +311,953 @@ fn create_page_for_row(
     let mut builder = fonts.create_content_builder();
 
     // Populate builder with content from the row
-    populate_content_builder(&mut builder, row, config, page_height, output_doc)?;
+    let geometry = &base_page.geometry;
+    populate_content_builder(
+        &mut builder,
+        row,
+        fields,
+        geometry.height(),
+        output_doc,
+        cache,
+    )?;
+
+    if let Some(marks) = &print.marks {
+        let (width, height) = (geometry.width(), geometry.height());
+        builder.add_print_marks(marks, width, height, &[0.0, width], &[0.0, height]);
+    }
 
     // Append overlay content to the cloned page
-    let overlay_bytes = builder.build_content_bytes();
+    let overlay_bytes = print.place_overlay(geometry, builder.build_content_bytes());
     output_doc.add_page_contents(page_id, overlay_bytes)?;
 
     // Update the page's resources with fonts and XObjects
     update_page_resources_with_fonts(
         output_doc,
         page_id,
-        fonts.regular_id,
-        &fonts.regular_name,
-        fonts.cid_id,
-        fonts.cid_name.as_deref(),
+        &fonts.resources,
         &builder.xobjects,
-    );
+        &builder.ext_gstates,
+        &builder.color_spaces,
+    )?;
 
     Ok(page_id)
 }
 
-/// Update the Pages dictionary to include all new pages in the Kids array
-fn update_pages_dictionary(doc: &mut Document, additional_page_ids: &[(u32, u16)]) -> Result<()> {
-    if additional_page_ids.is_empty() {
-        return Ok(());
+/// Page every sheet of one side starts from
+struct SheetSide {
+    /// Base page number of the sheet background, or 0 for a blank sheet
+    number: u32,
+    dict: Dictionary,
+    geometry: PageGeometry,
+}
+
+/// Blank sheet of the given size
+fn blank_sheet(width: f64, height: f64) -> SheetSide {
+    let visible_box = [0.0, 0.0, width, height];
+    let media_box: Vec<Object> = visible_box.iter().map(|v| Object::Real(*v as f32)).collect();
+    let mut dict = Dictionary::new();
+    dict.set("Type", "Page");
+    dict.set("MediaBox", media_box);
+    SheetSide { number: 0, dict, geometry: PageGeometry { visible_box, rotate: 0 } }
+}
+
+/// Sheets of an N-up layout, each holding the rows of one grid of cells
+struct Imposition<'a> {
+    layout: SheetLayout,
+    print: &'a PrintSetup,
+    config: &'a PlaceConfig,
+    base_pages: &'a [BasePage],
+    fonts: &'a FontRefs,
+    front: SheetSide,
+    /// Sheet for the back sides, when the configuration has a back
+    back: Option<SheetSide>,
+    /// Cell template Form XObjects by base page number
+    templates: HashMap<u32, ObjectId>,
+}
+
+impl<'a> Imposition<'a> {
+    /// Set up the sheets for a layout
+    ///
+    /// With a sheet background every sheet is a copy of the first front page
+    /// (and the back page for back sides). With cell templates the sheets are
+    /// blank pages of the configured size.
+    fn new(
+        layout: SheetLayout,
+        print: &'a PrintSetup,
+        base_pages: &'a [BasePage],
+        config: &'a PlaceConfig,
+        fonts: &'a FontRefs,
+    ) -> Result<Self> {
+        let back_page = config.back.as_ref()
+            .and_then(|back| back.page)
+            .map(|page| &base_pages[page as usize - 1]);
+        let (mut front, mut back) = match layout.base {
+            LayoutBase::Sheet => {
+                if config.settings.template_page_column.is_some() {
+                    return Err(anyhow!(
+                        "template_page_column needs a layout with \"base\": \"cell\""
+                    ));
+                }
+                let first_front_page = front_pages(config, base_pages.len())[0];
+                let base_page = &base_pages[first_front_page as usize - 1];
+                let geometry = base_page.geometry;
+                if let Some((width, height)) = layout.sheet_size
+                    && ((geometry.width() - width).abs() > 1.0 || (geometry.height() - height).abs() > 1.0) {
+                        eprintln!(
+                            "Warning: base.pdf page {} is {:.1} x {:.1} pt, but the label sheet is {:.1} x {:.1} pt",
+                            base_page.number, geometry.width(), geometry.height(), width, height
+                        );
+                    }
+                let back = config.back.as_ref().map(|_| {
+                    let back_page = back_page.cloned().unwrap_or_else(|| blank_page_like(&geometry));
+                    SheetSide { number: back_page.number, dict: back_page.dict, geometry: back_page.geometry }
+                });
+                (SheetSide { number: base_page.number, dict: base_page.dict.clone(), geometry }, back)
+            }
+            LayoutBase::Cell => {
+                let Some((width, height)) = layout.sheet_size else {
+                    return Err(anyhow!(
+                        "Layout with \"base\": \"cell\" needs sheet_width and sheet_height (or a label sheet preset)"
+                    ));
+                };
+                let back = config.back.as_ref().map(|_| blank_sheet(width, height));
+                (blank_sheet(width, height), back)
+            }
+        };
+        layout.grid.check_fits(front.geometry.width(), front.geometry.height())?;
+        if let Some(marks) = &print.marks {
+            for side in std::iter::once(&mut front).chain(back.as_mut()) {
+                marks.set_page_boxes(&mut side.dict, side.geometry.visible_box);
+            }
+        }
+        Ok(Self {
+            layout,
+            print,
+            config,
+            base_pages,
+            fonts,
+            front,
+            back,
+            templates: HashMap::new(),
+        })
+    }
+
+    /// Cell template XObject for a base page, added on first use
+    fn template(&mut self, doc: &mut Document, base_page: &BasePage) -> Result<ObjectId> {
+        if let Some(id) = self.templates.get(&base_page.number) {
+            return Ok(*id);
+        }
+        let id = create_template_xobject(doc, &base_page.dict, &base_page.geometry)
+            .with_context(|| format!("Failed to use base page {} as a cell template", base_page.number))?;
+        self.templates.insert(base_page.number, id);
+        Ok(id)
+    }
+
+    /// Base page drawn as the cell template for a row on one side
+    ///
+    /// Fronts use the row's template page (the first front page without
+    /// `template_page_column`); backs use the back page, if any.
+    fn cell_template_page(&self, side: Side, row_index: usize, row: &DataRow) -> Result<Option<&'a BasePage>> {
+        let page_count = self.base_pages.len();
+        let number = match side {
+            Side::Front => Some(pages_for_row(row_index, row, self.config, page_count)?[0]),
+            Side::Back => self.config.back.as_ref().and_then(|back| back.page),
+        };
+        Ok(number.map(|number| &self.base_pages[number as usize - 1]))
+    }
+
+    /// Create one side of a sheet with `rows` (row index and row) in
+    /// consecutive cells from `first_cell`
+    ///
+    /// Back sides mirror the cell positions horizontally when `back.mirror` is set.
+    fn create_sheet(
+        &mut self,
+        output_doc: &mut Document,
+        side: Side,
+        first_cell: usize,
+        rows: &[(usize, &DataRow)],
+        cache: &mut XObjectCache,
+    ) -> Result<ObjectId> {
+        let sheet = match side {
+            Side::Front => &self.front,
+            Side::Back => self.back.as_ref().ok_or_else(|| anyhow!("No back side configured"))?,
+        };
+        let page_id = output_doc.add_object(Object::Dictionary(sheet.dict.clone()));
+        let (sheet_number, geometry) = (sheet.number, sheet.geometry);
+        let (sheet_width, sheet_height) = (geometry.width(), geometry.height());
+        let mirror = side == Side::Back && self.config.back.as_ref().is_some_and(|back| back.mirror);
+        let mut builder = self.fonts.create_content_builder();
+
+        for (cell, (row_index, row)) in rows.iter().enumerate() {
+            // Move the cell's top-left corner to the sheet's top-left corner,
+            // so fields are positioned relative to their cell
+            let (mut left, top) = self.layout.grid.cell_origin(first_cell + cell);
+            if mirror {
+                left = sheet_width - left - self.layout.grid.cell_width;
+            }
+            builder.content_parts.push(format!("q 1 0 0 1 {} {} cm ", left, -top));
+
+            let page_number = match self.layout.base {
+                LayoutBase::Sheet => sheet_number,
+                LayoutBase::Cell => match self.cell_template_page(side, *row_index, row)? {
+                    Some(base_page) => {
+                        let template_id = self.template(output_doc, base_page)?;
+                        let name = builder.resource_name(format!("Tpl{}", base_page.number));
+                        builder.xobjects.set(name.clone(), Object::Reference(template_id));
+                        builder.content_parts.push(format!(
+                            "q 1 0 0 1 0 {} cm /{} Do Q ",
+                            sheet_height - base_page.geometry.height(),
+                            name
+                        ));
+                        base_page.number
+                    }
+                    None => 0,
+                },
+            };
+            let fields = fields_for_side(self.config, side, page_number, self.base_pages.len());
+            populate_content_builder(&mut builder, row, &fields, sheet_height, output_doc, cache)?;
+            builder.content_parts.push("Q ".to_string());
+        }
+
+        // Crop marks in line with every cell edge, outside the sheet
+        if let Some(marks) = &self.print.marks {
+            let (mut cuts_x, cuts_y) = self.layout.grid.cut_positions(sheet_height);
+            if mirror {
+                cuts_x = cuts_x.iter().rev().map(|x| sheet_width - x).collect();
+            }
+            builder.add_print_marks(marks, sheet_width, sheet_height, &cuts_x, &cuts_y);
+        }
+
+        let overlay_bytes = self.print.place_overlay(&geometry, builder.build_content_bytes());
+        output_doc.add_page_contents(page_id, overlay_bytes)?;
+        update_page_resources_with_fonts(
+            output_doc,
+            page_id,
+            &self.fonts.resources,
+            &builder.xobjects,
+            &builder.ext_gstates,
+            &builder.color_spaces,
+        )?;
+        Ok(page_id)
+    }
+}
+
+/// Object IDs of the nodes below the Pages node `root`: intermediate Pages
+/// nodes and pages
+fn page_tree_nodes(doc: &Document, root: ObjectId) -> Vec<ObjectId> {
+    let mut nodes = Vec::new();
+    let mut seen = HashSet::from([root]);
+    let mut pending = vec![root];
+    while let Some(id) = pending.pop() {
+        let Ok(kids) = doc.get_dictionary(id).and_then(|node| node.get(b"Kids")).and_then(Object::as_array) else {
+            continue;
+        };
+        for kid in kids.iter().filter_map(|kid| kid.as_reference().ok()) {
+            if seen.insert(kid) {
+                nodes.push(kid);
+                pending.push(kid);
+            }
+        }
     }
+    nodes
+}
 
+/// Make `page_ids` the pages of the document, in order
+///
+/// The pages become direct kids of the root Pages node. The page tree they
+/// replace, the base pages and any intermediate Pages nodes, is removed.
+fn set_document_pages(doc: &mut Document, page_ids: &[(u32, u16)]) -> Result<()> {
     let pages = doc.catalog()?.get(b"Pages")
         .with_context(|| "Failed to get Pages from catalog")?;
 
     let pages_id = pages.as_reference()
         .with_context(|| "Pages is not a reference")?;
 
-    // Get current kids array before mutable borrow
-    let current_kids = doc.get_dictionary(pages_id)
-        .ok()
-        .and_then(|d| d.get(b"Kids").ok())
-        .and_then(|k| k.as_array().ok())
-        .cloned()
-        .unwrap_or_default();
-
-    let mut new_kids = current_kids;
-    for page_id in additional_page_ids {
-        new_kids.push(Object::Reference(*page_id));
+    for id in page_tree_nodes(doc, pages_id) {
+        if !page_ids.contains(&id) {
+            doc.objects.remove(&id);
+        }
     }
-    let new_count = new_kids.len();
 
-    if let Ok(pages_mut) = doc.get_dictionary_mut(pages_id) {
-        pages_mut.set("Kids", new_kids);
-        pages_mut.set("Count", new_count as i64);
+    for page_id in page_ids {
+        doc.get_dictionary_mut(*page_id)?.set("Parent", Object::Reference(pages_id));
     }
 
+    let kids: Vec<Object> = page_ids.iter().map(|id| Object::Reference(*id)).collect();
+    let pages_mut = doc.get_dictionary_mut(pages_id)
+        .with_context(|| "Pages is not a dictionary")?;
+    pages_mut.set("Kids", kids);
+    pages_mut.set("Count", page_ids.len() as i64);
+
     Ok(())
 }
 
+/// Size of the blank base page used without base.pdf
+///
+/// This is `settings.page_size`, or else the sheet (or, with cell templates,
+/// the cell) of an N-up layout.
+fn blank_page_size(config: &PlaceConfig, layout: Option<&SheetLayout>) -> Result<(f64, f64)> {
+    if let Some(page_size) = &config.settings.page_size {
+        return Ok(page_size.oriented(config.settings.orientation));
+    }
+    match layout {
+        Some(layout) if layout.base == LayoutBase::Cell => {
+            Ok((layout.grid.cell_width, layout.grid.cell_height))
+        }
+        Some(SheetLayout { sheet_size: Some(size), .. }) => Ok(*size),
+        _ => Err(anyhow!(
+            "base.pdf not found: set settings.page_size (or a label sheet preset) to print on blank pages"
+        )),
+    }
+}
+
+/// Document with a single blank page of the given size, used in place of base.pdf
+fn blank_document(width: f64, height: f64) -> Document {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let media_box: Vec<Object> = [0.0, 0.0, width, height].iter().map(|v| Object::Real(*v as f32)).collect();
+
+    let mut page = Dictionary::new();
+    page.set("Type", "Page");
+    page.set("Parent", pages_id);
+    page.set("MediaBox", media_box);
+    page.set("Resources", Dictionary::new());
+    let page_id = doc.add_object(page);
+
+    let mut pages = Dictionary::new();
+    pages.set("Type", "Pages");
+    pages.set("Kids", vec![Object::Reference(page_id)]);
+    pages.set("Count", 1);
+    doc.objects.insert(pages_id, Object::Dictionary(pages));
+
+    let mut catalog = Dictionary::new();
+    catalog.set("Type", "Catalog");
+    catalog.set("Pages", pages_id);
+    let catalog_id = doc.add_object(catalog);
+    doc.trailer.set("Root", catalog_id);
+    doc
+}
+
 /// Create the output PDF with all data rows
+///
+/// Each row gets a copy of every base page, or of the single base page named
+/// by its `template_page_column` value. With a layout, rows instead fill the
+/// cells of N-up sheets. Without a base document, blank pages of
+/// `settings.page_size` are used.
 pub fn create_output_pdf(
-    base_doc: &Document,
+    base_doc: Option<&Document>,
     data_rows: &[DataRow],
     config: &PlaceConfig,
 ) -> Result<Document> {
+    let sheet_layout = SheetLayout::from_settings(&config.settings)?;
+
+    // Without base.pdf every row starts from a blank page
+    let blank_doc;
+    let base_doc = match base_doc {
+        // Without rows the base document is left as it is
+        Some(base_doc) if data_rows.is_empty() => return Ok(base_doc.clone()),
+        Some(base_doc) => {
+            if config.settings.page_size.is_some() {
+                eprintln!("Warning: settings.page_size is ignored because base.pdf is used");
+            }
+            base_doc
+        }
+        None => {
+            // A blank document without rows would have no pages at all
+            if data_rows.is_empty() {
+                return Err(anyhow!("data.csv contains no rows"));
+            }
+            let (width, height) = blank_page_size(config, sheet_layout.as_ref())?;
+            blank_doc = blank_document(width, height);
+            &blank_doc
+        }
+    };
+
     // Clone the base document to preserve all its content
     let mut output_doc = base_doc.clone();
 
-    // Get the first page from base document
-    let base_page_id = *base_doc.get_pages().iter().next()
-        .ok_or_else(|| anyhow!("No pages in base.pdf"))?.1;
-
-    let base_page = base_doc.get_object(base_page_id)?.as_dict()?;
+    // Fields are placed on the visible page as displayed; content is
+    // generated in display space and mapped onto each page by its geometry
+    let base_pages = load_base_pages(base_doc)?;
+    for (field_name, spec) in &config.fields {
+        if let Some(page) = spec.page
+            && (page == 0 || page as usize > base_pages.len()) {
+                return Err(anyhow!(
+                    "Field '{}' is on page {}, but base.pdf has {} pages",
+                    field_name, page, base_pages.len()
+                ));
+            }
+    }
+    if let Some(page) = config.back.as_ref().and_then(|back| back.page)
+        && (page == 0 || page as usize > base_pages.len()) {
+            return Err(anyhow!("Back page {} does not exist (base.pdf has {} pages)", page, base_pages.len()));
+        }
+    let sides: &[Side] = if config.back.is_some() { &[Side::Front, Side::Back] } else { &[Side::Front] };
 
-    // Get page dimensions from base page
-    let media_box = base_page.get(b"MediaBox")?.as_array()?;
-    let page_height = (media_box[3].as_float()? - media_box[1].as_float()?) as f64;
+    // Generated resources are merged into the base pages' resources, so
+    // their names must not replace the ones the base content uses
+    let reserved_names: HashSet<String> = base_pages.iter()
+        .filter_map(|page| page.dict.get(b"Resources").ok())
+        .flat_map(|resources| resource_names(base_doc, resources))
+        .collect();
 
     // Determine the fonts to use
-    // Always create a regular font for ASCII text
-    let regular_font = StandardFont::from_name("Helvetica").unwrap_or(StandardFont::Helvetica);
-    let (regular_font_id, regular_font_name) = create_font(&mut output_doc, regular_font)?;
-
-    // Create a CID font if non-ASCII text is detected
-    let (cid_font_id, cid_font_name) = if should_use_cid_font(data_rows, config) {
-        if let Some((font_data, font_name)) = find_cid_font(config.settings.font.as_deref()) {
-            let (fid, fname) = embed_cid_font(&mut output_doc, &font_data, &font_name)
-                .with_context(|| "Failed to embed CID font")?;
-            (Some(fid), Some(fname))
-        } else {
-            return Err(anyhow!(
-                "Non-ASCII characters detected in data, but no suitable CID font found on the system.\n\
-                Please install a CJK font package (e.g., fonts-noto-cjk on Debian/Ubuntu, \
-                noto-fonts-cjk on Arch, or similar packages on other distributions)."
-            ));
-        }
-    } else {
-        (None, None)
+    let mut registry = FontRegistry::new(&config.base_dir);
+    registry.reserve_resource_names(&reserved_names);
+    let text_fonts = prepare_text_fonts(&mut output_doc, &mut registry, data_rows, config)?;
+    let fonts = FontRefs {
+        text_fonts: Rc::new(text_fonts),
+        resources: registry.resources(),
+        reserved_names: Rc::new(reserved_names),
     };
 
-    let fonts = FontRefs {
-        regular_id: regular_font_id,
-        regular_name: regular_font_name,
-        cid_id: cid_font_id,
-        cid_name: cid_font_name,
+    let print = PrintSetup {
+        marks: config.settings.marks.as_ref()
+            .map(PrintMarks::from_section)
+            .transpose()?,
+        calibration: Calibration::from_profile(&config.settings.calibration)?,
     };
 
-    // Create additional pages for each row (beyond the first)
-    let mut additional_page_ids = Vec::new();
+    // Symbol XObjects shared by all pages, so repeated values are stored once
+    let mut xobject_cache = XObjectCache::new();
 
-    for row in data_rows.iter().skip(1) {
-        let page_id = create_page_for_row(
-            &mut output_doc,
-            base_page,
-            row,
-            config,
-            page_height,
-            &fonts,
-        )?;
-        additional_page_ids.push(page_id);
+    let mut page_ids = Vec::new();
+    if let Some(layout) = sheet_layout {
+        let mut imposition = Imposition::new(layout, &print, &base_pages, config, &fonts)?;
+        let rows: Vec<(usize, &DataRow)> = data_rows.iter().enumerate().collect();
+        for (sheet, sheet_rows) in layout.sheet_chunks(&rows).into_iter().enumerate() {
+            let first_cell = if sheet == 0 { layout.first_cell } else { 0 };
+            for side in sides {
+                let page_id = imposition.create_sheet(
+                    &mut output_doc,
+                    *side,
+                    first_cell,
+                    sheet_rows,
+                    &mut xobject_cache,
+                )?;
+                page_ids.push(page_id);
+            }
+        }
+    } else {
+        let back_fields = fields_for_side(config, Side::Back, 0, base_pages.len());
+        let back_page = config.back.as_ref()
+            .and_then(|back| back.page)
+            .map(|page| &base_pages[page as usize - 1]);
+        for (row_index, row) in data_rows.iter().enumerate() {
+            for page_number in pages_for_row(row_index, row, config, base_pages.len())? {
+                let base_page = &base_pages[page_number as usize - 1];
+                let fields = fields_for_side(config, Side::Front, page_number, base_pages.len());
+                let page_id = create_page_for_row(
+                    &mut output_doc,
+                    base_page,
+                    &fields,
+                    row,
+                    &fonts,
+                    &print,
+                    &mut xobject_cache,
+                )?;
+                page_ids.push(page_id);
+
+                // The back follows its front, so duplex printing pairs them
+                if config.back.is_some() {
+                    let blank_back;
+                    let back_page = match back_page {
+                        Some(back_page) => back_page,
+                        None => {
+                            blank_back = blank_page_like(&base_page.geometry);
+                            &blank_back
+                        }
+                    };
+                    let page_id = create_page_for_row(
+                        &mut output_doc,
+                        back_page,
+                        &back_fields,
+                        row,
+                        &fonts,
+                        &print,
+                        &mut xobject_cache,
+                    )?;
+                    page_ids.push(page_id);
+                }
+            }
+        }
     }
 
-    // Add content to the first page (base page) for the first row
-    if let Some(first_row) = data_rows.first() {
-        let mut builder = fonts.create_content_builder();
+    set_document_pages(&mut output_doc, &page_ids)?;
 
-        // Populate builder with content from the first row
-        populate_content_builder(&mut builder, first_row, config, page_height, &mut output_doc)?;
+    Ok(output_doc)
+}
 
-        // Append new content to the base page
-        let new_content = builder.build_content_bytes();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pdf::fonts::{FontProgram, PdfFont, PdfFontKind, StandardFont};
+    use crate::config::{BackSection, Dimension, FieldSpec, LayoutSection, MarksSection, Orientation, PageSize, SettingsSection};
+    use lopdf::dictionary;
+    use std::path::PathBuf;
 
-        // Add new content to the first page
-        let first_page_id = *output_doc.get_pages().values().next()
-            .ok_or_else(|| anyhow!("No pages"))?;
+    /// Base document with `page_count` empty A4 pages
+    fn base_doc(page_count: usize) -> Document {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let kids: Vec<Object> = (0..page_count)
+            .map(|_| doc.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id }).into())
+            .collect();
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => page_count as i64,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+        doc
+    }
 
-        output_doc.add_page_contents(first_page_id, new_content)?;
+    fn text_field(page: Option<u32>) -> FieldSpec {
+        FieldSpec {
+            w: Dimension(100.0),
+            h: Dimension(20.0),
+            output_type: "Text".to_string(),
+            page,
+            ..Default::default()
+        }
+    }
 
-        // Update the first page's resources
-        update_page_resources_with_fonts(
-            &mut output_doc,
-            first_page_id,
-            fonts.regular_id,
-            &fonts.regular_name,
-            fonts.cid_id,
-            fonts.cid_name.as_deref(),
-            &builder.xobjects,
-        );
+    fn config(fields: Vec<(&str, FieldSpec)>, template_page_column: Option<&str>) -> PlaceConfig {
+        PlaceConfig {
+            fields: fields.into_iter().map(|(name, spec)| (name.to_string(), spec)).collect(),
+            back: None,
+            settings: SettingsSection {
+                template_page_column: template_page_column.map(str::to_string),
+                ..Default::default()
+            },
+            base_dir: PathBuf::from("."),
+        }
     }
 
-    // Update the pages dictionary to include all new pages
-    update_pages_dictionary(&mut output_doc, &additional_page_ids)?;
+    fn row(values: &[(&str, &str)]) -> DataRow {
+        DataRow {
+            data: values.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        }
+    }
 
-    Ok(output_doc)
+    /// Overlay content of every output page
+    fn page_contents(doc: &Document) -> Vec<String> {
+        doc.get_pages()
+            .values()
+            .map(|id| String::from_utf8_lossy(&doc.get_page_content(*id).unwrap()).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_all_base_pages_per_row() {
+        let config = config(vec![("Name", text_field(None)), ("Notes", text_field(Some(2)))], None);
+        let rows = [row(&[("Name", "A"), ("Notes", "n1")]), row(&[("Name", "B"), ("Notes", "n2")])];
+
+        let output = create_output_pdf(Some(&base_doc(2)), &rows, &config).unwrap();
+
+        let contents = page_contents(&output);
+        assert_eq!(contents.len(), 4);
+        assert!(contents[0].contains("(A)") && !contents[0].contains("(n1)"));
+        assert!(contents[1].contains("(n1)") && !contents[1].contains("(A)"));
+        assert!(contents[2].contains("(B)"));
+        assert!(contents[3].contains("(n2)"));
+        // Pages inherit the MediaBox through the copied attributes
+        let first = output.get_dictionary(*output.get_pages().get(&1).unwrap()).unwrap();
+        assert!(first.has(b"MediaBox"));
+    }
+
+    #[test]
+    fn test_replaced_page_tree_is_removed() {
+        // Two base pages below an intermediate Pages node
+        let mut base = base_doc(2);
+        let root_id = base.catalog().unwrap().get(b"Pages").unwrap().as_reference().unwrap();
+        let base_pages: Vec<ObjectId> = base.get_pages().into_values().collect();
+        let kids: Vec<Object> = base_pages.iter().map(|id| Object::Reference(*id)).collect();
+        let middle_id = base.add_object(dictionary! {
+            "Type" => "Pages",
+            "Parent" => root_id,
+            "Kids" => kids,
+            "Count" => 2,
+        });
+        for id in &base_pages {
+            base.get_dictionary_mut(*id).unwrap().set("Parent", middle_id);
+        }
+        base.get_dictionary_mut(root_id).unwrap().set("Kids", vec![Object::Reference(middle_id)]);
+
+        let config = config(vec![("Name", text_field(None))], None);
+        let output = create_output_pdf(Some(&base), &names(1), &config).unwrap();
+        assert_eq!(output.get_pages().len(), 2);
+        assert!(!output.objects.contains_key(&middle_id));
+        assert!(base_pages.iter().all(|id| !output.objects.contains_key(id)));
+    }
+
+    #[test]
+    fn test_template_page_column() {
+        let config = config(vec![("Name", text_field(None)), ("Vip", text_field(Some(2)))], Some("Class"));
+        let rows = [
+            row(&[("Name", "A"), ("Class", "2"), ("Vip", "VIP")]),
+            row(&[("Name", "B"), ("Class", "1"), ("Vip", "VIP")]),
+        ];
+
+        let output = create_output_pdf(Some(&base_doc(2)), &rows, &config).unwrap();
+
+        let contents = page_contents(&output);
+        assert_eq!(contents.len(), 2);
+        assert!(contents[0].contains("(A)") && contents[0].contains("(VIP)"));
+        assert!(contents[1].contains("(B)") && !contents[1].contains("(VIP)"));
+    }
+
+    #[test]
+    fn test_invalid_pages() {
+        let config_with_column = config(vec![("Name", text_field(None))], Some("Class"));
+        let rows = [row(&[("Name", "A"), ("Class", "3")])];
+        let err = create_output_pdf(Some(&base_doc(2)), &rows, &config_with_column).err().unwrap();
+        assert!(err.to_string().contains("template page 3 does not exist"));
+
+        let config_with_page = config(vec![("Name", text_field(Some(3)))], None);
+        let rows = [row(&[("Name", "A")])];
+        assert!(create_output_pdf(Some(&base_doc(2)), &rows, &config_with_page).is_err());
+    }
+
+    fn layout(base: LayoutBase) -> LayoutSection {
+        LayoutSection {
+            columns: Some(2),
+            rows: Some(2),
+            cell_width: Some(Dimension(200.0)),
+            cell_height: Some(Dimension(100.0)),
+            gutter_x: Some(Dimension(10.0)),
+            margin_left: Some(Dimension(20.0)),
+            margin_top: Some(Dimension(40.0)),
+            base,
+            sheet_width: Some(Dimension(500.0)),
+            sheet_height: Some(Dimension(300.0)),
+            ..Default::default()
+        }
+    }
+
+    fn names(count: usize) -> Vec<DataRow> {
+        (0..count).map(|i| row(&[("Name", &format!("R{}", i))])).collect()
+    }
+
+    #[test]
+    fn test_layout_on_sheet_background() {
+        let mut config = config(vec![("Name", text_field(None))], None);
+        config.settings.layout = Some(layout(LayoutBase::Sheet));
+
+        let output = create_output_pdf(Some(&base_doc(1)), &names(5), &config).unwrap();
+
+        let contents = page_contents(&output);
+        assert_eq!(contents.len(), 2);
+        assert!(["(R0)", "(R1)", "(R2)", "(R3)"].iter().all(|name| contents[0].contains(name)));
+        assert!(contents[1].contains("(R4)") && !contents[1].contains("(R3)"));
+        // Second cell of the first row, fourth cell in the second row
+        assert!(contents[0].contains("q 1 0 0 1 230 -40 cm "));
+        assert!(contents[0].contains("q 1 0 0 1 230 -140 cm "));
+        // Sheets are copies of the base page
+        let sheet = output.get_dictionary(*output.get_pages().get(&1).unwrap()).unwrap();
+        assert!(sheet.has(b"MediaBox"));
+    }
+
+    #[test]
+    fn test_layout_with_cell_template() {
+        let mut config = config(vec![("Name", text_field(None))], None);
+        config.settings.layout = Some(layout(LayoutBase::Cell));
+
+        let output = create_output_pdf(Some(&base_doc(1)), &names(3), &config).unwrap();
+
+        let pages = output.get_pages();
+        assert_eq!(pages.len(), 1);
+        let contents = page_contents(&output);
+        assert_eq!(contents[0].matches("/Tpl1 Do").count(), 3);
+        // The A4 template's top edge meets the top of the cell
+        assert!(contents[0].contains("q 1 0 0 1 0 -542 cm /Tpl1 Do Q "));
+        let sheet = output.get_dictionary(*pages.get(&1).unwrap()).unwrap();
+        let xobjects = sheet.get(b"Resources").unwrap().as_dict().unwrap()
+            .get(b"XObject").unwrap().as_dict().unwrap();
+        assert!(xobjects.has(b"Tpl1"));
+    }
+
+    #[test]
+    fn test_invalid_layouts() {
+        let mut config = config(vec![("Name", text_field(None))], Some("Class"));
+        config.settings.layout = Some(layout(LayoutBase::Sheet));
+        assert!(create_output_pdf(Some(&base_doc(1)), &names(1), &config).is_err());
+
+        let mut missing_size = layout(LayoutBase::Cell);
+        missing_size.sheet_height = None;
+        config.settings.template_page_column = None;
+        config.settings.layout = Some(missing_size);
+        assert!(create_output_pdf(Some(&base_doc(1)), &names(1), &config).is_err());
+
+        let mut too_wide = layout(LayoutBase::Cell);
+        too_wide.columns = Some(3);
+        config.settings.layout = Some(too_wide);
+        let err = create_output_pdf(Some(&base_doc(1)), &names(1), &config).err().unwrap();
+        assert!(err.to_string().contains("does not fit"));
+    }
+
+    #[test]
+    fn test_sheet_preset_with_start_at() {
+        let mut config = config(vec![("Name", text_field(None))], None);
+        config.settings.sheet = Some("avery-5163".to_string());
+        config.settings.start_at = Some(9);
+        config.settings.layout = Some(LayoutSection { base: LayoutBase::Cell, ..Default::default() });
+
+        let output = create_output_pdf(Some(&base_doc(1)), &names(3), &config).unwrap();
+
+        // Avery 5163 has 10 cells, so rows 0 and 1 fill cells 9 and 10
+        let contents = page_contents(&output);
+        assert_eq!(contents.len(), 2);
+        assert_eq!(contents[0].matches("/Tpl1 Do").count(), 2);
+        assert!(contents[0].contains("q 1 0 0 1 11.25 -612 cm "));
+        assert!(contents[1].contains("(R2)"));
+        let sheet = output.get_dictionary(*output.get_pages().get(&1).unwrap()).unwrap();
+        let media_box: Vec<f32> = sheet.get(b"MediaBox").unwrap().as_array().unwrap()
+            .iter()
+            .map(|v| v.as_float().unwrap())
+            .collect();
+        assert_eq!(media_box, [0.0, 0.0, 612.0, 792.0]);
+    }
+
+    #[test]
+    fn test_blank_pages_without_base_pdf() {
+        let mut config = config(vec![("Name", text_field(None))], None);
+        config.settings.page_size = Some(PageSize { width: 200.0, height: 100.0 });
+        config.settings.orientation = Some(Orientation::Portrait);
+
+        let output = create_output_pdf(None, &names(2), &config).unwrap();
+
+        let pages = output.get_pages();
+        assert_eq!(pages.len(), 2);
+        let geometry = PageGeometry::from_page(&output, *pages.get(&2).unwrap()).unwrap();
+        assert_eq!(geometry.visible_box, [0.0, 0.0, 100.0, 200.0]);
+        assert!(page_contents(&output)[1].contains("(R1)"));
+    }
+
+    #[test]
+    fn test_blank_sheets_from_preset() {
+        let mut config = config(vec![("Name", text_field(None))], None);
+        config.settings.sheet = Some("avery-5160".to_string());
+
+        let output = create_output_pdf(None, &names(2), &config).unwrap();
+
+        let page_id = *output.get_pages().get(&1).unwrap();
+        let geometry = PageGeometry::from_page(&output, page_id).unwrap();
+        assert_eq!(geometry.visible_box, [0.0, 0.0, 612.0, 792.0]);
+    }
+
+    #[test]
+    fn test_blank_pages_need_a_size() {
+        let config = config(vec![("Name", text_field(None))], None);
+        let err = create_output_pdf(None, &names(1), &config).unwrap_err();
+        assert!(err.to_string().contains("page_size"));
+    }
+
+    #[test]
+    fn test_no_rows() {
+        let config = config(vec![("Name", text_field(None))], None);
+        // The base document comes back unchanged
+        let output = create_output_pdf(Some(&base_doc(2)), &names(0), &config).unwrap();
+        assert_eq!(output.get_pages().len(), 2);
+        // Blank pages need at least one row
+        assert!(create_output_pdf(None, &names(0), &config).is_err());
+    }
+
+    #[test]
+    fn test_generated_names_avoid_base_resources() {
+        let mut doc = base_doc(1);
+        let page_id = *doc.get_pages().get(&1).unwrap();
+        doc.get_dictionary_mut(page_id).unwrap().set("Resources", dictionary! {
+            "Font" => dictionary! { "Helvetica" => Object::Reference((900, 0)) },
+            "ExtGState" => dictionary! { "GS500" => Object::Reference((901, 0)) },
+        });
+        let mut spec = text_field(None);
+        spec.opacity = Some(0.5);
+        let config = config(vec![("Name", spec)], None);
+
+        let output = create_output_pdf(Some(&doc), &names(1), &config).unwrap();
+
+        let contents = page_contents(&output);
+        assert!(contents[0].contains("/GS500-2 gs") && contents[0].contains("/Helvetica-2 "));
+        let page = output.get_dictionary(*output.get_pages().get(&1).unwrap()).unwrap();
+        let resources = page.get(b"Resources").unwrap().as_dict().unwrap();
+        let font = resources.get(b"Font").unwrap().as_dict().unwrap();
+        assert_eq!(font.get(b"Helvetica").unwrap().as_reference().unwrap(), (900, 0));
+        assert!(font.has(b"Helvetica-2"));
+        let states = resources.get(b"ExtGState").unwrap().as_dict().unwrap();
+        assert_eq!(states.get(b"GS500").unwrap().as_reference().unwrap(), (901, 0));
+        assert!(states.get(b"GS500-2").unwrap().as_dict().is_ok());
+    }
+
+    #[test]
+    fn test_used_chars_of_embedded_fonts_only() {
+        let standard = PdfFont {
+            id: (1, 0),
+            resource_name: "F1".to_string(),
+            kind: PdfFontKind::Standard(StandardFont::Helvetica),
+        };
+        let embedded = PdfFont {
+            id: (2, 0),
+            resource_name: "F2".to_string(),
+            kind: PdfFontKind::Embedded(Rc::new(FontProgram::new(Vec::new()))),
+        };
+        let fonts = TextFonts {
+            default: standard,
+            named: HashMap::new(),
+            fallback: Some(embedded),
+            vertical: HashMap::new(),
+        };
+        let config = config(vec![("Name", text_field(None)), ("City", text_field(None))], None);
+        let rows = [row(&[("Name", "Bob"), ("City", "東京")]), row(&[("Name", "Ann"), ("City", "京都")])];
+
+        // ASCII names are drawn with the standard font and not collected
+        let used = used_chars(&config, &rows, &fonts);
+        assert_eq!(used.len(), 1);
+        assert_eq!(used[&(2, 0)], BTreeSet::from(['東', '京', '都']));
+    }
+
+    #[test]
+    fn test_marks_enlarge_pages() {
+        let mut config = config(vec![("Name", text_field(None))], None);
+        config.settings.marks = Some(MarksSection {
+            bleed: Some(Dimension(10.0)),
+            mark_offset: Some(Dimension(10.0)),
+            mark_length: Some(Dimension(20.0)),
+            ..Default::default()
+        });
+
+        let output = create_output_pdf(Some(&base_doc(1)), &names(1), &config).unwrap();
+
+        let page_id = *output.get_pages().get(&1).unwrap();
+        let page = output.get_dictionary(page_id).unwrap();
+        let rect = |key: &[u8]| -> Vec<f32> {
+            page.get(key).unwrap().as_array().unwrap().iter().map(|v| v.as_float().unwrap()).collect()
+        };
+        assert_eq!(rect(b"MediaBox"), [-30.0, -30.0, 625.0, 872.0]);
+        assert_eq!(rect(b"BleedBox"), [-10.0, -10.0, 605.0, 852.0]);
+        assert_eq!(rect(b"TrimBox"), [0.0, 0.0, 595.0, 842.0]);
+        // Fields keep their place relative to the trimmed page
+        let geometry = PageGeometry::from_page(&output, page_id).unwrap();
+        assert_eq!(geometry.visible_box, [-30.0, -30.0, 625.0, 872.0]);
+        let contents = page_contents(&output);
+        assert!(contents[0].contains("(R0)"));
+        assert!(contents[0].contains("0 -10 m 0 -30 l "));
+    }
+
+    #[test]
+    fn test_marks_per_cell() {
+        let mut config = config(vec![("Name", text_field(None))], None);
+        config.settings.layout = Some(layout(LayoutBase::Cell));
+        config.settings.marks = Some(MarksSection::default());
+
+        let output = create_output_pdf(Some(&base_doc(1)), &names(1), &config).unwrap();
+
+        // Cut lines at x = 20, 220, 230 and 430 are marked below and above the sheet
+        let contents = page_contents(&output);
+        for x in [20, 220, 230, 430] {
+            assert!(contents[0].contains(&format!("{} -8.5", x)), "cut at {}", x);
+        }
+    }
+
+    fn back(page: Option<u32>, mirror: bool) -> BackSection {
+        BackSection {
+            fields: [("Code".to_string(), text_field(None))].into_iter().collect(),
+            page,
+            mirror,
+        }
+    }
+
+    #[test]
+    fn test_back_follows_every_front() {
+        let mut config = config(vec![("Name", text_field(None))], None);
+        config.back = Some(back(Some(2), false));
+        let rows = [row(&[("Name", "A"), ("Code", "a")]), row(&[("Name", "B"), ("Code", "b")])];
+
+        let output = create_output_pdf(Some(&base_doc(2)), &rows, &config).unwrap();
+
+        // Page 2 of base.pdf is the back, so each row is one front and one back
+        let contents = page_contents(&output);
+        assert_eq!(contents.len(), 4);
+        assert!(contents[0].contains("(A)") && !contents[0].contains("(a)"));
+        assert!(contents[1].contains("(a)") && !contents[1].contains("(A)"));
+        assert!(contents[2].contains("(B)"));
+        assert!(contents[3].contains("(b)"));
+    }
+
+    #[test]
+    fn test_blank_back() {
+        let mut config = config(vec![("Name", text_field(None))], None);
+        config.back = Some(back(None, false));
+
+        let output = create_output_pdf(Some(&base_doc(1)), &[row(&[("Name", "A"), ("Code", "a")])], &config).unwrap();
+
+        let pages = output.get_pages();
+        assert_eq!(pages.len(), 2);
+        let geometry = PageGeometry::from_page(&output, *pages.get(&2).unwrap()).unwrap();
+        assert_eq!(geometry.visible_box, [0.0, 0.0, 595.0, 842.0]);
+        assert!(page_contents(&output)[1].contains("(a)"));
+    }
+
+    #[test]
+    fn test_front_pages_skip_the_back_page() {
+        let mut config = config(vec![], None);
+        config.back = Some(back(Some(1), false));
+        assert_eq!(front_pages(&config, 3), vec![2, 3]);
+        // A single page is used for both sides
+        assert_eq!(front_pages(&config, 1), vec![1]);
+
+        config.back = Some(back(Some(3), false));
+        assert!(create_output_pdf(Some(&base_doc(2)), &names(1), &config).is_err());
+    }
+
+    #[test]
+    fn test_template_page_cannot_be_the_back_page() {
+        let mut config = config(vec![("Name", text_field(None))], Some("Class"));
+        config.back = Some(back(Some(2), false));
+        let rows = [row(&[("Name", "A"), ("Class", "1")]), row(&[("Name", "B"), ("Class", "2")])];
+        let err = create_output_pdf(Some(&base_doc(2)), &rows, &config).err().unwrap();
+        assert!(err.to_string().contains("Row 2: template page 2 is the back page"));
+    }
+
+    #[test]
+    fn test_mirrored_back_sheets() {
+        let mut config = config(vec![("Name", text_field(None))], None);
+        config.settings.layout = Some(layout(LayoutBase::Cell));
+        config.back = Some(back(None, true));
+        let rows: Vec<DataRow> = (0..5)
+            .map(|i| row(&[("Name", &format!("R{}", i)), ("Code", &format!("C{}", i))]))
+            .collect();
+
+        let output = create_output_pdf(Some(&base_doc(1)), &rows, &config).unwrap();
+
+        // Front, back, front, back
+        let contents = page_contents(&output);
+        assert_eq!(contents.len(), 4);
+        assert!(contents[1].contains("(C0)") && !contents[1].contains("(R0)"));
+        assert!(contents[3].contains("(C4)"));
+        // The first cell (left 20) is mirrored to the right: 500 - 20 - 200
+        assert!(contents[0].contains("q 1 0 0 1 20 -40 cm "));
+        assert!(contents[1].contains("q 1 0 0 1 280 -40 cm "));
+        assert!(contents[1].contains("q 1 0 0 1 70 -40 cm "));
+        // A back without a base page has no cell template
+        assert!(!contents[1].contains("Do"));
+    }
+
+    #[test]
+    fn test_calibration_moves_overlay() {
+        let mut config = config(vec![("Name", text_field(None))], None);
+        config.settings.calibration.offset_x = Some(Dimension(5.0));
+        config.settings.calibration.scale_y = Some(0.5);
+
+        let output = create_output_pdf(Some(&base_doc(1)), &names(1), &config).unwrap();
+
+        let contents = page_contents(&output);
+        assert!(contents[0].contains("q 1 0 0 0.5 5 421 cm "));
+        assert!(contents[0].contains("(R0)"));
+
+        config.settings.calibration.scale_y = Some(-1.0);
+        assert!(create_output_pdf(Some(&base_doc(1)), &names(1), &config).is_err());
+    }
 }
@@ -0,0 +1,37 @@
+//! Extended Channel Interpretation (ECI) of symbol data.
+//!
+//! Data Matrix, Aztec, PDF417 and rMQR readers take the bytes of a symbol as
+//! ISO/IEC 8859-1 (Latin-1) unless an ECI designator in the data says
+//! otherwise. This module converts text to those bytes, naming the ECI to
+//! announce when Latin-1 is not enough.
+
+/// ECI assignment number of UTF-8
+pub const UTF8: u32 = 26;
+
+/// Bytes stored in a symbol for `data`, and the ECI they are read in
+///
+/// Text within Latin-1 is stored one byte per character with no ECI, as
+/// readers expect by default. Other text is stored as UTF-8 behind ECI 26.
+pub fn symbol_bytes(data: &str) -> (Vec<u8>, Option<u32>) {
+    let latin1: Option<Vec<u8>> = data.chars()
+        .map(|c| u8::try_from(u32::from(c)).ok())
+        .collect();
+    match latin1 {
+        Some(bytes) => (bytes, None),
+        None => (data.as_bytes().to_vec(), Some(UTF8)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbol_bytes() {
+        assert_eq!(symbol_bytes("AB1"), (b"AB1".to_vec(), None));
+        assert_eq!(symbol_bytes("café"), (b"caf\xE9".to_vec(), None));
+        assert_eq!(symbol_bytes("東京"), ("東京".as_bytes().to_vec(), Some(UTF8)));
+        // One character outside Latin-1 puts all of the text in UTF-8
+        assert_eq!(symbol_bytes("é€"), ("é€".as_bytes().to_vec(), Some(UTF8)));
+    }
+}
@@ -1,18 +1,25 @@
 //! Font management and embedding for PDF documents.
 //!
 //! This module provides functionality for:
-//! - Standard PDF Type1 fonts (Helvetica, Times, Courier)
-//! - TrueType font embedding
+//! - Standard PDF Type1 fonts (Helvetica, Times, Courier, Symbol, ZapfDingbats)
+//! - TrueType/OpenType font embedding from system fonts or font files
 //! - CID-keyed fonts for CJK character support
 //! - System font discovery and loading
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use lopdf::{Dictionary, Document, Object, Stream, StringFormat};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
+use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use fontdb::Database;
 use ttf_parser::Face;
 
+use super::content::escape_pdf_string;
+use super::standard_metrics;
+
 /// Standard PDF Type1 fonts
 #[derive(Debug, Clone, Copy)]
 pub enum StandardFont {
@@ -28,6 +35,8 @@ pub enum StandardFont {
     CourierBold,
     CourierOblique,
     CourierBoldOblique,
+    Symbol,
+    ZapfDingbats,
 }
 
 impl StandardFont {
@@ -46,6 +55,32 @@ impl StandardFont {
             StandardFont::CourierBold => "Courier-Bold",
             StandardFont::CourierOblique => "Courier-Oblique",
             StandardFont::CourierBoldOblique => "Courier-BoldOblique",
+            StandardFont::Symbol => "Symbol",
+            StandardFont::ZapfDingbats => "ZapfDingbats",
+        }
+    }
+
+    /// Width of a character in 1000-unit glyph space
+    pub fn char_width(&self, c: char) -> u16 {
+        // StandardEncoding maps ' and ` to the curly quote glyphs
+        match self {
+            StandardFont::Helvetica | StandardFont::HelveticaOblique => {
+                standard_metrics::table_width(&standard_metrics::HELVETICA, c)
+            }
+            StandardFont::HelveticaBold | StandardFont::HelveticaBoldOblique => {
+                standard_metrics::table_width(&standard_metrics::HELVETICA_BOLD, c)
+            }
+            StandardFont::TimesRoman => standard_metrics::table_width(&standard_metrics::TIMES_ROMAN, c),
+            StandardFont::TimesBold => standard_metrics::table_width(&standard_metrics::TIMES_BOLD, c),
+            StandardFont::TimesItalic => standard_metrics::table_width(&standard_metrics::TIMES_ITALIC, c),
+            StandardFont::TimesBoldItalic => {
+                standard_metrics::table_width(&standard_metrics::TIMES_BOLD_ITALIC, c)
+            }
+            StandardFont::Courier
+            | StandardFont::CourierBold
+            | StandardFont::CourierOblique
+            | StandardFont::CourierBoldOblique => standard_metrics::COURIER_WIDTH,
+            StandardFont::Symbol | StandardFont::ZapfDingbats => standard_metrics::SYMBOLIC_WIDTH,
         }
     }
 
@@ -65,6 +100,8 @@ impl StandardFont {
             "courier-bold" => Some(StandardFont::CourierBold),
             "courier-oblique" => Some(StandardFont::CourierOblique),
             "courier-boldoblique" => Some(StandardFont::CourierBoldOblique),
+            "symbol" => Some(StandardFont::Symbol),
+            "zapfdingbats" => Some(StandardFont::ZapfDingbats),
             _ => None,
         }
     }
@@ -88,127 +125,376 @@ pub fn create_font(doc: &mut Document, font: StandardFont) -> Result<((u32, u16)
     Ok((font_id, base_font_name))
 }
 
-/// Embed a TrueType font in the PDF document
-///
-/// This allows using custom fonts like "Meiryo UI"
-#[allow(dead_code)]
-pub fn create_true_type_font(
-    doc: &mut Document,
-    font_path: &Path,
-    font_name: &str,
-) -> Result<((u32, u16), String)> {
-    let font_data = fs::read(font_path)
-        .with_context(|| format!("Failed to read font file: {:?}", font_path))?;
+/// Font descriptor flag: all glyphs have the same width
+const FLAG_FIXED_PITCH: i64 = 1;
+/// Font descriptor flag: glyphs outside the standard Latin set
+const FLAG_SYMBOLIC: i64 = 1 << 2;
+/// Font descriptor flag: glyphs are slanted
+const FLAG_ITALIC: i64 = 1 << 6;
 
-    embed_true_type_font_data(doc, &font_data, font_name)
+/// Scale a value in font units to the 1000-unit glyph space used by PDF
+fn to_pdf_units(face: &Face, value: f32) -> i64 {
+    (value * 1000.0 / face.units_per_em() as f32).round() as i64
 }
 
-/// Create a basic font descriptor with default values
-fn create_font_descriptor(font_name: &str) -> Dictionary {
+/// Create a font descriptor with the metrics read from the font
+fn create_font_descriptor(font_name: &str, face: &Face) -> Dictionary {
+    let mut flags = FLAG_SYMBOLIC;
+    if face.is_monospaced() {
+        flags |= FLAG_FIXED_PITCH;
+    }
+    if face.is_italic() || face.italic_angle() != 0.0 {
+        flags |= FLAG_ITALIC;
+    }
+
+    let bbox = face.global_bounding_box();
+    let ascent = to_pdf_units(face, face.ascender() as f32);
+    let descent = to_pdf_units(face, face.descender() as f32);
+    let cap_height = face.capital_height()
+        .map_or(ascent, |h| to_pdf_units(face, h as f32));
+
+    // No font table records the stem width; estimate it from the weight class
+    let weight = face.weight().to_number() as f64;
+    let stem_v = (50.0 + (weight / 65.0).powi(2)).round() as i64;
+
     let mut font_descriptor = Dictionary::new();
     font_descriptor.set("Type", "FontDescriptor");
     font_descriptor.set("FontName", font_name);
-    font_descriptor.set("Flags", 4i64); // Symbolic
-    font_descriptor.set("FontBBox", vec![0i64, 0i64, 1000i64, 1000i64].into_iter().map(Object::Integer).collect::<Vec<_>>());
-    font_descriptor.set("ItalicAngle", 0i64);
-    font_descriptor.set("Ascent", 1000i64);
-    font_descriptor.set("Descent", -200i64);
-    font_descriptor.set("CapHeight", 700i64);
-    font_descriptor.set("StemV", 80i64);
+    font_descriptor.set("Flags", flags);
+    font_descriptor.set("FontBBox", vec![
+        to_pdf_units(face, bbox.x_min as f32),
+        to_pdf_units(face, bbox.y_min as f32),
+        to_pdf_units(face, bbox.x_max as f32),
+        to_pdf_units(face, bbox.y_max as f32),
+    ].into_iter().map(Object::Integer).collect::<Vec<_>>());
+    font_descriptor.set("ItalicAngle", Object::Real(face.italic_angle()));
+    font_descriptor.set("Ascent", ascent);
+    font_descriptor.set("Descent", descent);
+    font_descriptor.set("CapHeight", cap_height);
+    if let Some(x_height) = face.x_height() {
+        font_descriptor.set("XHeight", to_pdf_units(face, x_height as f32));
+    }
+    font_descriptor.set("StemV", stem_v);
     font_descriptor
 }
 
+/// Build the CIDFont /W array from (glyph ID, width) pairs sorted by glyph ID
+///
+/// Consecutive glyph IDs share one entry: `[first [w1 w2 ...] ...]`
+fn build_widths_array(widths: &[(u16, i64)]) -> Vec<Object> {
+    let mut array = Vec::new();
+    let mut run: Vec<Object> = Vec::new();
+    let mut run_start = 0u16;
+    let mut previous: Option<u16> = None;
+
+    for &(gid, width) in widths {
+        if previous.is_some_and(|p| p + 1 != gid) {
+            array.push(Object::Integer(run_start as i64));
+            array.push(Object::Array(std::mem::take(&mut run)));
+        }
+        if run.is_empty() {
+            run_start = gid;
+        }
+        run.push(Object::Integer(width));
+        previous = Some(gid);
+    }
+    if !run.is_empty() {
+        array.push(Object::Integer(run_start as i64));
+        array.push(Object::Array(run));
+    }
+    array
+}
+
+/// Build a CIDFont `/W2` array from `(glyph ID, w1y, vx, vy)` vertical metrics
+///
+/// Each glyph gets a `c c w1y vx vy` range entry.
+fn build_vertical_metrics_array(metrics: &[(u16, i64, i64, i64)]) -> Vec<Object> {
+    metrics
+        .iter()
+        .flat_map(|&(gid, w1y, vx, vy)| {
+            [gid as i64, gid as i64, w1y, vx, vy].map(Object::Integer)
+        })
+        .collect()
+}
+
+/// Vertical advance and vertical origin of a glyph, in font units
+///
+/// The origin is the point glyphs hang from in vertical writing: horizontally
+/// centred, at the top of the glyph's vertical advance. It comes from the
+/// `VORG` table, or from `vmtx` side bearings; fonts without vertical metrics
+/// use the ascender and the full line height.
+fn vertical_metrics(face: &Face, gid: ttf_parser::GlyphId) -> (f32, f32, f32) {
+    let advance = vertical_advance(face, gid);
+    let origin_x = face.glyph_hor_advance(gid).unwrap_or(0) as f32 / 2.0;
+    let origin_y = face.glyph_y_origin(gid)
+        .map(f32::from)
+        .or_else(|| {
+            let side_bearing = face.glyph_ver_side_bearing(gid)?;
+            let bbox = face.glyph_bounding_box(gid)?;
+            Some(side_bearing as f32 + bbox.y_max as f32)
+        })
+        .unwrap_or(face.ascender() as f32);
+    (advance, origin_x, origin_y)
+}
+
+/// Vertical advance of a glyph in font units, or the line height for fonts
+/// without vertical metrics
+fn vertical_advance(face: &Face, gid: ttf_parser::GlyphId) -> f32 {
+    let line_height = (face.ascender() as i32 - face.descender() as i32) as f32;
+    face.glyph_ver_advance(gid).map_or(line_height, f32::from)
+}
+
+/// Indices of the GSUB lookups of the `vert` feature
+fn vertical_lookup_indices(face: &Face) -> BTreeSet<u16> {
+    let Some(gsub) = face.tables().gsub else {
+        return BTreeSet::new();
+    };
+    // Every script and language has its own `vert` feature record, usually
+    // pointing at the same lookups
+    gsub.features
+        .into_iter()
+        .filter(|feature| feature.tag == ttf_parser::Tag::from_bytes(b"vert"))
+        .flat_map(|feature| feature.lookup_indices)
+        .collect()
+}
+
+/// Replace a glyph with its vertical form from the GSUB `vert` feature
+///
+/// Only single substitutions are applied, which covers the rotated
+/// punctuation, brackets and small kana vertical forms CJK fonts provide.
+fn vertical_glyph(face: &Face, gid: ttf_parser::GlyphId) -> ttf_parser::GlyphId {
+    apply_vertical_lookups(face, &vertical_lookup_indices(face), gid)
+}
+
+/// Apply the `vert` lookups `lookup_indices` to a glyph
+fn apply_vertical_lookups(face: &Face, lookup_indices: &BTreeSet<u16>, gid: ttf_parser::GlyphId) -> ttf_parser::GlyphId {
+    use ttf_parser::gsub::{SingleSubstitution, SubstitutionSubtable};
+
+    let Some(gsub) = face.tables().gsub else {
+        return gid;
+    };
+    let mut glyph = gid;
+    for &index in lookup_indices {
+        let Some(lookup) = gsub.lookups.get(index) else {
+            continue;
+        };
+        for subtable in lookup.subtables.into_iter::<SubstitutionSubtable>() {
+            let SubstitutionSubtable::Single(single) = subtable else {
+                continue;
+            };
+            let Some(coverage_index) = single.coverage().get(glyph) else {
+                continue;
+            };
+            glyph = match single {
+                SingleSubstitution::Format1 { delta, .. } => {
+                    ttf_parser::GlyphId(glyph.0.wrapping_add(delta as u16))
+                }
+                SingleSubstitution::Format2 { substitutes, .. } => {
+                    substitutes.get(coverage_index).unwrap_or(glyph)
+                }
+            };
+            break;
+        }
+    }
+    glyph
+}
+
+/// CID of every glyph of a CID-keyed CFF font, indexed by glyph ID
+///
+/// Returns `None` for TrueType and name-keyed CFF fonts, whose glyphs are
+/// selected by glyph ID.
+fn glyph_cids(face: &Face) -> Option<Vec<u16>> {
+    let cff = face.tables().cff?;
+    cff.glyph_cid(ttf_parser::GlyphId(0))?;
+    Some((0..face.number_of_glyphs())
+        .map(|gid| cff.glyph_cid(ttf_parser::GlyphId(gid)).unwrap_or(0))
+        .collect())
+}
+
+/// Character code that selects glyph `gid`: its CID when the font has a
+/// GID-to-CID table, otherwise the glyph ID itself
+fn glyph_code(cids: Option<&[u16]>, gid: u16) -> u16 {
+    cids.map_or(gid, |cids| cids.get(gid as usize).copied().unwrap_or(0))
+}
+
+/// Glyph drawn for `c`, in its vertical form when `vertical` is set
+fn glyph_for(face: &Face, c: char, vertical: bool) -> Option<ttf_parser::GlyphId> {
+    let gid = face.glyph_index(c)?;
+    Some(if vertical { vertical_glyph(face, gid) } else { gid })
+}
+
+/// PDF version that introduced OpenType FontFile3 streams
+const OPENTYPE_FONT_FILE_VERSION: &str = "1.6";
+
+/// Raise the document's PDF version to at least `version`
+fn require_pdf_version(doc: &mut Document, version: &str) {
+    let parse = |v: &str| -> Option<(u32, u32)> {
+        let (major, minor) = v.split_once('.')?;
+        Some((major.parse().ok()?, minor.parse().ok()?))
+    };
+    if parse(&doc.version) < parse(version) {
+        doc.version = version.to_string();
+    }
+}
+
 /// Embed font data and create a font stream in the document
-fn embed_font_stream(doc: &mut Document, font_data: &[u8], descriptor_id: (u32, u16)) -> Result<()> {
+///
+/// TrueType outlines go into a FontFile2 stream; fonts with CFF outlines go
+/// into an OpenType FontFile3 stream, which needs PDF 1.6 or later.
+fn embed_font_stream(
+    doc: &mut Document,
+    font_data: &[u8],
+    descriptor_id: (u32, u16),
+    has_cff_outlines: bool,
+) -> Result<()> {
     let mut font_stream_dict = Dictionary::new();
-    font_stream_dict.set("Length1", font_data.len() as i64);
+    if has_cff_outlines {
+        font_stream_dict.set("Subtype", "OpenType");
+        require_pdf_version(doc, OPENTYPE_FONT_FILE_VERSION);
+    } else {
+        font_stream_dict.set("Length1", font_data.len() as i64);
+    }
 
-    let font_stream = Stream::new(font_stream_dict, font_data.to_vec());
+    let mut font_stream = Stream::new(font_stream_dict, font_data.to_vec());
+    font_stream.compress()?;
     let font_stream_id = doc.add_object(font_stream);
 
     // Set the font file in the descriptor
+    let font_file_key = if has_cff_outlines { "FontFile3" } else { "FontFile2" };
     if let Ok(descriptor) = doc.get_dictionary_mut(descriptor_id) {
-        descriptor.set("FontFile2", Object::Reference(font_stream_id));
+        descriptor.set(font_file_key, Object::Reference(font_stream_id));
     }
 
     Ok(())
 }
 
-/// Embed a TrueType font from raw data
+/// Glyph IDs needed to draw `used_chars`, always including .notdef
 ///
-/// This allows using custom fonts loaded from memory
-#[allow(dead_code)]
-pub fn embed_true_type_font_data(
-    doc: &mut Document,
-    font_data: &[u8],
-    font_name: &str,
-) -> Result<((u32, u16), String)> {
-    // Create font dictionary
-    let mut font_dict = Dictionary::new();
-    font_dict.set("Type", "Font");
-    font_dict.set("Subtype", "TrueType");
-    font_dict.set("BaseFont", font_name);
+/// With `vertical` set, the vertical forms of the characters are kept as well.
+fn used_glyph_ids(face: &Face, used_chars: &BTreeSet<char>, vertical: bool) -> Vec<u16> {
+    let mut gids: BTreeSet<u16> = used_chars
+        .iter()
+        .filter_map(|&c| face.glyph_index(c))
+        .map(|g| g.0)
+        .collect();
+    if vertical {
+        gids.extend(used_chars.iter().filter_map(|&c| glyph_for(face, c, true)).map(|g| g.0));
+    }
+    gids.insert(0);
+    gids.into_iter().collect()
+}
 
-    // Create and embed font descriptor
-    let font_descriptor = create_font_descriptor(font_name);
-    let descriptor_id = doc.add_object(Object::Dictionary(font_descriptor));
-    font_dict.set("FontDescriptor", Object::Reference(descriptor_id));
+/// Map each glyph drawn for `used_chars` back to the character it was drawn for
+fn glyph_to_unicode(face: &Face, used_chars: &BTreeSet<char>, vertical: bool) -> BTreeMap<u16, char> {
+    let mut mappings = BTreeMap::new();
+    for &c in used_chars {
+        if let Some(gid) = face.glyph_index(c) {
+            mappings.entry(gid.0).or_insert(c);
+        }
+        if vertical && let Some(gid) = glyph_for(face, c, true) {
+            mappings.entry(gid.0).or_insert(c);
+        }
+    }
+    mappings
+}
 
-    // Embed the font program
-    embed_font_stream(doc, font_data, descriptor_id)?;
+/// Build a ToUnicode CMap mapping 2-byte character codes back to Unicode
+///
+/// This lets viewers extract, search and read aloud text drawn with the font.
+fn build_to_unicode_cmap(mappings: &BTreeMap<u16, char>) -> Vec<u8> {
+    let mut cmap = String::from(
+        "/CIDInit /ProcSet findresource begin\n\
+         12 dict begin\n\
+         begincmap\n\
+         /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+         /CMapName /Adobe-Identity-UCS def\n\
+         /CMapType 2 def\n\
+         1 begincodespacerange\n\
+         <0000> <FFFF>\n\
+         endcodespacerange\n",
+    );
 
-    let font_id = doc.add_object(Object::Dictionary(font_dict));
+    // A bfchar section may hold at most 100 entries
+    let entries: Vec<(&u16, &char)> = mappings.iter().collect();
+    for chunk in entries.chunks(100) {
+        cmap.push_str(&format!("{} beginbfchar\n", chunk.len()));
+        for (code, c) in chunk {
+            let mut utf16 = [0u16; 2];
+            let unicode: String = c
+                .encode_utf16(&mut utf16)
+                .iter()
+                .map(|unit| format!("{:04X}", unit))
+                .collect();
+            cmap.push_str(&format!("<{:04X}> <{}>\n", code, unicode));
+        }
+        cmap.push_str("endbfchar\n");
+    }
 
-    Ok((font_id, font_name.to_string()))
+    cmap.push_str(
+        "endcmap\n\
+         CMapName currentdict /CMap defineresource pop\n\
+         end\n\
+         end\n",
+    );
+    cmap.into_bytes()
 }
 
-/// Build a CIDToGIDMap stream from font's cmap table
-///
-/// For TrueType fonts where glyphs aren't arranged by Unicode order,
-/// we need to create a mapping from CID (character ID, which is Unicode in Identity-H)
-/// to GID (glyph ID in the font file)
-fn build_cidtogid_map(font_data: &[u8]) -> Option<Vec<u8>> {
-    // Parse the font to get the cmap
-    let face = Face::parse(font_data, 0).ok()?;
-    
-    // Build a mapping from Unicode codepoints to glyph IDs
-    // We'll create a format 2 CIDToGIDMap (simple array format)
-    // For each CID (0 to max), store the corresponding GID as a 2-byte big-endian value
-    
-    // Find the maximum codepoint we need to map (we'll map up to 0xFFFF for BMP)
-    const MAX_CID: u16 = 0xFFFF;
-    let mut gid_map: Vec<u8> = Vec::with_capacity((MAX_CID as usize + 1) * 2);
-    
-    for cid in 0..=MAX_CID {
-        // Try to get the glyph ID for this Unicode codepoint
-        // Skip invalid Unicode codepoints (surrogates, etc.)
-        let gid = if let Some(ch) = char::from_u32(cid as u32) {
-            face.glyph_index(ch).map(|g| g.0).unwrap_or(0)
-        } else {
-            0  // Use GID 0 (.notdef) for invalid codepoints
-        };
-        
-        // Write GID as big-endian u16
-        gid_map.push((gid >> 8) as u8);
-        gid_map.push((gid & 0xFF) as u8);
-    }
-    
-    Some(gid_map)
+/// Six-letter subset tag prefixed to the font name, as required for subset fonts
+fn subset_tag(font_name: &str, glyph_ids: &[u16]) -> String {
+    let mut hasher = DefaultHasher::new();
+    font_name.hash(&mut hasher);
+    glyph_ids.hash(&mut hasher);
+    let mut hash = hasher.finish();
+    (0..6)
+        .map(|_| {
+            let letter = (b'A' + (hash % 26) as u8) as char;
+            hash /= 26;
+            letter
+        })
+        .collect()
 }
 
 /// Embed a CID-keyed font for CJK characters
 ///
-/// This creates a Type0 font with a CIDFont descendant for proper CJK rendering
-pub fn embed_cid_font(
+/// This creates a Type0 font with a CIDFont descendant for proper CJK rendering.
+/// Text is encoded as glyph IDs (see `FontProgram::encode_text`). The font
+/// program is subset to the glyphs needed for `used_chars`, and a ToUnicode
+/// CMap covering those characters is attached. With `vertical` set, the
+/// vertical glyph forms and `/W2` vertical metrics are included too, so the
+/// descendant font can also be used through an `Identity-V` Type0 font.
+/// Returns the (`Identity-H`) Type0 font dictionary; the caller adds it to the document.
+pub fn build_cid_font(
     doc: &mut Document,
     font_data: &[u8],
     font_name: &str,
-) -> Result<((u32, u16), String)> {
+    used_chars: &BTreeSet<char>,
+    vertical: bool,
+) -> Result<Dictionary> {
+    let face = Face::parse(font_data, 0)
+        .map_err(|e| anyhow!("Failed to parse font '{}': {}", font_name, e))?;
+
+    // Keep only the outlines of the glyphs we draw
+    let glyph_ids = used_glyph_ids(&face, used_chars, vertical);
+    let subset_data = subsetter::subset(font_data, 0, subsetter::Profile::pdf(&glyph_ids))
+        .map_err(|e| anyhow!("Failed to subset font '{}': {}", font_name, e))?;
+    let subset_name = format!("{}+{}", subset_tag(font_name, &glyph_ids), font_name.replace(' ', ""));
+
     // Create CIDFont dictionary
+    // Character codes are CIDs: glyph IDs, except in CID-keyed CFF fonts whose
+    // charset maps glyphs to their own CIDs. Every glyph in the font is
+    // reachable (including characters outside the Basic Multilingual Plane).
+    let cids = glyph_cids(&face);
+    let code = |gid: u16| glyph_code(cids.as_deref(), gid);
+    let has_cff_outlines = face.tables().cff.is_some();
     let mut cid_font = Dictionary::new();
     cid_font.set("Type", "Font");
-    cid_font.set("Subtype", "CIDFontType2"); // TrueType-based CID font
-    cid_font.set("BaseFont", font_name);
+    if has_cff_outlines {
+        cid_font.set("Subtype", "CIDFontType0"); // CFF-based CID font
+    } else {
+        cid_font.set("Subtype", "CIDFontType2"); // TrueType-based CID font
+        cid_font.set("CIDToGIDMap", "Identity");
+    }
+    cid_font.set("BaseFont", subset_name.as_str());
     cid_font.set("CIDSystemInfo", {
         let mut cid_system = Dictionary::new();
         cid_system.set("Registry", Object::String("Adobe".into(), StringFormat::Literal));
@@ -216,26 +502,44 @@ pub fn embed_cid_font(
         cid_system.set("Supplement", 0i64);
         Object::Dictionary(cid_system)
     });
-    
-    // Build and embed CIDToGIDMap stream for proper glyph mapping
-    // This maps Unicode codepoints (CIDs) to font glyph IDs (GIDs)
-    if let Some(cidtogid_data) = build_cidtogid_map(font_data) {
-        let cidtogid_stream = Stream::new(Dictionary::new(), cidtogid_data);
-        let cidtogid_id = doc.add_object(cidtogid_stream);
-        cid_font.set("CIDToGIDMap", Object::Reference(cidtogid_id));
-    } else {
-        // Fallback to Identity if we can't build the map
-        // This will work for fonts where glyphs are arranged by Unicode order
-        cid_font.set("CIDToGIDMap", "Identity");
+
+    // Advance widths of the glyphs we draw, in 1000-unit glyph space
+    let mut widths: Vec<(u16, i64)> = glyph_ids
+        .iter()
+        .map(|&gid| {
+            let advance = face.glyph_hor_advance(ttf_parser::GlyphId(gid)).unwrap_or(0);
+            (code(gid), to_pdf_units(&face, advance as f32))
+        })
+        .collect();
+    widths.sort_unstable();
+    cid_font.set("DW", 1000i64);
+    cid_font.set("W", build_widths_array(&widths));
+
+    if vertical {
+        // Vertical displacement (negative, downwards) and origin of each glyph
+        let mut metrics: Vec<(u16, i64, i64, i64)> = glyph_ids
+            .iter()
+            .map(|&gid| {
+                let (advance, origin_x, origin_y) = vertical_metrics(&face, ttf_parser::GlyphId(gid));
+                (
+                    code(gid),
+                    -to_pdf_units(&face, advance),
+                    to_pdf_units(&face, origin_x),
+                    to_pdf_units(&face, origin_y),
+                )
+            })
+            .collect();
+        metrics.sort_unstable();
+        cid_font.set("W2", build_vertical_metrics_array(&metrics));
     }
 
     // Create and embed font descriptor (using common helper)
-    let font_descriptor = create_font_descriptor(font_name);
+    let font_descriptor = create_font_descriptor(&subset_name, &face);
     let descriptor_id = doc.add_object(Object::Dictionary(font_descriptor));
     cid_font.set("FontDescriptor", Object::Reference(descriptor_id));
 
     // Embed the font program (using common helper)
-    embed_font_stream(doc, font_data, descriptor_id)?;
+    embed_font_stream(doc, &subset_data, descriptor_id, has_cff_outlines)?;
 
     let cid_font_id = doc.add_object(Object::Dictionary(cid_font));
 
@@ -243,79 +547,21 @@ pub fn embed_cid_font(
     let mut type0_font = Dictionary::new();
     type0_font.set("Type", "Font");
     type0_font.set("Subtype", "Type0");
-    type0_font.set("BaseFont", font_name);
-    type0_font.set("Encoding", "Identity-H"); // Use Identity-H encoding for UCS-2
+    type0_font.set("BaseFont", subset_name.as_str());
+    type0_font.set("Encoding", "Identity-H"); // 2-byte codes are CIDs
     type0_font.set("DescendantFonts", vec![Object::Reference(cid_font_id)].into_iter().collect::<Vec<_>>());
 
-    let type0_font_id = doc.add_object(Object::Dictionary(type0_font));
-
-    // Return a name that won't have spaces (for use in content stream)
-    let safe_font_name = font_name.replace(' ', "-");
-
-    Ok((type0_font_id, safe_font_name))
-}
-
-/// Find a font file in the system
-///
-/// Searches common font directories for the given font name
-#[allow(dead_code)]
-pub fn find_system_font(font_name: &str) -> Option<String> {
-    // Common font directories on different platforms
-    let font_dirs: Vec<PathBuf> = if cfg!(target_os = "macos") {
-        vec![
-            PathBuf::from("/System/Library/Fonts"),
-            PathBuf::from("/Library/Fonts"),
-            PathBuf::from(std::env::var("HOME").ok()?).join("Library/Fonts"),
-        ]
-    } else if cfg!(target_os = "windows") {
-        vec![
-            PathBuf::from("C:\\Windows\\Fonts"),
-        ]
-    } else if cfg!(target_os = "linux") {
-        vec![
-            PathBuf::from("/usr/share/fonts"),
-            PathBuf::from("/usr/local/share/fonts"),
-            PathBuf::from(std::env::var("HOME").ok()?).join(".fonts"),
-            PathBuf::from(std::env::var("HOME").ok()?).join(".local/share/fonts"),
-        ]
-    } else {
-        return None;
-    };
-
-    // Common font file extensions
-    let extensions = [".ttf", ".ttc", ".otf"];
-
-    // Try to find the font file
-    for font_dir in font_dirs {
-        if !font_dir.exists() {
-            continue;
-        }
-
-        // Try exact match with extensions
-        for ext in &extensions {
-            let font_path = font_dir.join(format!("{}{}", font_name, ext));
-            if font_path.exists() {
-                return font_path.to_str().map(String::from);
-            }
-        }
-
-        // Try case-insensitive search
-        if let Ok(entries) = fs::read_dir(&font_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.extension().and_then(|s| s.to_str()).is_some_and(|e| {
-                    extensions.contains(&e.to_lowercase().as_str())
-                }) {
-                    let stem = path.file_stem()?.to_str()?;
-                    if stem.eq_ignore_ascii_case(font_name) {
-                        return path.to_str().map(String::from);
-                    }
-                }
-            }
-        }
-    }
+    // Map the codes back to Unicode so the text can be searched and copied
+    let mappings = glyph_to_unicode(&face, used_chars, vertical)
+        .into_iter()
+        .map(|(gid, c)| (code(gid), c))
+        .collect();
+    let mut to_unicode_stream = Stream::new(Dictionary::new(), build_to_unicode_cmap(&mappings));
+    to_unicode_stream.compress()?;
+    let to_unicode_id = doc.add_object(to_unicode_stream);
+    type0_font.set("ToUnicode", Object::Reference(to_unicode_id));
 
-    None
+    Ok(type0_font)
 }
 
 /// Load system fonts into a fontdb database
@@ -344,16 +590,46 @@ fn load_system_fonts_into_db(db: &mut Database) {
     }
 }
 
+/// Load a system font family from the database
+///
+/// Returns the font data (extracted from a collection if needed)
+fn load_system_font(db: &Database, family: &str) -> Option<Vec<u8>> {
+    let family_ref = fontdb::Family::Name(family);
+    let query = fontdb::Query {
+        families: &[family_ref],
+        ..Default::default()
+    };
+
+    let id = db.query(&query)?;
+    let (source, index) = db.face_source(id)?;
+    let data = match source {
+        // Try to read the font file
+        fontdb::Source::File(path) => fs::read(&path).ok()?,
+        // Convert Arc to Vec
+        fontdb::Source::Binary(data) => data.as_ref().as_ref().to_vec(),
+        _ => return None,
+    };
+    standalone_face(data, index)
+}
+
+/// Turn font data into a standalone font for the face at `index`
+///
+/// Collections (.ttc/.otc) hold several faces sharing one file, e.g. the JP,
+/// KR and SC variants of Noto Sans CJK; the requested face is extracted so
+/// that glyph lookup, subsetting and embedding all use the same face.
+fn standalone_face(data: Vec<u8>, index: u32) -> Option<Vec<u8>> {
+    if data.starts_with(b"ttcf") {
+        extract_from_ttc(&data, index)
+    } else {
+        Some(data)
+    }
+}
+
 /// Find a CID font that supports Unicode text
 ///
 /// Searches for CJK fonts in the system that can render non-ASCII text.
 /// If preferred_font is provided, tries to use that font first.
-pub fn find_cid_font(preferred_font: Option<&str>) -> Option<(Vec<u8>, String)> {
-    let mut db = Database::new();
-
-    // Load system fonts using helper
-    load_system_fonts_into_db(&mut db);
-
+pub fn find_cid_font(db: &Database, preferred_font: Option<&str>) -> Option<(Vec<u8>, String)> {
     // Build font family list: preferred font first, then fallbacks
     let mut font_families = Vec::new();
 
@@ -363,73 +639,836 @@ pub fn find_cid_font(preferred_font: Option<&str>) -> Option<(Vec<u8>, String)>
     }
 
     // Common Japanese font family names to try as fallbacks
-    font_families.extend([
-        "Hiragino Kaku Gothic Pro",
-        "Hiragino Kaku Gothic ProN",
-        "Hiragino Sans",
-        "Hiragino Sans GB",
-        "Hiragino Mincho ProN",
-        "Noto Sans CJK JP",
-        "Noto Sans JP",
-        "Source Han Sans",
-        "IPA Gothic",
-        "IPA Mincho",
-        "Yu Gothic",
-        "Yu Mincho",
-        "Meiryo",
-        "MS Gothic",
-        "MS Mincho",
-    ]);
-
-    for family in &font_families {
-        let family_ref = fontdb::Family::Name(family);
-        let query = fontdb::Query {
-            families: &[family_ref],
-            ..Default::default()
-        };
+    font_families.extend(CID_FONT_FALLBACKS);
+
+    font_families
+        .into_iter()
+        .find_map(|family| load_system_font(db, family).map(|data| (data, family.to_string())))
+}
+
+/// Common Japanese font family names tried when no preferred CID font is available
+const CID_FONT_FALLBACKS: [&str; 15] = [
+    "Hiragino Kaku Gothic Pro",
+    "Hiragino Kaku Gothic ProN",
+    "Hiragino Sans",
+    "Hiragino Sans GB",
+    "Hiragino Mincho ProN",
+    "Noto Sans CJK JP",
+    "Noto Sans JP",
+    "Source Han Sans",
+    "IPA Gothic",
+    "IPA Mincho",
+    "Yu Gothic",
+    "Yu Mincho",
+    "Meiryo",
+    "MS Gothic",
+    "MS Mincho",
+];
+
+/// Split a `#N` face index off a font path, e.g. `fonts/NotoSansCJK.ttc#2`
+///
+/// Paths without an index select the first face.
+fn split_face_index(name: &str) -> (&str, Option<u32>) {
+    if let Some((path, index)) = name.rsplit_once('#')
+        && !path.is_empty()
+        && index.bytes().all(|b| b.is_ascii_digit())
+        && let Ok(index) = index.parse() {
+            return (path, Some(index));
+        }
+    (name, None)
+}
+
+/// Whether a font name refers to a font file rather than a family name
+fn is_font_path(name: &str) -> bool {
+    let has_font_extension = Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| matches!(e.to_lowercase().as_str(), "ttf" | "otf" | "ttc" | "otc"));
+    has_font_extension || name.contains('/') || name.contains('\\')
+}
+
+/// Turn a font name into a name usable as a resource key in content streams
+fn resource_name_for(font_name: &str) -> String {
+    font_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+        .collect()
+}
+
+/// Font program embedded in the output document
+///
+/// The font is parsed once when the program is created; the glyph lookups
+/// and advances that text layout and encoding need are kept in tables.
+#[derive(Debug)]
+pub struct FontProgram {
+    pub data: Vec<u8>,
+    /// Glyph tables, or `None` if the font cannot be parsed
+    glyphs: Option<GlyphTables>,
+}
 
-        if let Some(id) = db.query(&query)
-            && let Some((source, index)) = db.face_source(id) {
-                match source {
-                    fontdb::Source::File(path) => {
-                        // Try to read the font file
-                        if let Ok(data) = fs::read(&path) {
-                            // For TTC files, we need to extract the correct font
-                            if path.extension().and_then(|s| s.to_str()) == Some("ttc") {
-                                // Try to find the correct font in the collection
-                                if let Some(font_data) = extract_from_ttc(&data, index) {
-                                    return Some((font_data, family.to_string()));
-                                }
-                            } else {
-                                return Some((data, family.to_string()));
-                            }
+/// Per-character and per-glyph data read from a font
+#[derive(Debug, Default)]
+struct GlyphTables {
+    units_per_em: f64,
+    /// Glyph ID of every character in the font's Unicode cmap
+    glyphs: HashMap<char, u16>,
+    /// Vertical forms of the glyphs that have one
+    vertical_forms: HashMap<u16, u16>,
+    /// CID of each glyph of a CID-keyed CFF font
+    cids: Option<Vec<u16>>,
+    /// Horizontal advance of each glyph, in font units
+    advances: Vec<u16>,
+    /// Vertical advance of each glyph, in font units
+    vertical_advances: Vec<f32>,
+}
+
+impl GlyphTables {
+    fn new(face: &Face) -> Self {
+        // Same lookup order as `Face::glyph_index`: the first Unicode subtable
+        // that maps a character wins. Characters mapped to .notdef count as
+        // missing.
+        let mut glyphs = HashMap::new();
+        if let Some(cmap) = face.tables().cmap {
+            for subtable in cmap.subtables.into_iter().filter(|subtable| subtable.is_unicode()) {
+                subtable.codepoints(|code_point| {
+                    if let Some(c) = char::from_u32(code_point)
+                        && let Some(gid) = subtable.glyph_index(code_point)
+                        && gid.0 != 0 {
+                            glyphs.entry(c).or_insert(gid.0);
                         }
-                    }
-                    fontdb::Source::Binary(data) => {
-                        // Convert Arc to Vec
-                        let data_vec: Vec<u8> = data.as_ref().as_ref().to_vec();
-                        return Some((data_vec, family.to_string()));
-                    }
-                    _ => {}
-                }
+                });
+            }
+        }
+
+        let lookup_indices = vertical_lookup_indices(face);
+        let vertical_forms = glyphs.values()
+            .filter_map(|&gid| {
+                let vertical = apply_vertical_lookups(face, &lookup_indices, ttf_parser::GlyphId(gid)).0;
+                (vertical != gid).then_some((gid, vertical))
+            })
+            .collect();
+
+        let glyph_ids = (0..face.number_of_glyphs()).map(ttf_parser::GlyphId);
+        Self {
+            units_per_em: face.units_per_em() as f64,
+            glyphs,
+            vertical_forms,
+            cids: glyph_cids(face),
+            advances: glyph_ids.clone().map(|gid| face.glyph_hor_advance(gid).unwrap_or(0)).collect(),
+            vertical_advances: glyph_ids.map(|gid| vertical_advance(face, gid)).collect(),
+        }
+    }
+
+    /// Glyph drawn for `c`, in its vertical form when `vertical` is set
+    fn glyph(&self, c: char, vertical: bool) -> Option<u16> {
+        let gid = *self.glyphs.get(&c)?;
+        Some(if vertical { self.vertical_forms.get(&gid).copied().unwrap_or(gid) } else { gid })
+    }
+}
+
+impl FontProgram {
+    pub fn new(data: Vec<u8>) -> Self {
+        let glyphs = Face::parse(&data, 0).ok().map(|face| GlyphTables::new(&face));
+        Self { data, glyphs }
+    }
+
+    /// Whether the font has a glyph for every printable character of `text`
+    pub fn has_glyphs(&self, text: &str) -> bool {
+        let Some(tables) = &self.glyphs else {
+            return false;
+        };
+        text.chars()
+            .filter(|c| !c.is_control())
+            .all(|c| tables.glyphs.contains_key(&c))
+    }
+
+    /// Encode text as hex CIDs for an Identity-H (or, with `vertical` set,
+    /// Identity-V) composite font
+    ///
+    /// Vertical text uses the font's vertical glyph forms. Characters missing
+    /// from the font map to glyph 0 (.notdef).
+    pub fn encode_text(&self, text: &str, vertical: bool) -> String {
+        let Some(tables) = &self.glyphs else {
+            return "0000".repeat(text.chars().count());
+        };
+        text.chars()
+            .map(|c| {
+                let gid = tables.glyph(c, vertical).unwrap_or(0);
+                format!("{:04X}", glyph_code(tables.cids.as_deref(), gid))
+            })
+            .collect()
+    }
+
+    /// Advance width of `text` in 1000-unit glyph space
+    ///
+    /// Characters missing from the font use the width of glyph 0 (.notdef).
+    pub fn text_width(&self, text: &str) -> f64 {
+        let Some(tables) = &self.glyphs else {
+            return 0.0;
+        };
+        let advance: u32 = text.chars()
+            .map(|c| {
+                let gid = tables.glyph(c, false).unwrap_or(0);
+                tables.advances.get(gid as usize).copied().unwrap_or(0) as u32
+            })
+            .sum();
+        advance as f64 * 1000.0 / tables.units_per_em
+    }
+
+    /// Vertical advance of `text` in 1000-unit glyph space, using the
+    /// vertical glyph forms
+    pub fn text_height(&self, text: &str) -> f64 {
+        let Some(tables) = &self.glyphs else {
+            return 0.0;
+        };
+        let advance: f32 = text.chars()
+            .map(|c| {
+                let gid = tables.glyph(c, true).unwrap_or(0);
+                tables.vertical_advances.get(gid as usize).copied().unwrap_or(0.0)
+            })
+            .sum();
+        advance as f64 * 1000.0 / tables.units_per_em
+    }
+}
+
+#[cfg(test)]
+impl FontProgram {
+    /// Program mapping each character of `glyphs` to its glyph ID, every
+    /// glyph one em wide, for tests that need no font file
+    pub fn from_glyphs(glyphs: &[(char, u16)]) -> Self {
+        let glyph_count = glyphs.iter().map(|&(_, gid)| gid as usize + 1).max().unwrap_or(1);
+        Self {
+            data: Vec::new(),
+            glyphs: Some(GlyphTables {
+                units_per_em: 1000.0,
+                glyphs: glyphs.iter().copied().collect(),
+                vertical_forms: HashMap::new(),
+                cids: None,
+                advances: vec![1000; glyph_count],
+                vertical_advances: vec![1000.0; glyph_count],
+            }),
+        }
+    }
+}
+
+/// How text is encoded for a font in the content stream
+#[derive(Debug, Clone)]
+pub enum PdfFontKind {
+    /// One of the standard Type1 fonts, drawn with 1-byte character codes
+    Standard(StandardFont),
+    /// Embedded composite (Type0) font, drawn with 2-byte glyph IDs
+    Embedded(Rc<FontProgram>),
+    /// Embedded composite font drawn top to bottom (`Identity-V`)
+    Vertical(Rc<FontProgram>),
+}
+
+/// A font added to the output document
+#[derive(Debug, Clone)]
+pub struct PdfFont {
+    /// Font dictionary object ID
+    pub id: (u32, u16),
+    /// Name of the font in page resources (used with the `Tf` operator)
+    pub resource_name: String,
+    pub kind: PdfFontKind,
+}
+
+impl PdfFont {
+    /// Whether the font is a composite (Type0) font addressed with 2-byte codes
+    pub fn is_composite(&self) -> bool {
+        !matches!(self.kind, PdfFontKind::Standard(_))
+    }
+
+    /// Whether the font writes top to bottom
+    pub fn is_vertical(&self) -> bool {
+        matches!(self.kind, PdfFontKind::Vertical(_))
+    }
+
+    /// Whether every character of `text` can be drawn with this font
+    pub fn can_render(&self, text: &str) -> bool {
+        match &self.kind {
+            PdfFontKind::Embedded(program) | PdfFontKind::Vertical(program) => program.has_glyphs(text),
+            PdfFontKind::Standard(_) => text.is_ascii(),
+        }
+    }
+
+    /// Advance width of `text` in 1000-unit glyph space
+    pub fn text_width(&self, text: &str) -> f64 {
+        match &self.kind {
+            PdfFontKind::Embedded(program) | PdfFontKind::Vertical(program) => program.text_width(text),
+            PdfFontKind::Standard(font) => text.chars().map(|c| font.char_width(c) as f64).sum(),
+        }
+    }
+
+    /// Vertical advance of `text` in 1000-unit glyph space
+    ///
+    /// Fonts without vertical metrics stack their glyphs one em apart.
+    pub fn text_height(&self, text: &str) -> f64 {
+        match &self.kind {
+            PdfFontKind::Vertical(program) => program.text_height(text),
+            _ => text.chars().count() as f64 * 1000.0,
+        }
+    }
+
+    /// Encode `text` as a string operand for the `Tj` operator
+    pub fn encode_text(&self, text: &str) -> String {
+        match &self.kind {
+            PdfFontKind::Embedded(program) => format!("<{}>", program.encode_text(text, false)),
+            PdfFontKind::Vertical(program) => format!("<{}>", program.encode_text(text, true)),
+            PdfFontKind::Standard(_) => format!("({})", escape_pdf_string(text)),
+        }
+    }
+}
+
+/// Fonts added to an output document, keyed by the name used in settings.json
+///
+/// Font names are either one of the standard PDF fonts, a system font family
+/// or a path to a font file (relative to `base_dir`).
+///
+/// Embedded fonts get their object ID when first requested, but their font
+/// program is only written by `embed_programs` once the characters drawn with
+/// them are known, so each one can be subset.
+pub struct FontRegistry {
+    base_dir: PathBuf,
+    db: Option<Database>,
+    fonts: HashMap<String, PdfFont>,
+    resource_names: HashSet<String>,
+    pending: Vec<PendingProgram>,
+}
+
+/// An embedded font whose font program has not been written yet
+struct PendingProgram {
+    id: (u32, u16),
+    /// Object ID reserved for the `Identity-V` variant, if one was requested
+    vertical_id: Option<(u32, u16)>,
+    font_name: String,
+    program: Rc<FontProgram>,
+}
+
+impl FontRegistry {
+    pub fn new(base_dir: &Path) -> Self {
+        Self {
+            base_dir: base_dir.to_path_buf(),
+            db: None,
+            fonts: HashMap::new(),
+            resource_names: HashSet::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// System font database, loaded on first use
+    fn database(&mut self) -> &Database {
+        self.db.get_or_insert_with(|| {
+            let mut db = Database::new();
+            load_system_fonts_into_db(&mut db);
+            db
+        })
+    }
+
+    /// Keep font resource names clear of `names`, the resources base pages
+    /// already use
+    pub fn reserve_resource_names(&mut self, names: &HashSet<String>) {
+        self.resource_names.extend(names.iter().cloned());
+    }
+
+    /// Reserve a unique resource name for a font
+    fn unique_resource_name(&mut self, font_name: &str) -> String {
+        let base = resource_name_for(font_name);
+        let mut name = base.clone();
+        let mut suffix = 2;
+        while self.resource_names.contains(&name) {
+            name = format!("{}-{}", base, suffix);
+            suffix += 1;
+        }
+        self.resource_names.insert(name.clone());
+        name
+    }
+
+    /// Register a font program as `font_name` under the settings name `key`
+    ///
+    /// The program is embedded later by `embed_programs`.
+    fn add_program(
+        &mut self,
+        doc: &mut Document,
+        key: &str,
+        font_name: &str,
+        data: Vec<u8>,
+    ) -> Result<PdfFont> {
+        let program = Rc::new(FontProgram::new(data));
+        let font = PdfFont {
+            id: doc.new_object_id(),
+            resource_name: self.unique_resource_name(font_name),
+            kind: PdfFontKind::Embedded(Rc::clone(&program)),
+        };
+        self.pending.push(PendingProgram {
+            id: font.id,
+            vertical_id: None,
+            font_name: font_name.to_string(),
+            program,
+        });
+        self.fonts.insert(key.to_string(), font.clone());
+        Ok(font)
+    }
+
+    /// Write every registered embedded font, subset to the characters drawn with it
+    ///
+    /// `used_chars` maps font object IDs to the characters drawn with that font.
+    pub fn embed_programs(
+        &mut self,
+        doc: &mut Document,
+        used_chars: &HashMap<(u32, u16), BTreeSet<char>>,
+    ) -> Result<()> {
+        let no_chars = BTreeSet::new();
+        for pending in self.pending.drain(..) {
+            let chars = used_chars.get(&pending.id).unwrap_or(&no_chars);
+            let vertical = pending.vertical_id.is_some();
+            let type0_font = build_cid_font(doc, &pending.program.data, &pending.font_name, chars, vertical)
+                .with_context(|| format!("Failed to embed font '{}'", pending.font_name))?;
+            // The vertical variant shares the descendant font and ToUnicode CMap
+            if let Some(vertical_id) = pending.vertical_id {
+                let mut vertical_font = type0_font.clone();
+                vertical_font.set("Encoding", "Identity-V");
+                doc.objects.insert(vertical_id, Object::Dictionary(vertical_font));
             }
+            doc.objects.insert(pending.id, Object::Dictionary(type0_font));
+        }
+        Ok(())
     }
 
-    None
+    /// Get a font by its settings.json name, adding it to the document on first use
+    ///
+    /// Returns `Ok(None)` if the name is neither a standard font nor an installed
+    /// system font. Missing font files are reported as errors.
+    pub fn get_or_add(&mut self, doc: &mut Document, name: &str) -> Result<Option<PdfFont>> {
+        // Standard fonts have several spellings, so key them by their base font name
+        let standard = StandardFont::from_name(name);
+        let key = standard.map_or(name, |font| font.base_font_name());
+        if let Some(font) = self.fonts.get(key) {
+            return Ok(Some(font.clone()));
+        }
+
+        if let Some(standard) = standard {
+            let (id, base_name) = create_font(doc, standard)?;
+            let font = PdfFont {
+                id,
+                resource_name: self.unique_resource_name(&base_name),
+                kind: PdfFontKind::Standard(standard),
+            };
+            self.fonts.insert(key.to_string(), font.clone());
+            return Ok(Some(font));
+        }
+
+        let (file, face_index) = split_face_index(name);
+        if is_font_path(file) {
+            let path = self.base_dir.join(file);
+            let data = fs::read(&path)
+                .with_context(|| format!("Failed to read font file: {:?}", path))?;
+            let index = face_index.unwrap_or(0);
+            if index > 0 && !data.starts_with(b"ttcf") {
+                return Err(anyhow!("Font file {:?} is not a collection, so it has no face {}", path, index));
+            }
+            let data = standalone_face(data, index)
+                .ok_or_else(|| anyhow!("Failed to read face {} of font collection {:?}", index, path))?;
+            Face::parse(&data, 0)
+                .map_err(|e| anyhow!("Failed to parse font file {:?}: {}", path, e))?;
+            let stem = path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or(file);
+            // Faces of one collection need their own names
+            let font_name = match face_index {
+                Some(index) => format!("{}-{}", stem, index),
+                None => stem.to_string(),
+            };
+            return self.add_program(doc, name, &font_name, data).map(Some);
+        }
+
+        match load_system_font(self.database(), name) {
+            Some(data) => self.add_program(doc, name, name, data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the top-to-bottom variant of an embedded font
+    ///
+    /// Returns `None` for the standard fonts, which have no vertical writing mode.
+    /// The variant is a second Type0 font using `Identity-V` over the same
+    /// embedded font program.
+    pub fn get_or_add_vertical(&mut self, doc: &mut Document, font: &PdfFont) -> Option<PdfFont> {
+        let PdfFontKind::Embedded(program) = &font.kind else {
+            return None;
+        };
+        let key = format!("{} (vertical)", font.resource_name);
+        if let Some(vertical) = self.fonts.get(&key) {
+            return Some(vertical.clone());
+        }
+
+        let pending = self.pending.iter().position(|p| p.id == font.id)?;
+        let vertical = PdfFont {
+            id: doc.new_object_id(),
+            resource_name: self.unique_resource_name(&format!("{}-V", font.resource_name)),
+            kind: PdfFontKind::Vertical(Rc::clone(program)),
+        };
+        self.pending[pending].vertical_id = Some(vertical.id);
+        self.fonts.insert(key, vertical.clone());
+        Some(vertical)
+    }
+
+    /// Get a CJK-capable font for text the configured fonts cannot draw
+    ///
+    /// Tries `preferred_font` first, then common CJK families.
+    pub fn get_or_add_cid_fallback(
+        &mut self,
+        doc: &mut Document,
+        preferred_font: Option<&str>,
+    ) -> Result<Option<PdfFont>> {
+        let Some((data, family)) = find_cid_font(self.database(), preferred_font) else {
+            return Ok(None);
+        };
+        if let Some(font) = self.fonts.get(&family) {
+            return Ok(Some(font.clone()));
+        }
+        self.add_program(doc, &family, &family, data).map(Some)
+    }
+
+    /// Font resources dictionary covering every font added so far
+    pub fn resources(&self) -> Dictionary {
+        let mut font_resources = Dictionary::new();
+        for font in self.fonts.values() {
+            font_resources.set(font.resource_name.clone(), Object::Reference(font.id));
+        }
+        font_resources
+    }
+}
+
+/// Read a big-endian u16 at `offset`
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+/// Read a big-endian u32 at `offset`
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Sum of a font's data as big-endian u32 values (zero-padded), as used by sfnt checksums
+fn sfnt_checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
 }
 
 /// Extract a single font from a TrueType Collection file
 ///
-/// Returns the font data
+/// Rebuilds the face at `index` as a standalone sfnt: its offset table, the
+/// table records it references and the table data, with offsets rewritten.
+/// Returns `None` if the data is not a valid collection or the index is out of range.
 fn extract_from_ttc(ttc_data: &[u8], index: u32) -> Option<Vec<u8>> {
-    // Try to parse the font at the given index
-    if let Ok(_face) = Face::parse(ttc_data, index) {
-        // For TTC files, we can extract the specific font
-        // However, for PDF embedding, we need the full TTC data
-        // and specify the index in the font descriptor
-        // For simplicity, return the whole TTC file
-        return Some(ttc_data.to_vec());
+    if !ttc_data.starts_with(b"ttcf") || index >= read_u32(ttc_data, 8)? {
+        return None;
+    }
+    let face_offset = read_u32(ttc_data, 12 + 4 * index as usize)? as usize;
+    let num_tables = read_u16(ttc_data, face_offset + 4)? as usize;
+
+    // Offset table (sfnt version, table count and search hints) is copied as is
+    let mut font = ttc_data.get(face_offset..face_offset + 12)?.to_vec();
+    let mut table_data = Vec::new();
+    let mut head_offset = None;
+    let data_start = 12 + 16 * num_tables;
+
+    for i in 0..num_tables {
+        let record = face_offset + 12 + 16 * i;
+        let tag = ttc_data.get(record..record + 4)?;
+        let checksum = read_u32(ttc_data, record + 4)?;
+        let offset = read_u32(ttc_data, record + 8)? as usize;
+        let length = read_u32(ttc_data, record + 12)? as usize;
+        let table = ttc_data.get(offset..offset + length)?;
+
+        let new_offset = data_start + table_data.len();
+        if tag == b"head" {
+            head_offset = Some(new_offset);
+        }
+        font.extend_from_slice(tag);
+        font.extend_from_slice(&checksum.to_be_bytes());
+        font.extend_from_slice(&(new_offset as u32).to_be_bytes());
+        font.extend_from_slice(&(length as u32).to_be_bytes());
+
+        // Tables start on 4-byte boundaries
+        table_data.extend_from_slice(table);
+        table_data.resize(table_data.len().next_multiple_of(4), 0);
+    }
+    font.extend_from_slice(&table_data);
+
+    // The whole-font checksum changes with the new layout
+    if let Some(head) = head_offset
+        && font.len() >= head + 12 {
+            font[head + 8..head + 12].fill(0);
+            let adjustment = 0xB1B0_AFBAu32.wrapping_sub(sfnt_checksum(&font));
+            font[head + 8..head + 12].copy_from_slice(&adjustment.to_be_bytes());
+        }
+
+    Some(font)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standard_font_from_name() {
+        assert_eq!(
+            StandardFont::from_name("Times").map(|f| f.base_font_name()),
+            Some("Times-Roman")
+        );
+        assert_eq!(
+            StandardFont::from_name("zapfdingbats").map(|f| f.base_font_name()),
+            Some("ZapfDingbats")
+        );
+        assert!(StandardFont::from_name("Meiryo UI").is_none());
+    }
+
+    /// Build a two-face collection whose faces each have one "test" table
+    fn build_ttc(first: &[u8], second: &[u8]) -> Vec<u8> {
+        let mut ttc = b"ttcf".to_vec();
+        ttc.extend_from_slice(&[0, 1, 0, 0]);
+        ttc.extend_from_slice(&2u32.to_be_bytes());
+        // Two offset tables of 12 + 16 bytes each follow the 20-byte header
+        ttc.extend_from_slice(&20u32.to_be_bytes());
+        ttc.extend_from_slice(&48u32.to_be_bytes());
+        let first_data = 76u32;
+        let second_data = first_data + first.len().next_multiple_of(4) as u32;
+        for (table, offset) in [(first, first_data), (second, second_data)] {
+            ttc.extend_from_slice(&[0, 1, 0, 0, 0, 1, 0, 16, 0, 0, 0, 0]);
+            ttc.extend_from_slice(b"test");
+            ttc.extend_from_slice(&0u32.to_be_bytes());
+            ttc.extend_from_slice(&offset.to_be_bytes());
+            ttc.extend_from_slice(&(table.len() as u32).to_be_bytes());
+        }
+        for table in [first, second] {
+            ttc.extend_from_slice(table);
+            ttc.resize(ttc.len().next_multiple_of(4), 0);
+        }
+        ttc
+    }
+
+    #[test]
+    fn test_extract_from_ttc_selects_face() {
+        let ttc = build_ttc(b"JP glyphs", b"KR glyphs!");
+
+        let face = extract_from_ttc(&ttc, 1).unwrap();
+        // Standalone offset table with one table record pointing right after it
+        assert_eq!(&face[0..4], &[0, 1, 0, 0]);
+        assert_eq!(read_u16(&face, 4), Some(1));
+        assert_eq!(&face[12..16], b"test");
+        assert_eq!(read_u32(&face, 20), Some(28));
+        assert_eq!(&face[28..38], b"KR glyphs!");
+
+        let face = extract_from_ttc(&ttc, 0).unwrap();
+        assert_eq!(&face[28..37], b"JP glyphs");
+    }
+
+    #[test]
+    fn test_extract_from_ttc_invalid() {
+        let ttc = build_ttc(b"a", b"b");
+        assert!(extract_from_ttc(&ttc, 2).is_none());
+        assert!(extract_from_ttc(b"OTTO", 0).is_none());
+        // Plain fonts pass through unchanged
+        assert_eq!(standalone_face(b"OTTO".to_vec(), 0), Some(b"OTTO".to_vec()));
+    }
+
+    #[test]
+    fn test_build_widths_array_groups_consecutive_glyphs() {
+        let widths = build_widths_array(&[(0, 500), (3, 600), (4, 610), (5, 620), (9, 1000)]);
+        assert_eq!(
+            widths,
+            vec![
+                Object::Integer(0),
+                Object::Array(vec![Object::Integer(500)]),
+                Object::Integer(3),
+                Object::Array(vec![Object::Integer(600), Object::Integer(610), Object::Integer(620)]),
+                Object::Integer(9),
+                Object::Array(vec![Object::Integer(1000)]),
+            ]
+        );
+        assert!(build_widths_array(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_build_vertical_metrics_array() {
+        let metrics = build_vertical_metrics_array(&[(3, -1000, 500, 880), (7, -1164, 300, 928)]);
+        let expected: Vec<Object> = [3, 3, -1000, 500, 880, 7, 7, -1164, 300, 928]
+            .into_iter()
+            .map(Object::Integer)
+            .collect();
+        assert_eq!(metrics, expected);
+    }
+
+    #[test]
+    fn test_build_to_unicode_cmap() {
+        let mut mappings = BTreeMap::new();
+        mappings.insert(0x0041, 'A');
+        mappings.insert(0x3042, 'あ');
+        let cmap = String::from_utf8(build_to_unicode_cmap(&mappings)).unwrap();
+        assert!(cmap.contains("2 beginbfchar\n<0041> <0041>\n<3042> <3042>\nendbfchar"));
+    }
+
+    #[test]
+    fn test_build_to_unicode_cmap_supplementary_plane() {
+        // CJK Extension B characters map to a surrogate pair
+        let mut mappings = BTreeMap::new();
+        mappings.insert(0x0123, '𠀋');
+        let cmap = String::from_utf8(build_to_unicode_cmap(&mappings)).unwrap();
+        assert!(cmap.contains("<0123> <D840DC0B>"));
+        assert!(cmap.contains("/CMapName /Adobe-Identity-UCS def"));
+    }
+
+    #[test]
+    fn test_build_to_unicode_cmap_splits_sections() {
+        let mappings: BTreeMap<u16, char> = (0..150u16)
+            .map(|code| (code, char::from_u32(0x4E00 + code as u32).unwrap()))
+            .collect();
+        let cmap = String::from_utf8(build_to_unicode_cmap(&mappings)).unwrap();
+        assert!(cmap.contains("100 beginbfchar"));
+        assert!(cmap.contains("50 beginbfchar"));
+    }
+
+    /// A program with glyphs for "A" (glyph 1) and "（" (glyph 2, vertical
+    /// form glyph 3) in a 1000-unit em
+    fn test_program() -> FontProgram {
+        FontProgram {
+            data: Vec::new(),
+            glyphs: Some(GlyphTables {
+                units_per_em: 1000.0,
+                glyphs: HashMap::from([('A', 1), ('（', 2)]),
+                vertical_forms: HashMap::from([(2, 3)]),
+                cids: None,
+                advances: vec![500, 600, 1000, 1000],
+                vertical_advances: vec![1000.0, 1000.0, 1000.0, 800.0],
+            }),
+        }
+    }
+
+    #[test]
+    fn test_font_program_encodes_from_cached_tables() {
+        let program = test_program();
+        assert!(program.has_glyphs("A（\n"));
+        assert!(!program.has_glyphs("B"));
+        assert_eq!(program.encode_text("A（B", false), "000100020000");
+        assert_eq!(program.encode_text("（", true), "0003");
+        // Missing characters use the .notdef width
+        assert_eq!(program.text_width("AB"), 1100.0);
+        assert_eq!(program.text_height("A（"), 1800.0);
+    }
+
+    #[test]
+    fn test_font_program_without_font() {
+        let program = FontProgram::new(Vec::new());
+        assert!(!program.has_glyphs("A"));
+        assert_eq!(program.encode_text("A", false), "0000");
+        assert_eq!(program.text_width("A"), 0.0);
+    }
+
+    #[test]
+    fn test_cid_keyed_font_encodes_cids() {
+        let mut program = test_program();
+        if let Some(tables) = &mut program.glyphs {
+            tables.cids = Some(vec![0, 1200, 633, 8721]);
+        }
+        assert_eq!(program.encode_text("A（B", false), "04B002790000");
+        assert_eq!(program.encode_text("（", true), "2211");
+        // Widths are still looked up by glyph
+        assert_eq!(program.text_width("A"), 600.0);
+    }
+
+    #[test]
+    fn test_glyph_code() {
+        assert_eq!(glyph_code(None, 42), 42);
+        assert_eq!(glyph_code(Some(&[0, 1200, 633]), 2), 633);
+        assert_eq!(glyph_code(Some(&[0, 1200, 633]), 3), 0);
+    }
+
+    #[test]
+    fn test_split_face_index() {
+        assert_eq!(split_face_index("fonts/NotoSansCJK.ttc#2"), ("fonts/NotoSansCJK.ttc", Some(2)));
+        assert_eq!(split_face_index("msgothic.ttc#0"), ("msgothic.ttc", Some(0)));
+        assert_eq!(split_face_index("fonts/NotoSansCJK.ttc"), ("fonts/NotoSansCJK.ttc", None));
+        assert_eq!(split_face_index("fonts/C#.ttf"), ("fonts/C#.ttf", None));
+        assert_eq!(split_face_index("font.ttc#"), ("font.ttc#", None));
     }
 
-    None
+    #[test]
+    fn test_opentype_font_file_raises_pdf_version() {
+        let mut doc = Document::with_version("1.5");
+        let descriptor_id = doc.add_object(Dictionary::new());
+        embed_font_stream(&mut doc, b"true", descriptor_id, false).unwrap();
+        assert_eq!(doc.version, "1.5");
+        embed_font_stream(&mut doc, b"OTTO", descriptor_id, true).unwrap();
+        assert_eq!(doc.version, "1.6");
+        assert!(doc.get_dictionary(descriptor_id).unwrap().has(b"FontFile3"));
+
+        // Newer base documents keep their version
+        let mut doc = Document::with_version("1.7");
+        let descriptor_id = doc.add_object(Dictionary::new());
+        embed_font_stream(&mut doc, b"OTTO", descriptor_id, true).unwrap();
+        assert_eq!(doc.version, "1.7");
+    }
+
+    /// An installed TrueType font with glyphs for "HeloZ", if there is one
+    fn system_truetype_font() -> Option<Vec<u8>> {
+        let mut db = Database::new();
+        load_system_fonts_into_db(&mut db);
+        db.faces().find_map(|info| {
+            let (fontdb::Source::File(path), index) = db.face_source(info.id)? else {
+                return None;
+            };
+            let data = standalone_face(fs::read(path).ok()?, index)?;
+            let face = Face::parse(&data, 0).ok()?;
+            let has_glyphs = "HeloZ".chars().all(|c| face.glyph_index(c).is_some_and(|gid| gid.0 != 0));
+            (face.tables().glyf.is_some() && has_glyphs).then_some(data)
+        })
+    }
+
+    fn name_of(dict: &Dictionary, key: &[u8]) -> String {
+        String::from_utf8(dict.get(key).unwrap().as_name().unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_build_cid_font_subsets_program() {
+        let Some(data) = system_truetype_font() else {
+            eprintln!("No TrueType font installed; skipping");
+            return;
+        };
+        let mut doc = Document::with_version("1.5");
+        let used_chars: BTreeSet<char> = "Hello".chars().collect();
+        let type0_font = build_cid_font(&mut doc, &data, "Test Sans", &used_chars, false).unwrap();
+
+        // The subset tag prefixes the name of the font, its descendant and descriptor
+        let base_font = name_of(&type0_font, b"BaseFont");
+        let (tag, name) = base_font.split_once('+').unwrap();
+        assert_eq!(name, "TestSans");
+        assert!(tag.len() == 6 && tag.bytes().all(|b| b.is_ascii_uppercase()));
+        let descendant_id = type0_font.get(b"DescendantFonts").unwrap().as_array().unwrap()[0]
+            .as_reference()
+            .unwrap();
+        let cid_font = doc.get_dictionary(descendant_id).unwrap();
+        assert_eq!(name_of(cid_font, b"BaseFont"), base_font);
+        assert_eq!(name_of(cid_font, b"CIDToGIDMap"), "Identity");
+        let descriptor = doc.get_dictionary(cid_font.get(b"FontDescriptor").unwrap().as_reference().unwrap()).unwrap();
+        assert_eq!(name_of(descriptor, b"FontName"), base_font);
+
+        // The embedded program is smaller than the source font and still parses
+        let stream_id = descriptor.get(b"FontFile2").unwrap().as_reference().unwrap();
+        let program = doc.get_object(stream_id).unwrap().as_stream().unwrap().decompressed_content().unwrap();
+        assert!(program.len() < data.len());
+        let subset = Face::parse(&program, 0).unwrap();
+
+        // Used glyphs keep their IDs, so the Identity CIDToGIDMap still finds them
+        let face = Face::parse(&data, 0).unwrap();
+        for c in used_chars {
+            let gid = face.glyph_index(c).unwrap();
+            assert_eq!(subset.glyph_bounding_box(gid), face.glyph_bounding_box(gid), "glyph of {:?}", c);
+        }
+        let unused = face.glyph_index('Z').unwrap();
+        assert!(subset.glyph_bounding_box(unused).is_none());
+    }
 }
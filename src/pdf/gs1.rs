@@ -0,0 +1,75 @@
+//! GS1 element strings.
+//!
+//! This module converts human-readable GS1 data such as
+//! `(01)09501101530003(17)260101(10)AB12` into the element string encoded in
+//! GS1 symbols, with a group separator after variable-length values.
+
+use anyhow::{anyhow, Result};
+
+/// Group separator (GS) ending a variable-length value
+pub const GROUP_SEPARATOR: u8 = 0x1D;
+
+/// First two digits of the application identifiers with predefined lengths,
+/// which never need a separator
+const PREDEFINED_LENGTH_PREFIXES: [&str; 22] = [
+    "00", "01", "02", "03", "04", "11", "12", "13", "14", "15", "16",
+    "17", "18", "19", "20", "31", "32", "33", "34", "35", "36", "41",
+];
+
+/// Convert `(AI)value` pairs to a GS1 element string
+///
+/// Data that does not start with `(` is taken to be an element string
+/// already, with group separators where needed.
+pub fn element_string(data: &str) -> Result<Vec<u8>> {
+    if !data.starts_with('(') {
+        return Ok(data.as_bytes().to_vec());
+    }
+    let mut elements = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (ai, after) = rest.strip_prefix('(')
+            .and_then(|s| s.split_once(')'))
+            .ok_or_else(|| anyhow!("Invalid GS1 data, expected (AI)value: {}", data))?;
+        if !(2..=4).contains(&ai.len()) || !ai.chars().all(|c| c.is_ascii_digit()) {
+            return Err(anyhow!("Invalid GS1 application identifier '{}' in {}", ai, data));
+        }
+        let end = after.find('(').unwrap_or(after.len());
+        let value = &after[..end];
+        if value.is_empty() {
+            return Err(anyhow!("GS1 application identifier ({}) has no value in {}", ai, data));
+        }
+        elements.push((ai, value));
+        rest = &after[end..];
+    }
+
+    let mut bytes = Vec::new();
+    for (i, (ai, value)) in elements.iter().enumerate() {
+        bytes.extend_from_slice(ai.as_bytes());
+        bytes.extend_from_slice(value.as_bytes());
+        let last = i + 1 == elements.len();
+        if !last && !PREDEFINED_LENGTH_PREFIXES.contains(&&ai[..2]) {
+            bytes.push(GROUP_SEPARATOR);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_element_string() {
+        let bytes = element_string("(01)09501101530003(10)AB12(17)260101(21)S1").unwrap();
+        // (17) has a predefined length, so only (10) needs a separator
+        assert_eq!(bytes, b"010950110153000310AB12\x1d1726010121S1");
+        assert_eq!(element_string("0109501101530003").unwrap(), b"0109501101530003");
+    }
+
+    #[test]
+    fn test_invalid_element_string() {
+        assert!(element_string("(01").is_err());
+        assert!(element_string("(1)5").is_err());
+        assert!(element_string("(01)").is_err());
+    }
+}
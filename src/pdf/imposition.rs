@@ -0,0 +1,376 @@
+//! N-up imposition of data rows onto sheets.
+//!
+//! This module handles:
+//! - Resolving the grid layout from settings.json and label sheet presets into points
+//! - Positions of the cells on a sheet
+//! - Turning base pages into Form XObjects drawn as cell templates
+
+use anyhow::{anyhow, Context, Result};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use crate::config::{Dimension, LayoutBase, LayoutSection, SettingsSection};
+use super::content::compress_data;
+use super::page::PageGeometry;
+use super::sheet_presets::{find_preset, preset_names, SheetPreset};
+
+/// Tolerance for checking that the grid fits on the sheet, in points
+const FIT_TOLERANCE: f64 = 0.01;
+
+/// Grid layout with all dimensions in points
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SheetGrid {
+    pub columns: u32,
+    pub rows: u32,
+    pub cell_width: f64,
+    pub cell_height: f64,
+    pub gutter_x: f64,
+    pub gutter_y: f64,
+    pub margin_left: f64,
+    pub margin_top: f64,
+}
+
+/// A layout value in points, falling back to the preset's value
+fn layout_value(value: Option<Dimension>, preset_value: Option<f64>) -> Option<f64> {
+    value.map(|v| v.as_points()).or(preset_value)
+}
+
+impl SheetGrid {
+    /// Resolve and validate the grid from settings.json, with values left out
+    /// of the layout taken from the label sheet preset
+    pub fn resolve(layout: &LayoutSection, preset: Option<&SheetPreset>) -> Result<Self> {
+        let missing = |name: &str| anyhow!("Layout needs {} (or a label sheet preset in settings.sheet)", name);
+        let grid = Self {
+            columns: layout.columns.or(preset.map(|p| p.columns)).ok_or_else(|| missing("columns"))?,
+            rows: layout.rows.or(preset.map(|p| p.rows)).ok_or_else(|| missing("rows"))?,
+            cell_width: layout_value(layout.cell_width, preset.map(|p| p.cell_width))
+                .ok_or_else(|| missing("cell_width"))?,
+            cell_height: layout_value(layout.cell_height, preset.map(|p| p.cell_height))
+                .ok_or_else(|| missing("cell_height"))?,
+            gutter_x: layout_value(layout.gutter_x, preset.map(|p| p.gutter_x)).unwrap_or(0.0),
+            gutter_y: layout_value(layout.gutter_y, preset.map(|p| p.gutter_y)).unwrap_or(0.0),
+            margin_left: layout_value(layout.margin_left, preset.map(|p| p.margin_left)).unwrap_or(0.0),
+            margin_top: layout_value(layout.margin_top, preset.map(|p| p.margin_top)).unwrap_or(0.0),
+        };
+        if grid.columns == 0 || grid.rows == 0 {
+            return Err(anyhow!("Layout needs at least one column and one row"));
+        }
+        if grid.cell_width <= 0.0 || grid.cell_height <= 0.0 {
+            return Err(anyhow!("Layout cell size must be positive"));
+        }
+        if grid.gutter_x < 0.0 || grid.gutter_y < 0.0 {
+            return Err(anyhow!("Layout gutters must not be negative"));
+        }
+        Ok(grid)
+    }
+
+    /// Number of cells on one sheet
+    pub fn cells_per_sheet(&self) -> usize {
+        (self.columns * self.rows) as usize
+    }
+
+    /// Top-left corner of a cell, measured from the top-left corner of the sheet
+    ///
+    /// Cells are numbered left to right, top to bottom, starting at 0.
+    pub fn cell_origin(&self, index: usize) -> (f64, f64) {
+        let column = (index % self.columns as usize) as f64;
+        let row = (index / self.columns as usize % self.rows as usize) as f64;
+        (
+            self.margin_left + column * (self.cell_width + self.gutter_x),
+            self.margin_top + row * (self.cell_height + self.gutter_y),
+        )
+    }
+
+    /// Positions of the vertical and horizontal cuts between cells on a
+    /// sheet of the given height, measured from the bottom-left corner
+    pub fn cut_positions(&self, sheet_height: f64) -> (Vec<f64>, Vec<f64>) {
+        let mut cuts_x = Vec::new();
+        for column in 0..self.columns as usize {
+            let (left, _) = self.cell_origin(column);
+            push_cut(&mut cuts_x, left);
+            push_cut(&mut cuts_x, left + self.cell_width);
+        }
+        let mut cuts_y = Vec::new();
+        for row in 0..self.rows as usize {
+            let (_, top) = self.cell_origin(row * self.columns as usize);
+            push_cut(&mut cuts_y, sheet_height - top);
+            push_cut(&mut cuts_y, sheet_height - top - self.cell_height);
+        }
+        (cuts_x, cuts_y)
+    }
+
+    /// Check that every cell lies on a sheet of the given size
+    pub fn check_fits(&self, sheet_width: f64, sheet_height: f64) -> Result<()> {
+        let (left, top) = self.cell_origin(self.cells_per_sheet() - 1);
+        let right = left + self.cell_width;
+        let bottom = top + self.cell_height;
+        if right > sheet_width + FIT_TOLERANCE || bottom > sheet_height + FIT_TOLERANCE {
+            return Err(anyhow!(
+                "Layout grid ({:.1} x {:.1} pt) does not fit on the sheet ({:.1} x {:.1} pt)",
+                right, bottom, sheet_width, sheet_height
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Add a cut position unless it meets the previous one (cells without a gutter)
+fn push_cut(cuts: &mut Vec<f64>, position: f64) {
+    if cuts.last().is_none_or(|last| (last - position).abs() > FIT_TOLERANCE) {
+        cuts.push(position);
+    }
+}
+
+/// N-up layout resolved from settings.json
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SheetLayout {
+    pub grid: SheetGrid,
+    pub base: LayoutBase,
+    /// Sheet size in points from the layout or the label sheet preset
+    pub sheet_size: Option<(f64, f64)>,
+    /// Cell (0-based) the first row is printed in
+    pub first_cell: usize,
+}
+
+impl SheetLayout {
+    /// Resolve the layout from `settings.layout` and `settings.sheet`
+    ///
+    /// Returns `None` when neither is set, so every row gets its own pages.
+    pub fn from_settings(settings: &SettingsSection) -> Result<Option<Self>> {
+        let preset = match settings.sheet.as_deref() {
+            Some(name) => Some(find_preset(name).ok_or_else(|| {
+                anyhow!("Unknown label sheet '{}' (known sheets: {})", name, preset_names().join(", "))
+            })?),
+            None => None,
+        };
+        if preset.is_none() && settings.layout.is_none() {
+            if settings.start_at.is_some() {
+                return Err(anyhow!("start_at needs a layout or a label sheet preset"));
+            }
+            return Ok(None);
+        }
+
+        let default_layout = LayoutSection::default();
+        let layout = settings.layout.as_ref().unwrap_or(&default_layout);
+        let grid = SheetGrid::resolve(layout, preset)?;
+        let sheet_size = layout_value(layout.sheet_width, preset.map(|p| p.sheet_width))
+            .zip(layout_value(layout.sheet_height, preset.map(|p| p.sheet_height)));
+
+        let first_cell = match settings.start_at {
+            None => 0,
+            Some(start_at) if start_at >= 1 && start_at as usize <= grid.cells_per_sheet() => {
+                start_at as usize - 1
+            }
+            Some(start_at) => {
+                return Err(anyhow!(
+                    "start_at must be between 1 and {} (cells per sheet), found {}",
+                    grid.cells_per_sheet(), start_at
+                ));
+            }
+        };
+
+        Ok(Some(Self { grid, base: layout.base, sheet_size, first_cell }))
+    }
+
+    /// Split rows into the groups printed on each sheet
+    ///
+    /// The first sheet starts at `first_cell`; the rest start at the first cell.
+    pub fn sheet_chunks<'a, T>(&self, rows: &'a [T]) -> Vec<&'a [T]> {
+        let mut chunks = Vec::new();
+        let mut remaining = rows;
+        let mut free_cells = self.grid.cells_per_sheet() - self.first_cell;
+        while !remaining.is_empty() {
+            let (chunk, rest) = remaining.split_at(free_cells.min(remaining.len()));
+            chunks.push(chunk);
+            remaining = rest;
+            free_cells = self.grid.cells_per_sheet();
+        }
+        chunks
+    }
+}
+
+/// Content streams of a page, decoded and joined
+fn page_content(doc: &Document, page: &Dictionary) -> Result<Vec<u8>> {
+    let contents = match page.get(b"Contents") {
+        Ok(contents) => doc.dereference(contents)?.1,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let references = match contents {
+        Object::Array(items) => items.clone(),
+        Object::Stream(_) => vec![page.get(b"Contents")?.clone()],
+        _ => return Err(anyhow!("Page Contents is neither a stream nor an array")),
+    };
+
+    let mut content = Vec::new();
+    for reference in &references {
+        let stream = doc.dereference(reference)?.1.as_stream()?;
+        let data = if stream.dict.has(b"Filter") {
+            stream.decompressed_content()?
+        } else {
+            stream.content.clone()
+        };
+        content.extend(data);
+        content.push(b'\n');
+    }
+    Ok(content)
+}
+
+/// Add a Form XObject drawing the visible area of a page as displayed
+///
+/// The form's coordinate system is the page's display space: its origin is the
+/// bottom-left corner of the displayed page and it extends `geometry.width()`
+/// by `geometry.height()`.
+pub fn create_template_xobject(
+    doc: &mut Document,
+    page: &Dictionary,
+    geometry: &PageGeometry,
+) -> Result<ObjectId> {
+    let content = page_content(doc, page)
+        .with_context(|| "Failed to read the content of the cell template page")?;
+
+    let bbox: Vec<Object> = geometry.visible_box.iter().map(|v| Object::Real(*v as f32)).collect();
+    let matrix: Vec<Object> = geometry.user_to_display_matrix()
+        .iter()
+        .map(|v| Object::Real(*v as f32))
+        .collect();
+
+    let mut form_dict = Dictionary::new();
+    form_dict.set("Type", "XObject");
+    form_dict.set("Subtype", "Form");
+    form_dict.set("BBox", bbox);
+    form_dict.set("Matrix", matrix);
+    if let Ok(resources) = page.get(b"Resources") {
+        form_dict.set("Resources", resources.clone());
+    }
+    form_dict.set("Filter", "FlateDecode");
+
+    Ok(doc.add_object(Stream::new(form_dict, compress_data(&content)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::dictionary;
+
+    fn layout(columns: u32, rows: u32) -> LayoutSection {
+        LayoutSection {
+            columns: Some(columns),
+            rows: Some(rows),
+            cell_width: Some(Dimension(100.0)),
+            cell_height: Some(Dimension(50.0)),
+            gutter_x: Some(Dimension(10.0)),
+            gutter_y: Some(Dimension(5.0)),
+            margin_left: Some(Dimension(20.0)),
+            margin_top: Some(Dimension(30.0)),
+            ..Default::default()
+        }
+    }
+
+    fn settings(layout: Option<LayoutSection>, sheet: Option<&str>, start_at: Option<u32>) -> SettingsSection {
+        SettingsSection {
+            layout,
+            sheet: sheet.map(str::to_string),
+            start_at,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cell_origin_fills_rows_first() {
+        let grid = SheetGrid::resolve(&layout(3, 2), None).unwrap();
+        assert_eq!(grid.cells_per_sheet(), 6);
+        assert_eq!(grid.cell_origin(0), (20.0, 30.0));
+        assert_eq!(grid.cell_origin(1), (130.0, 30.0));
+        assert_eq!(grid.cell_origin(3), (20.0, 85.0));
+        assert_eq!(grid.cell_origin(5), (240.0, 85.0));
+    }
+
+    #[test]
+    fn test_invalid_layout() {
+        assert!(SheetGrid::resolve(&layout(0, 2), None).is_err());
+        let mut negative = layout(1, 1);
+        negative.cell_height = Some(Dimension(-1.0));
+        assert!(SheetGrid::resolve(&negative, None).is_err());
+        let mut incomplete = layout(1, 1);
+        incomplete.cell_width = None;
+        let err = SheetGrid::resolve(&incomplete, None).unwrap_err();
+        assert!(err.to_string().contains("cell_width"));
+    }
+
+    #[test]
+    fn test_check_fits() {
+        let grid = SheetGrid::resolve(&layout(3, 2), None).unwrap();
+        // The last cell ends at (340, 135)
+        assert!(grid.check_fits(340.0, 135.0).is_ok());
+        assert!(grid.check_fits(339.0, 135.0).is_err());
+        assert!(grid.check_fits(340.0, 134.0).is_err());
+    }
+
+    #[test]
+    fn test_cut_positions() {
+        let grid = SheetGrid::resolve(&layout(2, 2), None).unwrap();
+        let (cuts_x, cuts_y) = grid.cut_positions(200.0);
+        assert_eq!(cuts_x, vec![20.0, 120.0, 130.0, 230.0]);
+        assert_eq!(cuts_y, vec![170.0, 120.0, 115.0, 65.0]);
+
+        let mut butted = layout(2, 1);
+        butted.gutter_x = None;
+        let (cuts_x, _) = SheetGrid::resolve(&butted, None).unwrap().cut_positions(200.0);
+        assert_eq!(cuts_x, vec![20.0, 120.0, 220.0]);
+    }
+
+    #[test]
+    fn test_layout_from_preset_with_overrides() {
+        let overrides = LayoutSection { margin_top: Some(Dimension(40.0)), ..Default::default() };
+        let sheet = SheetLayout::from_settings(&settings(Some(overrides), Some("avery-5160"), None))
+            .unwrap()
+            .unwrap();
+        assert_eq!((sheet.grid.columns, sheet.grid.rows), (3, 10));
+        assert_eq!(sheet.grid.cell_width, 189.0);
+        assert_eq!(sheet.grid.margin_left, 13.5);
+        assert_eq!(sheet.grid.margin_top, 40.0);
+        assert_eq!(sheet.sheet_size, Some((612.0, 792.0)));
+        assert_eq!(sheet.base, LayoutBase::Sheet);
+    }
+
+    #[test]
+    fn test_layout_settings_errors() {
+        assert_eq!(SheetLayout::from_settings(&settings(None, None, None)).unwrap(), None);
+        assert!(SheetLayout::from_settings(&settings(None, None, Some(2))).is_err());
+        let err = SheetLayout::from_settings(&settings(None, Some("avery-9999"), None)).unwrap_err();
+        assert!(err.to_string().contains("avery-5160"));
+        assert!(SheetLayout::from_settings(&settings(None, Some("avery-5160"), Some(0))).is_err());
+        assert!(SheetLayout::from_settings(&settings(None, Some("avery-5160"), Some(31))).is_err());
+    }
+
+    #[test]
+    fn test_sheet_chunks_with_start_at() {
+        let sheet = SheetLayout::from_settings(&settings(Some(layout(2, 2)), None, Some(3)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(sheet.first_cell, 2);
+        let rows = [1, 2, 3, 4, 5, 6, 7];
+        assert_eq!(sheet.sheet_chunks(&rows), vec![&rows[..2], &rows[2..6], &rows[6..]]);
+    }
+
+    #[test]
+    fn test_template_xobject() {
+        let mut doc = Document::with_version("1.5");
+        let first = doc.add_object(Stream::new(Dictionary::new(), b"0 0 m".to_vec()));
+        let second = doc.add_object(Stream::new(Dictionary::new(), b"10 10 l S".to_vec()));
+        let page = dictionary! {
+            "Contents" => vec![first.into(), second.into()],
+            "Resources" => dictionary! { "ProcSet" => vec!["PDF".into()] },
+        };
+        let geometry = PageGeometry { visible_box: [0.0, 0.0, 200.0, 100.0], rotate: 90 };
+
+        let id = create_template_xobject(&mut doc, &page, &geometry).unwrap();
+
+        let stream = doc.get_object(id).unwrap().as_stream().unwrap();
+        assert_eq!(stream.decompressed_content().unwrap(), b"0 0 m\n10 10 l S\n");
+        assert!(stream.dict.has(b"Resources"));
+        let matrix: Vec<f32> = stream.dict.get(b"Matrix").unwrap().as_array().unwrap()
+            .iter()
+            .map(|v| v.as_float().unwrap())
+            .collect();
+        assert_eq!(matrix, [0.0, -1.0, 1.0, 0.0, 0.0, 200.0]);
+    }
+}
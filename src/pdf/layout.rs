@@ -0,0 +1,362 @@
+//! Text layout inside a field box.
+//!
+//! This module provides:
+//! - Line breaking by word (Unicode line breaking rules) or by character
+//! - Horizontal and vertical alignment of the resulting lines
+//! - Vertical (top to bottom, right to left) writing
+//! - Shrinking the font size until the text fits the box
+//!
+//! Text widths are measured with a caller-supplied function so layout does not
+//! depend on how fonts are stored.
+
+use crate::config::{Align, Direction, FieldSpec, FitMode, VAlign, WrapMode};
+use unicode_linebreak::{linebreaks, BreakOpportunity};
+
+/// Distance between baselines, as a multiple of the font size
+pub const LINE_SPACING: f64 = 1.2;
+
+/// Smallest font size `fit: "shrink"` reduces text to, in points
+const MIN_FONT_SIZE: f64 = 1.0;
+
+/// Number of bisection steps used to find the largest fitting font size
+const SHRINK_STEPS: usize = 20;
+
+/// Field box in PDF coordinates (origin at the bottom-left of the page)
+#[derive(Debug, Clone, Copy)]
+pub struct TextBox {
+    pub x: f64,
+    /// Top edge of the box
+    pub top: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// A line of text and where it starts
+///
+/// Horizontal lines start at the left end of their baseline. Vertical lines
+/// (columns) start at the top of the column, centred horizontally, which is
+/// where vertical-mode glyphs hang from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionedLine {
+    pub text: String,
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Result of laying out a text value
+#[derive(Debug, Clone)]
+pub struct TextLayout {
+    pub font_size: f64,
+    pub lines: Vec<PositionedLine>,
+}
+
+/// Lay out `text` inside `bbox` according to the field's text options
+///
+/// `measure` returns the advance of a string at a given font size, in points:
+/// its width for horizontal text, its height for vertical text. Vertical text
+/// runs in columns from right to left; `valign` places the text within each
+/// column and `align` places the block of columns (defaulting to the right).
+pub fn layout_text(
+    text: &str,
+    bbox: &TextBox,
+    font_size: f64,
+    spec: &FieldSpec,
+    measure: &dyn Fn(&str, f64) -> f64,
+) -> TextLayout {
+    let vertical = spec.direction == Some(Direction::Vertical);
+    // Lines run along the inline axis and stack along the block axis
+    let (inline_size, block_size) = if vertical {
+        (bbox.height, bbox.width)
+    } else {
+        (bbox.width, bbox.height)
+    };
+
+    let lines_at = |size: f64| match spec.wrap {
+        Some(mode) => break_lines(text, inline_size, mode, &|line| measure(line, size)),
+        None => split_lines(text),
+    };
+    let fits = |lines: &[String], size: f64| {
+        block_extent(lines.len(), size) <= block_size
+            && lines.iter().all(|line| measure(line, size) <= inline_size)
+    };
+
+    let mut font_size = font_size;
+    let mut lines = lines_at(font_size);
+    if spec.fit == Some(FitMode::Shrink) && !fits(&lines, font_size) {
+        // Bisect between a size known to fit (or the minimum) and one known
+        // not to; text already set below the minimum is never enlarged
+        let (mut low, mut high) = (MIN_FONT_SIZE.min(font_size), font_size);
+        for _ in 0..SHRINK_STEPS {
+            let mid = (low + high) / 2.0;
+            if fits(&lines_at(mid), mid) {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        font_size = low;
+        lines = lines_at(font_size);
+    }
+
+    let pitch = font_size * LINE_SPACING;
+    let block_slack = block_size - block_extent(lines.len(), font_size);
+    let lines = if vertical {
+        let block_offset = match spec.align.unwrap_or(Align::Right) {
+            Align::Right => 0.0,
+            Align::Center => block_slack / 2.0,
+            Align::Left => block_slack,
+        };
+        let first_center = bbox.x + bbox.width - block_offset - font_size / 2.0;
+        lines
+            .into_iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let slack = bbox.height - measure(&text, font_size);
+                let y = match spec.valign.unwrap_or_default() {
+                    VAlign::Top => bbox.top,
+                    VAlign::Middle => bbox.top - slack / 2.0,
+                    VAlign::Bottom => bbox.top - slack,
+                };
+                PositionedLine { text, x: first_center - pitch * i as f64, y }
+            })
+            .collect()
+    } else {
+        let block_offset = match spec.valign.unwrap_or_default() {
+            VAlign::Top => 0.0,
+            VAlign::Middle => block_slack / 2.0,
+            VAlign::Bottom => block_slack,
+        };
+        let first_baseline = bbox.top - block_offset - font_size;
+        lines
+            .into_iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let slack = bbox.width - measure(&text, font_size);
+                let x = match spec.align.unwrap_or_default() {
+                    Align::Left => bbox.x,
+                    Align::Center => bbox.x + slack / 2.0,
+                    Align::Right => bbox.x + slack,
+                };
+                PositionedLine { text, x, y: first_baseline - pitch * i as f64 }
+            })
+            .collect()
+    };
+
+    TextLayout { font_size, lines }
+}
+
+/// Extent of `line_count` stacked lines across the line direction
+fn block_extent(line_count: usize, font_size: f64) -> f64 {
+    font_size + font_size * LINE_SPACING * line_count.saturating_sub(1) as f64
+}
+
+/// Split text at explicit line breaks only
+fn split_lines(text: &str) -> Vec<String> {
+    text.lines().map(str::to_string).collect()
+}
+
+/// Break text into lines no wider than `max_width`
+///
+/// Explicit line breaks are always kept. A word wider than `max_width` is
+/// broken between characters.
+pub fn break_lines(
+    text: &str,
+    max_width: f64,
+    mode: WrapMode,
+    measure: &dyn Fn(&str) -> f64,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut push_segment = |segment: &str, mandatory: bool, lines: &mut Vec<String>| {
+        let candidate = format!("{}{}", line, segment);
+        if line.is_empty() || measure(candidate.trim_end()) <= max_width {
+            line = candidate;
+        } else {
+            lines.push(line.trim_end().to_string());
+            line = segment.to_string();
+        }
+
+        // A single segment can still be too wide on its own
+        while measure(line.trim_end()) > max_width {
+            let split = char_fit(&line, max_width, measure);
+            if split == line.len() {
+                break;
+            }
+            lines.push(line[..split].to_string());
+            line = line[split..].to_string();
+        }
+
+        if mandatory {
+            lines.push(line.trim_end_matches(['\r', '\n']).trim_end().to_string());
+            line.clear();
+        }
+    };
+
+    match mode {
+        WrapMode::Word => {
+            let mut start = 0;
+            for (end, opportunity) in linebreaks(text) {
+                let mandatory = opportunity == BreakOpportunity::Mandatory;
+                push_segment(&text[start..end], mandatory, &mut lines);
+                start = end;
+            }
+        }
+        WrapMode::Char => {
+            for (i, c) in text.char_indices() {
+                let segment = &text[i..i + c.len_utf8()];
+                push_segment(segment, c == '\n', &mut lines);
+            }
+            push_segment("", true, &mut lines);
+        }
+    }
+
+    // Text ending in a line break gets a trailing empty line from the final
+    // mandatory break; drop it
+    if lines.len() > 1 && lines.last().is_some_and(|l| l.is_empty()) && text.ends_with('\n') {
+        lines.pop();
+    }
+    lines
+}
+
+/// Byte length of the longest prefix of `line` (at least one character) that
+/// fits `max_width`
+fn char_fit(line: &str, max_width: f64, measure: &dyn Fn(&str) -> f64) -> usize {
+    let mut fit = line.chars().next().map_or(0, char::len_utf8);
+    for (i, c) in line.char_indices().skip(1) {
+        let end = i + c.len_utf8();
+        if measure(&line[..end]) > max_width {
+            break;
+        }
+        fit = end;
+    }
+    fit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every character is 0.5 points wide at size 1
+    fn measure(text: &str) -> f64 {
+        text.chars().count() as f64 * 0.5
+    }
+
+    fn measure_at(text: &str, size: f64) -> f64 {
+        measure(text) * size
+    }
+
+    fn text_box(width: f64, height: f64) -> TextBox {
+        TextBox { x: 10.0, top: 100.0, width, height }
+    }
+
+    #[test]
+    fn test_break_lines_by_word() {
+        let lines = break_lines("aaa bbb ccc", 2.0, WrapMode::Word, &measure);
+        assert_eq!(lines, vec!["aaa", "bbb", "ccc"]);
+
+        let lines = break_lines("aaa bbb ccc", 4.0, WrapMode::Word, &measure);
+        assert_eq!(lines, vec!["aaa bbb", "ccc"]);
+    }
+
+    #[test]
+    fn test_break_lines_splits_long_words() {
+        let lines = break_lines("abcdefgh", 2.0, WrapMode::Word, &measure);
+        assert_eq!(lines, vec!["abcd", "efgh"]);
+    }
+
+    #[test]
+    fn test_break_lines_by_char() {
+        let lines = break_lines("aaa bbb", 2.5, WrapMode::Char, &measure);
+        assert_eq!(lines, vec!["aaa b", "bb"]);
+    }
+
+    #[test]
+    fn test_break_lines_cjk() {
+        // CJK ideographs may be broken between any two characters
+        let lines = break_lines("東京都渋谷区", 1.5, WrapMode::Word, &measure);
+        assert_eq!(lines, vec!["東京都", "渋谷区"]);
+    }
+
+    #[test]
+    fn test_break_lines_keeps_explicit_breaks() {
+        let lines = break_lines("ab\ncd\n", 10.0, WrapMode::Word, &measure);
+        assert_eq!(lines, vec!["ab", "cd"]);
+    }
+
+    #[test]
+    fn test_layout_alignment() {
+        let spec = FieldSpec {
+            align: Some(Align::Right),
+            valign: Some(VAlign::Bottom),
+            ..Default::default()
+        };
+        let layout = layout_text("abcd", &text_box(50.0, 30.0), 10.0, &spec, &measure_at);
+        // Width 20 in a 50 wide box, baseline on the bottom edge
+        assert_eq!(layout.lines, vec![PositionedLine { text: "abcd".into(), x: 40.0, y: 70.0 }]);
+
+        let spec = FieldSpec {
+            align: Some(Align::Center),
+            valign: Some(VAlign::Middle),
+            ..Default::default()
+        };
+        let layout = layout_text("abcd", &text_box(50.0, 30.0), 10.0, &spec, &measure_at);
+        assert_eq!(layout.lines[0].x, 25.0);
+        assert_eq!(layout.lines[0].y, 80.0);
+    }
+
+    #[test]
+    fn test_layout_wraps_lines() {
+        let spec = FieldSpec { wrap: Some(WrapMode::Word), ..Default::default() };
+        let layout = layout_text("aaa bbb", &text_box(20.0, 50.0), 10.0, &spec, &measure_at);
+        assert_eq!(layout.lines.len(), 2);
+        assert_eq!(layout.lines[0].y, 90.0);
+        assert_eq!(layout.lines[1].y, 78.0);
+    }
+
+    #[test]
+    fn test_layout_shrinks_to_fit() {
+        let spec = FieldSpec { fit: Some(FitMode::Shrink), ..Default::default() };
+        let layout = layout_text("abcdefghij", &text_box(25.0, 20.0), 10.0, &spec, &measure_at);
+        assert!(layout.font_size <= 5.0 && layout.font_size > 4.99);
+
+        // Text that already fits keeps its size
+        let layout = layout_text("ab", &text_box(25.0, 20.0), 10.0, &spec, &measure_at);
+        assert_eq!(layout.font_size, 10.0);
+
+        // Sizes below the minimum stay as they are rather than growing
+        let layout = layout_text(&"a".repeat(200), &text_box(25.0, 20.0), 0.5, &spec, &measure_at);
+        assert_eq!(layout.font_size, 0.5);
+    }
+
+    #[test]
+    fn test_layout_shrinks_wrapped_text() {
+        let spec = FieldSpec {
+            wrap: Some(WrapMode::Word),
+            fit: Some(FitMode::Shrink),
+            ..Default::default()
+        };
+        let bbox = text_box(30.0, 25.0);
+        let layout = layout_text("aaaa bbbb cccc dddd", &bbox, 10.0, &spec, &measure_at);
+        assert!(layout.font_size < 10.0);
+        assert!(block_extent(layout.lines.len(), layout.font_size) <= bbox.height);
+        assert!(layout.lines.iter().all(|l| measure_at(&l.text, layout.font_size) <= bbox.width));
+    }
+
+    #[test]
+    fn test_layout_vertical_columns() {
+        let spec = FieldSpec {
+            direction: Some(Direction::Vertical),
+            wrap: Some(WrapMode::Char),
+            ..Default::default()
+        };
+        // Two 10pt characters per 20pt column, columns 12pt apart from the right edge
+        let layout = layout_text("abcd", &text_box(50.0, 20.0), 10.0, &spec, &|t, size| measure(t) * 2.0 * size);
+        assert_eq!(
+            layout.lines,
+            vec![
+                PositionedLine { text: "ab".into(), x: 55.0, y: 100.0 },
+                PositionedLine { text: "cd".into(), x: 43.0, y: 100.0 },
+            ]
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! Bleed and printer's marks.
+//!
+//! This module handles:
+//! - Resolving the `marks` section of settings.json into points
+//! - The MediaBox, BleedBox and TrimBox of pages with bleed and marks
+//! - Placement of crop marks and registration targets around the trim box
+
+use anyhow::{anyhow, Result};
+use lopdf::{Dictionary, Object};
+use crate::config::MarksSection;
+
+/// Points per millimetre
+const MM: f64 = 72.0 / 25.4;
+
+/// Default crop mark length
+const DEFAULT_MARK_LENGTH: f64 = 5.0 * MM;
+
+/// Smallest default gap between the trim edge and the marks
+const MIN_MARK_OFFSET: f64 = 3.0 * MM;
+
+/// Default line width of the marks, in points
+const DEFAULT_MARK_WIDTH: f64 = 0.25;
+
+/// Bleed and marks in points
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrintMarks {
+    pub bleed: f64,
+    pub crop: bool,
+    pub registration: bool,
+    pub length: f64,
+    pub offset: f64,
+    pub line_width: f64,
+}
+
+impl PrintMarks {
+    /// Resolve and validate the marks section of settings.json
+    pub fn from_section(section: &MarksSection) -> Result<Self> {
+        let bleed = section.bleed.map(|v| v.as_points()).unwrap_or(0.0);
+        let marks = Self {
+            bleed,
+            crop: section.crop_marks.unwrap_or(true),
+            registration: section.registration_marks.unwrap_or(false),
+            length: section.mark_length.map(|v| v.as_points()).unwrap_or(DEFAULT_MARK_LENGTH),
+            offset: section.mark_offset.map(|v| v.as_points()).unwrap_or(bleed.max(MIN_MARK_OFFSET)),
+            line_width: section.mark_width.map(|v| v.as_points()).unwrap_or(DEFAULT_MARK_WIDTH),
+        };
+        if marks.bleed < 0.0 || marks.offset < 0.0 {
+            return Err(anyhow!("Bleed and mark offset must not be negative"));
+        }
+        if marks.length <= 0.0 || marks.line_width <= 0.0 {
+            return Err(anyhow!("Mark length and width must be positive"));
+        }
+        Ok(marks)
+    }
+
+    /// Whether any marks are drawn
+    pub fn draws_marks(&self) -> bool {
+        self.crop || self.registration
+    }
+
+    /// Space added around the trim box on every side
+    pub fn margin(&self) -> f64 {
+        let marks = if self.draws_marks() { self.offset + self.length } else { 0.0 };
+        self.bleed.max(marks)
+    }
+
+    /// Crop mark segments `[x0, y0, x1, y1]` around a trimmed area of
+    /// `width` by `height`, in display space
+    ///
+    /// `cuts_x` are the positions of vertical cuts and `cuts_y` of horizontal
+    /// cuts, measured from the bottom-left corner of the trimmed area. Marks
+    /// are drawn outside the trimmed area in line with every cut.
+    pub fn crop_lines(&self, width: f64, height: f64, cuts_x: &[f64], cuts_y: &[f64]) -> Vec<[f64; 4]> {
+        if !self.crop {
+            return Vec::new();
+        }
+        let (near, far) = (self.offset, self.offset + self.length);
+        let mut lines = Vec::new();
+        for &x in cuts_x {
+            lines.push([x, -near, x, -far]);
+            lines.push([x, height + near, x, height + far]);
+        }
+        for &y in cuts_y {
+            lines.push([-near, y, -far, y]);
+            lines.push([width + near, y, width + far, y]);
+        }
+        lines
+    }
+
+    /// Centres of the registration targets, one beyond the middle of each edge
+    pub fn registration_centres(&self, width: f64, height: f64) -> Vec<(f64, f64)> {
+        if !self.registration {
+            return Vec::new();
+        }
+        let distance = self.offset + self.length / 2.0;
+        vec![
+            (width / 2.0, height + distance),
+            (width / 2.0, -distance),
+            (-distance, height / 2.0),
+            (width + distance, height / 2.0),
+        ]
+    }
+
+    /// Set the MediaBox, CropBox, BleedBox and TrimBox of a page whose
+    /// trimmed area is `trim_box` in default user space
+    pub fn set_page_boxes(&self, page: &mut Dictionary, trim_box: [f64; 4]) {
+        let grow = |amount: f64| -> Vec<Object> {
+            let [x0, y0, x1, y1] = trim_box;
+            [x0 - amount, y0 - amount, x1 + amount, y1 + amount]
+                .iter()
+                .map(|v| Object::Real(*v as f32))
+                .collect()
+        };
+        page.set("MediaBox", grow(self.margin()));
+        page.set("CropBox", grow(self.margin()));
+        page.set("BleedBox", grow(self.bleed));
+        page.set("TrimBox", grow(0.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Dimension;
+
+    fn marks(bleed: f64) -> PrintMarks {
+        PrintMarks::from_section(&MarksSection {
+            bleed: Some(Dimension(bleed)),
+            mark_length: Some(Dimension(10.0)),
+            ..Default::default()
+        }).unwrap()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let marks = PrintMarks::from_section(&MarksSection::default()).unwrap();
+        assert_eq!(marks.bleed, 0.0);
+        assert!(marks.crop && !marks.registration);
+        assert!((marks.offset - 8.504).abs() < 0.01);
+        assert!((marks.length - 14.173).abs() < 0.01);
+        // The offset follows a bleed larger than 3 mm
+        assert_eq!(self::marks(20.0).offset, 20.0);
+    }
+
+    #[test]
+    fn test_margin() {
+        assert_eq!(marks(20.0).margin(), 30.0);
+        let bleed_only = PrintMarks { crop: false, ..marks(20.0) };
+        assert_eq!(bleed_only.margin(), 20.0);
+    }
+
+    #[test]
+    fn test_crop_lines_outside_trim() {
+        let marks = marks(20.0);
+        let lines = marks.crop_lines(100.0, 50.0, &[0.0, 100.0], &[0.0, 50.0]);
+        assert_eq!(lines.len(), 8);
+        assert!(lines.contains(&[0.0, -20.0, 0.0, -30.0]));
+        assert!(lines.contains(&[100.0, 70.0, 100.0, 80.0]));
+        assert!(lines.contains(&[-20.0, 50.0, -30.0, 50.0]));
+        assert!(lines.contains(&[120.0, 0.0, 130.0, 0.0]));
+    }
+
+    #[test]
+    fn test_registration_centres() {
+        let marks = PrintMarks { registration: true, ..marks(20.0) };
+        let centres = marks.registration_centres(100.0, 50.0);
+        assert_eq!(centres, vec![(50.0, 75.0), (50.0, -25.0), (-25.0, 25.0), (125.0, 25.0)]);
+    }
+
+    #[test]
+    fn test_set_page_boxes() {
+        let mut page = Dictionary::new();
+        marks(20.0).set_page_boxes(&mut page, [0.0, 0.0, 100.0, 50.0]);
+        let rect = |key: &[u8]| -> Vec<f32> {
+            page.get(key).unwrap().as_array().unwrap().iter().map(|v| v.as_float().unwrap()).collect()
+        };
+        assert_eq!(rect(b"MediaBox"), [-30.0, -30.0, 130.0, 80.0]);
+        assert_eq!(rect(b"BleedBox"), [-20.0, -20.0, 120.0, 70.0]);
+        assert_eq!(rect(b"TrimBox"), [0.0, 0.0, 100.0, 50.0]);
+    }
+
+    #[test]
+    fn test_invalid_marks() {
+        let section = MarksSection { bleed: Some(Dimension(-1.0)), ..Default::default() };
+        assert!(PrintMarks::from_section(&section).is_err());
+        let section = MarksSection { mark_length: Some(Dimension(0.0)), ..Default::default() };
+        assert!(PrintMarks::from_section(&section).is_err());
+    }
+}
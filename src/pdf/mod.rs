@@ -1,6 +1,23 @@
+mod aztec;
+mod barcode;
+mod calibration;
 mod content;
+mod datamatrix;
 mod document;
+mod eci;
 mod fonts;
+mod gs1;
+mod imposition;
+mod layout;
+mod marks;
+mod page;
+mod pdf417;
+mod pdf417_patterns;
+mod reed_solomon;
 mod resources;
+mod rmqr;
+mod sheet_presets;
+mod standard_metrics;
 
+pub use calibration::create_calibration_pdf;
 pub use document::create_output_pdf;
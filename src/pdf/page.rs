@@ -0,0 +1,236 @@
+//! Page geometry of the base template.
+//!
+//! This module handles:
+//! - Resolving page attributes inherited from the page tree
+//! - The visible page area (CropBox clipped to the MediaBox)
+//! - Mapping top-left field coordinates onto rotated and offset pages
+
+use anyhow::{anyhow, Context, Result};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+
+/// Deepest page tree walked when resolving inherited attributes
+const MAX_TREE_DEPTH: usize = 64;
+
+/// Look up a page attribute, following `/Parent` links for inheritable
+/// attributes (MediaBox, CropBox, Rotate, Resources)
+pub fn inherited_attribute<'a>(doc: &'a Document, page: &'a Dictionary, key: &[u8]) -> Option<&'a Object> {
+    let mut node = page;
+    for _ in 0..MAX_TREE_DEPTH {
+        if let Ok(value) = node.get(key) {
+            return doc.dereference(value).ok().map(|(_, object)| object);
+        }
+        let parent = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        node = doc.get_dictionary(parent).ok()?;
+    }
+    None
+}
+
+/// Read a rectangle array as `[llx, lly, urx, ury]` with normalised corners
+fn read_rectangle(doc: &Document, object: &Object) -> Result<[f64; 4]> {
+    let values = object.as_array()?
+        .iter()
+        .map(|value| {
+            let (_, value) = doc.dereference(value)?;
+            value.as_float().map(f64::from)
+        })
+        .collect::<lopdf::Result<Vec<f64>>>()?;
+    let [x0, y0, x1, y1] = values[..] else {
+        return Err(anyhow!("rectangle must have 4 numbers, found {}", values.len()));
+    };
+    Ok([x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)])
+}
+
+/// The visible area of a page and how it is turned for display
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageGeometry {
+    /// Visible area in default user space: `[llx, lly, urx, ury]`
+    pub visible_box: [f64; 4],
+    /// Clockwise display rotation in degrees: 0, 90, 180 or 270
+    pub rotate: i64,
+}
+
+impl PageGeometry {
+    /// Read the geometry of a page, including attributes inherited from the page tree
+    pub fn from_page(doc: &Document, page_id: ObjectId) -> Result<Self> {
+        let page = doc.get_dictionary(page_id)
+            .with_context(|| format!("Page {:?} is not a dictionary", page_id))?;
+
+        let media_box = inherited_attribute(doc, page, b"MediaBox")
+            .ok_or_else(|| anyhow!("Page has no MediaBox"))
+            .and_then(|object| read_rectangle(doc, object))
+            .with_context(|| "Invalid MediaBox")?;
+
+        // The CropBox defaults to the MediaBox and is clipped to it
+        let visible_box = match inherited_attribute(doc, page, b"CropBox") {
+            Some(object) => {
+                let crop_box = read_rectangle(doc, object).with_context(|| "Invalid CropBox")?;
+                [
+                    crop_box[0].max(media_box[0]),
+                    crop_box[1].max(media_box[1]),
+                    crop_box[2].min(media_box[2]),
+                    crop_box[3].min(media_box[3]),
+                ]
+            }
+            None => media_box,
+        };
+
+        let rotate = match inherited_attribute(doc, page, b"Rotate") {
+            Some(object) => object.as_i64().with_context(|| "Invalid Rotate")?,
+            None => 0,
+        };
+        if rotate % 90 != 0 {
+            return Err(anyhow!("Page /Rotate must be a multiple of 90, found {}", rotate));
+        }
+
+        Ok(Self { visible_box, rotate: rotate.rem_euclid(360) })
+    }
+
+    /// Whether the page is displayed turned a quarter turn
+    fn is_sideways(&self) -> bool {
+        self.rotate == 90 || self.rotate == 270
+    }
+
+    /// Width of the page as displayed
+    pub fn width(&self) -> f64 {
+        let [x0, y0, x1, y1] = self.visible_box;
+        if self.is_sideways() { y1 - y0 } else { x1 - x0 }
+    }
+
+    /// Height of the page as displayed
+    pub fn height(&self) -> f64 {
+        let [x0, y0, x1, y1] = self.visible_box;
+        if self.is_sideways() { x1 - x0 } else { y1 - y0 }
+    }
+
+    /// Matrix from display space to default user space
+    ///
+    /// Display space has its origin at the bottom-left corner of the page as
+    /// displayed (visible area, after rotation), with y pointing up. Field
+    /// content is generated in display space and placed with this matrix.
+    pub fn display_matrix(&self) -> [f64; 6] {
+        let [x0, y0, x1, y1] = self.visible_box;
+        match self.rotate {
+            90 => [0.0, 1.0, -1.0, 0.0, x1, y0],
+            180 => [-1.0, 0.0, 0.0, -1.0, x1, y1],
+            270 => [0.0, -1.0, 1.0, 0.0, x0, y1],
+            _ => [1.0, 0.0, 0.0, 1.0, x0, y0],
+        }
+    }
+
+    /// Matrix from default user space to display space (the inverse of
+    /// [`Self::display_matrix`])
+    pub fn user_to_display_matrix(&self) -> [f64; 6] {
+        let [a, b, c, d, e, f] = self.display_matrix();
+        // Rotations by multiples of 90 degrees have determinant 1
+        [a, c, b, d, 0.0 - (a * e + b * f), 0.0 - (c * e + d * f)]
+    }
+
+    /// Wrap content generated in display space so it lands on the visible page
+    pub fn place_content(&self, content: Vec<u8>) -> Vec<u8> {
+        let matrix = self.display_matrix();
+        if matrix == [1.0, 0.0, 0.0, 1.0, 0.0, 0.0] {
+            return content;
+        }
+        let matrix: Vec<String> = matrix.iter().map(|v| format!("{}", v)).collect();
+        let mut placed = format!("q {} cm ", matrix.join(" ")).into_bytes();
+        placed.extend(content);
+        placed.extend_from_slice(b"Q ");
+        placed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::dictionary;
+
+    /// Document with a page tree node carrying `inherited` and a page carrying `own`
+    fn page_doc(inherited: Dictionary, own: Dictionary) -> (Document, ObjectId) {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let mut page = own;
+        page.set("Type", "Page");
+        page.set("Parent", pages_id);
+        let page_id = doc.add_object(page);
+        let mut pages = inherited;
+        pages.set("Type", "Pages");
+        pages.set("Kids", vec![page_id.into()]);
+        pages.set("Count", 1);
+        doc.objects.insert(pages_id, Object::Dictionary(pages));
+        (doc, page_id)
+    }
+
+    /// Apply a matrix to a point
+    fn transform(m: [f64; 6], x: f64, y: f64) -> (f64, f64) {
+        (m[0] * x + m[2] * y + m[4], m[1] * x + m[3] * y + m[5])
+    }
+
+    #[test]
+    fn test_inherited_media_box_and_rotate() {
+        let (doc, page_id) = page_doc(
+            dictionary! { "MediaBox" => vec![0.into(), 0.into(), 600.into(), 800.into()], "Rotate" => 90 },
+            Dictionary::new(),
+        );
+        let geometry = PageGeometry::from_page(&doc, page_id).unwrap();
+        assert_eq!(geometry.visible_box, [0.0, 0.0, 600.0, 800.0]);
+        assert_eq!(geometry.rotate, 90);
+        assert_eq!(geometry.height(), 600.0);
+    }
+
+    #[test]
+    fn test_crop_box_with_offset_origin() {
+        let (doc, page_id) = page_doc(
+            Dictionary::new(),
+            dictionary! {
+                "MediaBox" => vec![(-50).into(), (-50).into(), 650.into(), 850.into()],
+                "CropBox" => vec![0.into(), 0.into(), 600.into(), 800.into()],
+                "Rotate" => -90,
+            },
+        );
+        let geometry = PageGeometry::from_page(&doc, page_id).unwrap();
+        assert_eq!(geometry.visible_box, [0.0, 0.0, 600.0, 800.0]);
+        assert_eq!(geometry.rotate, 270);
+    }
+
+    #[test]
+    fn test_missing_media_box() {
+        let (doc, page_id) = page_doc(Dictionary::new(), Dictionary::new());
+        assert!(PageGeometry::from_page(&doc, page_id).is_err());
+    }
+
+    #[test]
+    fn test_display_matrix_maps_top_left_corner() {
+        // The top-left corner of the displayed page is (0, height) in display space
+        let cases = [
+            (0, (10.0, 820.0)),
+            (90, (10.0, 20.0)),
+            (180, (610.0, 20.0)),
+            (270, (610.0, 820.0)),
+        ];
+        for (rotate, expected) in cases {
+            let geometry = PageGeometry { visible_box: [10.0, 20.0, 610.0, 820.0], rotate };
+            let top_left = transform(geometry.display_matrix(), 0.0, geometry.height());
+            assert_eq!(top_left, expected, "rotate {}", rotate);
+        }
+    }
+
+    #[test]
+    fn test_user_to_display_matrix_inverts_display_matrix() {
+        for rotate in [0, 90, 180, 270] {
+            let geometry = PageGeometry { visible_box: [10.0, 20.0, 610.0, 820.0], rotate };
+            let (x, y) = transform(geometry.display_matrix(), 30.0, 40.0);
+            assert_eq!(transform(geometry.user_to_display_matrix(), x, y), (30.0, 40.0), "rotate {}", rotate);
+        }
+        let sideways = PageGeometry { visible_box: [0.0, 0.0, 600.0, 800.0], rotate: 90 };
+        assert_eq!((sideways.width(), sideways.height()), (800.0, 600.0));
+    }
+
+    #[test]
+    fn test_place_content() {
+        let geometry = PageGeometry { visible_box: [0.0, 0.0, 600.0, 800.0], rotate: 0 };
+        assert_eq!(geometry.place_content(b"x ".to_vec()), b"x ");
+
+        let geometry = PageGeometry { visible_box: [10.0, 20.0, 600.0, 800.0], rotate: 0 };
+        assert_eq!(geometry.place_content(b"x ".to_vec()), b"q 1 0 0 1 10 20 cm x Q ");
+    }
+}
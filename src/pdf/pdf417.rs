@@ -0,0 +1,522 @@
+//! PDF417 encoding.
+//!
+//! This module handles:
+//! - Text, byte and numeric compaction of the data, with an ECI for text
+//!   outside Latin-1
+//! - Reed-Solomon error correction over GF(929) at security levels 0 to 8
+//! - Selection of the number of columns and rows
+//! - Start and stop patterns, row indicators and the codeword clusters
+
+use anyhow::{anyhow, Result};
+use super::eci::symbol_bytes;
+use super::pdf417_patterns::{CLUSTERS, START, STOP};
+use super::reed_solomon::GaloisField;
+
+/// Latch to text compaction, also used as the pad codeword
+const TEXT_LATCH: u16 = 900;
+
+/// Latch to byte compaction
+const BYTE_LATCH: u16 = 901;
+
+/// Latch to byte compaction for a multiple of six bytes
+const BYTE_LATCH_6: u16 = 924;
+
+/// Latch to numeric compaction
+const NUMERIC_LATCH: u16 = 902;
+
+/// Shift to byte compaction for the next codeword only, from text compaction
+const BYTE_SHIFT: u16 = 913;
+
+/// ECI designator, followed by the ECI number (for ECIs up to 899)
+const ECI: u16 = 927;
+
+/// Digit runs at least this long are numeric compacted
+const MIN_NUMERIC_RUN: usize = 13;
+
+/// Text runs shorter than this between binary data are byte compacted
+const MIN_TEXT_RUN: usize = 5;
+
+/// Digits per numeric compaction group
+const NUMERIC_GROUP: usize = 44;
+
+/// Limits of the symbol size
+const MAX_COLUMNS: usize = 30;
+const MIN_ROWS: usize = 3;
+const MAX_ROWS: usize = 90;
+
+/// Largest number of codewords, including the length descriptor
+const MAX_CODEWORDS: usize = 928;
+
+/// Height of each row, in modules
+const ROW_HEIGHT: usize = 3;
+
+/// Width to height ratio aimed for when neither columns nor rows are given
+const PREFERRED_RATIO: f64 = 3.0;
+
+/// Characters of the mixed sub-mode, codes 0-24 (26 is space)
+const MIXED: &[u8] = b"0123456789&\r\t,:#-.$/+%*=^";
+
+/// Characters of the punctuation sub-mode, codes 0-28
+const PUNCTUATION: &[u8] = b";<>@[\\]_`~!\r\t,:\n-.$/\"|*()?{}'";
+
+/// Text compaction sub-modes
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum SubMode {
+    Alpha,
+    Lower,
+    Mixed,
+    Punctuation,
+}
+
+/// Sub-mode value of the latch to lower case (alpha and mixed) or the shift
+/// to alpha (lower)
+const LL: u16 = 27;
+/// Sub-mode value of the latch to mixed (alpha and lower) or to alpha (mixed)
+const ML: u16 = 28;
+/// Sub-mode value of the shift to punctuation, also the pad value
+const PS: u16 = 29;
+/// Mixed sub-mode value of the latch to punctuation
+const PL: u16 = 25;
+/// Punctuation sub-mode value of the latch to alpha
+const AL: u16 = 29;
+
+fn mixed_code(b: u8) -> Option<u16> {
+    MIXED.iter().position(|&c| c == b).map(|i| i as u16)
+}
+
+fn punctuation_code(b: u8) -> Option<u16> {
+    PUNCTUATION.iter().position(|&c| c == b).map(|i| i as u16)
+}
+
+/// Whether text compaction can encode the byte
+fn is_text(b: u8) -> bool {
+    b == b' ' || b.is_ascii_alphabetic() || mixed_code(b).is_some() || punctuation_code(b).is_some()
+}
+
+/// Number of digits starting at `start`
+fn digit_run(bytes: &[u8], start: usize) -> usize {
+    bytes[start..].iter().take_while(|b| b.is_ascii_digit()).count()
+}
+
+/// Number of text bytes starting at `start`, up to a numeric run
+fn text_run(bytes: &[u8], start: usize) -> usize {
+    let mut end = start;
+    while end < bytes.len() && is_text(bytes[end]) {
+        if digit_run(bytes, end) >= MIN_NUMERIC_RUN {
+            break;
+        }
+        end += 1;
+    }
+    end - start
+}
+
+/// Number of bytes starting at `start` that are best byte compacted
+fn binary_run(bytes: &[u8], start: usize) -> usize {
+    let mut end = start + 1;
+    while end < bytes.len()
+        && digit_run(bytes, end) < MIN_NUMERIC_RUN
+        && text_run(bytes, end) < MIN_TEXT_RUN
+    {
+        end += 1;
+    }
+    end - start
+}
+
+/// Text compaction of `text`, starting in `sub_mode`
+///
+/// Values are paired into codewords, padding an odd count with PS. Returns
+/// the sub-mode in effect at the end.
+fn encode_text(text: &[u8], mut sub_mode: SubMode, codewords: &mut Vec<u16>) -> SubMode {
+    let mut values = Vec::new();
+    let mut i = 0;
+    while i < text.len() {
+        let b = text[i];
+        match sub_mode {
+            SubMode::Alpha => {
+                if b.is_ascii_uppercase() {
+                    values.push((b - b'A') as u16);
+                } else if b == b' ' {
+                    values.push(26);
+                } else if b.is_ascii_lowercase() {
+                    values.push(LL);
+                    sub_mode = SubMode::Lower;
+                    continue;
+                } else if mixed_code(b).is_some() {
+                    values.push(ML);
+                    sub_mode = SubMode::Mixed;
+                    continue;
+                } else {
+                    values.extend([PS, punctuation_code(b).unwrap_or(0)]);
+                }
+            }
+            SubMode::Lower => {
+                if b.is_ascii_lowercase() {
+                    values.push((b - b'a') as u16);
+                } else if b == b' ' {
+                    values.push(26);
+                } else if b.is_ascii_uppercase() {
+                    // Alpha shift, for this character only
+                    values.extend([LL, (b - b'A') as u16]);
+                } else if mixed_code(b).is_some() {
+                    values.push(ML);
+                    sub_mode = SubMode::Mixed;
+                    continue;
+                } else {
+                    values.extend([PS, punctuation_code(b).unwrap_or(0)]);
+                }
+            }
+            SubMode::Mixed => {
+                if let Some(code) = mixed_code(b) {
+                    values.push(code);
+                } else if b == b' ' {
+                    values.push(26);
+                } else if b.is_ascii_uppercase() {
+                    values.push(ML);
+                    sub_mode = SubMode::Alpha;
+                    continue;
+                } else if b.is_ascii_lowercase() {
+                    values.push(LL);
+                    sub_mode = SubMode::Lower;
+                    continue;
+                } else if text.get(i + 1).is_some_and(|&next| punctuation_code(next).is_some()) {
+                    values.push(PL);
+                    sub_mode = SubMode::Punctuation;
+                    continue;
+                } else {
+                    values.extend([PS, punctuation_code(b).unwrap_or(0)]);
+                }
+            }
+            SubMode::Punctuation => {
+                if let Some(code) = punctuation_code(b) {
+                    values.push(code);
+                } else {
+                    values.push(AL);
+                    sub_mode = SubMode::Alpha;
+                    continue;
+                }
+            }
+        }
+        i += 1;
+    }
+    if values.len() % 2 == 1 {
+        // The pad is a latch to alpha in the punctuation sub-mode
+        values.push(PS);
+        if sub_mode == SubMode::Punctuation {
+            sub_mode = SubMode::Alpha;
+        }
+    }
+    codewords.extend(values.chunks(2).map(|pair| pair[0] * 30 + pair[1]));
+    sub_mode
+}
+
+/// Byte compaction of `bytes`: five codewords per six bytes, one codeword
+/// per remaining byte
+fn encode_bytes(bytes: &[u8], codewords: &mut Vec<u16>) {
+    let groups = bytes.chunks_exact(6);
+    let remainder = groups.remainder();
+    for group in groups {
+        let mut value = group.iter().fold(0u64, |acc, &b| acc << 8 | b as u64);
+        let mut chunk = [0u16; 5];
+        for word in chunk.iter_mut().rev() {
+            *word = (value % 900) as u16;
+            value /= 900;
+        }
+        codewords.extend(chunk);
+    }
+    codewords.extend(remainder.iter().map(|&b| b as u16));
+}
+
+/// Numeric compaction of `digits`: each group of up to 44 digits, with a
+/// leading 1, in base 900
+fn encode_numeric(digits: &[u8], codewords: &mut Vec<u16>) {
+    for group in digits.chunks(NUMERIC_GROUP) {
+        let mut number: Vec<u32> = std::iter::once(1)
+            .chain(group.iter().map(|d| (d - b'0') as u32))
+            .collect();
+        let mut words = Vec::new();
+        while number.iter().any(|&d| d != 0) {
+            let mut remainder = 0;
+            for d in number.iter_mut() {
+                let value = remainder * 10 + *d;
+                *d = value / 900;
+                remainder = value % 900;
+            }
+            words.push(remainder as u16);
+        }
+        codewords.extend(words.into_iter().rev());
+    }
+}
+
+/// Compaction modes
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Text,
+    Byte,
+    Numeric,
+}
+
+/// Data codewords of `bytes`, without the length descriptor
+///
+/// Long digit runs are numeric compacted, text runs text compacted and the
+/// rest byte compacted, with a byte shift for single bytes within text.
+fn encode_codewords(bytes: &[u8], eci: Option<u32>) -> Vec<u16> {
+    let mut codewords = Vec::new();
+    if let Some(eci) = eci {
+        codewords.extend([ECI, eci as u16]);
+    }
+    // Symbols start in text compaction, alpha sub-mode
+    let mut mode = Mode::Text;
+    let mut sub_mode = SubMode::Alpha;
+    let mut p = 0;
+    while p < bytes.len() {
+        let digits = digit_run(bytes, p);
+        if digits >= MIN_NUMERIC_RUN {
+            codewords.push(NUMERIC_LATCH);
+            mode = Mode::Numeric;
+            encode_numeric(&bytes[p..p + digits], &mut codewords);
+            p += digits;
+            continue;
+        }
+        let text = text_run(bytes, p);
+        if text >= MIN_TEXT_RUN || (text > 0 && p + text == bytes.len()) {
+            if mode != Mode::Text {
+                codewords.push(TEXT_LATCH);
+                mode = Mode::Text;
+                sub_mode = SubMode::Alpha;
+            }
+            sub_mode = encode_text(&bytes[p..p + text], sub_mode, &mut codewords);
+            p += text;
+            continue;
+        }
+        let binary = binary_run(bytes, p);
+        if binary == 1 && mode == Mode::Text {
+            codewords.extend([BYTE_SHIFT, bytes[p] as u16]);
+        } else {
+            codewords.push(if binary.is_multiple_of(6) { BYTE_LATCH_6 } else { BYTE_LATCH });
+            mode = Mode::Byte;
+            encode_bytes(&bytes[p..p + binary], &mut codewords);
+        }
+        p += binary;
+    }
+    codewords
+}
+
+/// Recommended security level for a number of data codewords
+fn default_security_level(data_codewords: usize) -> u8 {
+    match data_codewords {
+        0..=40 => 2,
+        41..=160 => 3,
+        161..=320 => 4,
+        _ => 5,
+    }
+}
+
+/// Columns and rows holding `needed` codewords, from the requested ones or
+/// the size closest to the preferred width to height ratio
+fn choose_size(needed: usize, columns: Option<usize>, rows: Option<usize>) -> Result<(usize, usize)> {
+    if let Some(columns) = columns && !(1..=MAX_COLUMNS).contains(&columns) {
+        return Err(anyhow!("PDF417 columns must be 1 to {}, got {}", MAX_COLUMNS, columns));
+    }
+    if let Some(rows) = rows && !(MIN_ROWS..=MAX_ROWS).contains(&rows) {
+        return Err(anyhow!("PDF417 rows must be {} to {}, got {}", MIN_ROWS, MAX_ROWS, rows));
+    }
+    let rows_for = |columns: usize| needed.div_ceil(columns).max(MIN_ROWS);
+    let size = match (columns, rows) {
+        (Some(columns), Some(rows)) => Some((columns, rows)),
+        (Some(columns), None) => Some((columns, rows_for(columns))),
+        (None, Some(rows)) => Some((needed.div_ceil(rows).max(1), rows)),
+        (None, None) => (1..=MAX_COLUMNS)
+            .map(|columns| (columns, rows_for(columns)))
+            .filter(|&(_, rows)| rows <= MAX_ROWS)
+            .min_by(|a, b| {
+                let ratio = |(columns, rows): (usize, usize)| {
+                    let width = (17 * columns + 69) as f64;
+                    ((width / (rows * ROW_HEIGHT) as f64) - PREFERRED_RATIO).abs()
+                };
+                ratio(*a).total_cmp(&ratio(*b))
+            }),
+    };
+    match size {
+        Some((columns, rows)) if columns <= MAX_COLUMNS && rows <= MAX_ROWS && columns * rows >= needed => {
+            Ok((columns, rows))
+        }
+        _ => Err(anyhow!("Data for PDF417 is too long: {} codewords do not fit the symbol size", needed)),
+    }
+}
+
+/// Left and right row indicator values of `row`
+fn row_indicators(row: usize, rows: usize, columns: usize, security_level: usize) -> (usize, usize) {
+    let base = 30 * (row / 3);
+    let row_count = base + (rows - 1) / 3;
+    let level = base + 3 * security_level + (rows - 1) % 3;
+    let column_count = base + columns - 1;
+    match row % 3 {
+        0 => (row_count, column_count),
+        1 => (level, row_count),
+        _ => (column_count, level),
+    }
+}
+
+/// Append the `width` modules of `pattern`, most significant bit first
+fn push_pattern(line: &mut Vec<bool>, pattern: u32, width: usize) {
+    line.extend((0..width).rev().map(|bit| pattern >> bit & 1 == 1));
+}
+
+/// Encode `data` as a PDF417 symbol
+///
+/// Returns the width, height and row-major dark modules (top row first),
+/// each row being three modules high. `columns` and `rows` force the number
+/// of data columns (1-30) and rows (3-90), otherwise a symbol about three
+/// times as wide as high is chosen. `security_level` (0-8) defaults to the
+/// level recommended for the data size.
+pub fn encode(
+    data: &str,
+    columns: Option<usize>,
+    rows: Option<usize>,
+    security_level: Option<u8>,
+) -> Result<(usize, usize, Vec<bool>)> {
+    if data.is_empty() {
+        return Err(anyhow!("PDF417 data must not be empty"));
+    }
+    let (bytes, eci) = symbol_bytes(data);
+    let mut codewords = encode_codewords(&bytes, eci);
+    let security_level = security_level.unwrap_or_else(|| default_security_level(codewords.len() + 1));
+    if security_level > 8 {
+        return Err(anyhow!("PDF417 security level must be 0 to 8, got {}", security_level));
+    }
+    let ec_count = 2 << security_level;
+    if codewords.len() + 1 > MAX_CODEWORDS - ec_count {
+        return Err(anyhow!(
+            "Data for PDF417 is too long: {} codewords, at most {} at security level {}",
+            codewords.len() + 1, MAX_CODEWORDS - ec_count, security_level
+        ));
+    }
+    let (columns, rows) = choose_size(codewords.len() + 1 + ec_count, columns, rows)?;
+
+    // Length descriptor, data and padding fill all but the check codewords
+    let data_count = columns * rows - ec_count;
+    if data_count > MAX_CODEWORDS {
+        return Err(anyhow!(
+            "PDF417 symbol of {} columns and {} rows holds more than {} data codewords",
+            columns, rows, MAX_CODEWORDS
+        ));
+    }
+    codewords.insert(0, data_count as u16);
+    codewords.resize(data_count, TEXT_LATCH);
+    let field = GaloisField::prime(929, 3);
+    codewords.extend(field.check_codewords(&codewords, ec_count, 1));
+
+    let width = 17 * columns + 69;
+    let mut dark = Vec::with_capacity(width * rows * ROW_HEIGHT);
+    for (row, row_codewords) in codewords.chunks(columns).enumerate() {
+        let cluster = &CLUSTERS[row % 3];
+        let (left, right) = row_indicators(row, rows, columns, security_level as usize);
+        let mut line = Vec::with_capacity(width);
+        push_pattern(&mut line, START, 17);
+        push_pattern(&mut line, cluster[left], 17);
+        for &codeword in row_codewords {
+            push_pattern(&mut line, cluster[codeword as usize], 17);
+        }
+        push_pattern(&mut line, cluster[right], 17);
+        push_pattern(&mut line, STOP, 18);
+        for _ in 0..ROW_HEIGHT {
+            dark.extend_from_slice(&line);
+        }
+    }
+    Ok((width, rows * ROW_HEIGHT, dark))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_compaction() {
+        assert_eq!(encode_codewords(b"PDF417", None), vec![453, 178, 121, 239]);
+        // Lower case latch, then an alpha shift for a single capital
+        assert_eq!(encode_codewords(b"abcDe", None), vec![27 * 30, 30 + 2, 27 * 30 + 3, 4 * 30 + 29]);
+        // Two punctuation characters latch from mixed to punctuation
+        assert_eq!(encode_codewords(b"1?!", None), vec![ML * 30 + 1, PL * 30 + 25, 10 * 30 + PS]);
+    }
+
+    #[test]
+    fn test_numeric_compaction() {
+        assert_eq!(
+            encode_codewords(b"000213298174000", None),
+            vec![NUMERIC_LATCH, 1, 624, 434, 632, 282, 200]
+        );
+        // Text after a numeric run latches back to text compaction
+        let codewords = encode_codewords(b"1234567890123AB", None);
+        assert_eq!(codewords[0], NUMERIC_LATCH);
+        assert_eq!(&codewords[codewords.len() - 2..], &[TEXT_LATCH, 1]);
+    }
+
+    #[test]
+    fn test_byte_compaction() {
+        // A single byte within text is shifted
+        assert_eq!(encode_codewords(b"ABCDE\xE9FGHIJ", None)[3..5], [BYTE_SHIFT, 0xE9]);
+        // Six bytes take five codewords after the multiple-of-six latch
+        assert_eq!(encode_codewords(b"\x01\x02\x03\x04\x05\x06", None), vec![BYTE_LATCH_6, 1, 620, 89, 74, 846]);
+        assert_eq!(encode_codewords(b"\x80\x81", None), vec![BYTE_LATCH, 0x80, 0x81]);
+    }
+
+    #[test]
+    fn test_eci() {
+        let (bytes, eci) = symbol_bytes("東京");
+        let codewords = encode_codewords(&bytes, eci);
+        assert_eq!(&codewords[..3], &[ECI, 26, BYTE_LATCH_6]);
+        assert_eq!(codewords.len(), 8);
+    }
+
+    #[test]
+    fn test_check_codewords() {
+        // Security level 1 example of ISO/IEC 15438 annex Q
+        let field = GaloisField::prime(929, 3);
+        assert_eq!(field.check_codewords(&[5, 453, 178, 121, 239], 4, 1), vec![452, 327, 657, 619]);
+    }
+
+    #[test]
+    fn test_row_indicators() {
+        // 10 rows, 4 columns, security level 2
+        assert_eq!(row_indicators(0, 10, 4, 2), (3, 3));
+        assert_eq!(row_indicators(1, 10, 4, 2), (6, 3));
+        assert_eq!(row_indicators(2, 10, 4, 2), (3, 6));
+        assert_eq!(row_indicators(3, 10, 4, 2), (33, 33));
+    }
+
+    #[test]
+    fn test_choose_size() {
+        assert_eq!(choose_size(20, Some(2), None).unwrap(), (2, 10));
+        assert_eq!(choose_size(20, None, Some(5)).unwrap(), (4, 5));
+        assert_eq!(choose_size(4, Some(5), None).unwrap(), (5, 3));
+        assert!(choose_size(20, Some(2), Some(5)).is_err());
+        assert!(choose_size(20, Some(31), None).is_err());
+        let (columns, rows) = choose_size(100, None, None).unwrap();
+        assert!(columns * rows >= 100);
+        assert!((17 * columns + 69) as f64 / (rows * ROW_HEIGHT) as f64 > 2.0);
+    }
+
+    #[test]
+    fn test_encode() {
+        let (width, height, dark) = encode("PDF417", Some(1), None, Some(1)).unwrap();
+        // 1 + 4 data codewords and 4 check codewords in one column
+        assert_eq!((width, height), (17 + 69, 9 * ROW_HEIGHT));
+        assert_eq!(dark.len(), width * height);
+        // Every row starts with the start pattern and ends with the stop pattern
+        let start: Vec<bool> = (0..17).rev().map(|bit| START >> bit & 1 == 1).collect();
+        let stop: Vec<bool> = (0..18).rev().map(|bit| STOP >> bit & 1 == 1).collect();
+        for row in dark.chunks(width) {
+            assert_eq!(&row[..17], start.as_slice());
+            assert_eq!(&row[width - 18..], stop.as_slice());
+        }
+        // The seventh row holds the second check codeword
+        let module = |x: usize| dark[ROW_HEIGHT * 6 * width + 34 + x];
+        let codeword: Vec<bool> = (0..17).map(module).collect();
+        let expected: Vec<bool> = (0..17).rev().map(|bit| CLUSTERS[0][327] >> bit & 1 == 1).collect();
+        assert_eq!(codeword, expected);
+
+        assert!(encode("", None, None, None).is_err());
+        assert!(encode("PDF417", None, None, Some(9)).is_err());
+        assert!(encode(&"\u{1}".repeat(1200), None, None, None).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Reed-Solomon error correction over GF(2^m) and GF(p).
+//!
+//! This module provides the Galois field arithmetic and check codeword
+//! generation shared by the Data Matrix, Aztec, rMQR and PDF417 encoders.
+
+/// Galois field GF(2^m) or GF(p) with log and antilog tables
+pub struct GaloisField {
+    exp: Vec<u16>,
+    log: Vec<u16>,
+    /// The prime of a prime field, or `None` for GF(2^m)
+    prime: Option<u16>,
+}
+
+impl GaloisField {
+    /// Field of `2^bits` elements generated by the primitive polynomial `poly`
+    pub fn new(bits: u32, poly: u32) -> Self {
+        let size = 1usize << bits;
+        let mut exp = vec![0u16; size];
+        let mut log = vec![0u16; size];
+        let mut x = 1u32;
+        for (i, value) in exp.iter_mut().enumerate() {
+            *value = x as u16;
+            x <<= 1;
+            if x >= size as u32 {
+                x ^= poly;
+            }
+            if i < size - 1 {
+                log[*value as usize] = i as u16;
+            }
+        }
+        Self { exp, log, prime: None }
+    }
+
+    /// Prime field GF(`prime`) with the primitive element `generator`, such
+    /// as GF(929) with generator 3 for PDF417
+    pub fn prime(prime: u16, generator: u16) -> Self {
+        let size = prime as usize;
+        let mut exp = vec![0u16; size];
+        let mut log = vec![0u16; size];
+        let mut x = 1u32;
+        for (i, value) in exp.iter_mut().enumerate() {
+            *value = x as u16;
+            x = x * generator as u32 % prime as u32;
+            if i < size - 1 {
+                log[*value as usize] = i as u16;
+            }
+        }
+        Self { exp, log, prime: Some(prime) }
+    }
+
+    /// Number of non-zero elements
+    fn order(&self) -> usize {
+        self.exp.len() - 1
+    }
+
+    pub fn mul(&self, a: u16, b: u16) -> u16 {
+        if a == 0 || b == 0 {
+            return 0;
+        }
+        self.exp[(self.log[a as usize] as usize + self.log[b as usize] as usize) % self.order()]
+    }
+
+    /// The generator raised to `power`
+    pub fn pow(&self, power: usize) -> u16 {
+        self.exp[power % self.order()]
+    }
+
+    /// Sum of two elements, which is XOR in GF(2^m)
+    pub fn add(&self, a: u16, b: u16) -> u16 {
+        match self.prime {
+            Some(prime) => ((a as u32 + b as u32) % prime as u32) as u16,
+            None => a ^ b,
+        }
+    }
+
+    /// Additive inverse, which is the element itself in GF(2^m)
+    pub fn neg(&self, a: u16) -> u16 {
+        match self.prime {
+            Some(prime) => (prime - a) % prime,
+            None => a,
+        }
+    }
+
+    /// `count` check codewords for `data`, with the generator polynomial
+    /// having the roots α^first_root .. α^(first_root + count - 1)
+    ///
+    /// The check codewords are the negated remainder of `data * x^count`
+    /// divided by the generator, so the whole codeword is divisible by it.
+    pub fn check_codewords(&self, data: &[u16], count: usize, first_root: usize) -> Vec<u16> {
+        // Generator coefficients, highest power first
+        let mut generator = vec![1u16];
+        for i in 0..count {
+            let root = self.neg(self.pow(first_root + i));
+            let mut next = vec![0u16; generator.len() + 1];
+            for (j, &coefficient) in generator.iter().enumerate() {
+                next[j] = self.add(next[j], coefficient);
+                next[j + 1] = self.add(next[j + 1], self.mul(coefficient, root));
+            }
+            generator = next;
+        }
+
+        let mut remainder = vec![0u16; count];
+        for &value in data {
+            let factor = self.add(value, remainder[0]);
+            remainder.rotate_left(1);
+            remainder[count - 1] = 0;
+            for (r, &g) in remainder.iter_mut().zip(&generator[1..]) {
+                *r = self.add(*r, self.neg(self.mul(g, factor)));
+            }
+        }
+        remainder.into_iter().map(|r| self.neg(r)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Evaluate a polynomial (highest power first) at x
+    fn evaluate(field: &GaloisField, coefficients: &[u16], x: u16) -> u16 {
+        coefficients.iter().fold(0, |acc, &c| field.add(field.mul(acc, x), c))
+    }
+
+    #[test]
+    fn test_field_tables() {
+        let field = GaloisField::new(8, 0x12D);
+        assert_eq!(field.pow(8), 0x2D);
+        assert_eq!(field.mul(field.pow(200), field.pow(100)), field.pow(300 % 255));
+        assert_eq!(field.mul(0, 7), 0);
+    }
+
+    #[test]
+    fn test_codewords_are_divisible_by_generator() {
+        for (bits, poly) in [(4, 0x13), (6, 0x43), (8, 0x12D), (10, 0x409), (12, 0x1069)] {
+            let field = GaloisField::new(bits, poly);
+            let data: Vec<u16> = (1..12).map(|v| v * 3 % (1 << bits)).collect();
+            let mut codeword = data.clone();
+            codeword.extend(field.check_codewords(&data, 6, 1));
+            for i in 1..=6 {
+                assert_eq!(evaluate(&field, &codeword, field.pow(i)), 0, "GF(2^{}) root {}", bits, i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_prime_field() {
+        let field = GaloisField::prime(929, 3);
+        assert_eq!(field.pow(1), 3);
+        assert_eq!(field.pow(928), 1);
+        assert_eq!(field.mul(field.pow(500), field.pow(600)), field.pow(1100 % 928));
+        assert_eq!(field.add(900, 100), 71);
+        assert_eq!(field.neg(0), 0);
+
+        let data = [5, 453, 178, 121, 239];
+        let mut codeword = data.to_vec();
+        codeword.extend(field.check_codewords(&data, 8, 1));
+        for i in 1..=8 {
+            assert_eq!(evaluate(&field, &codeword, field.pow(i)), 0, "root {}", i);
+        }
+    }
+}
@@ -3,66 +3,186 @@
 //! This module handles updating page resources dictionaries with:
 //! - Font references (standard and CID fonts)
 //! - XObject references (images, QR codes)
+//! - Graphics state parameter dictionaries (opacity)
+//! - Colour spaces (spot colours)
+//! - Names already used by base page resources, which generated names avoid
 
+use anyhow::{anyhow, Result};
 use lopdf::{Dictionary, Document, Object};
+use std::collections::HashSet;
+use super::page::inherited_attribute;
 
-/// Update a page's resources dictionary with multiple fonts and XObjects
+/// Update a page's resources dictionary with fonts, XObjects, graphics states
+/// and colour spaces
 ///
-/// This function allows adding both a regular font and a CID font
+/// The page's effective resources (an indirect object, an inline dictionary or
+/// inherited from the page tree) are copied into a new inline dictionary owned
+/// by the page, and entries from `font_dict`, `xobject_dict`,
+/// `ext_gstate_dict` and `color_space_dict` are merged into its Font, XObject,
+/// ExtGState and ColorSpace resources. Resources shared with other pages are
+/// never modified.
 pub fn update_page_resources_with_fonts(
     doc: &mut Document,
     page_id: (u32, u16),
-    regular_font_id: (u32, u16),
-    regular_font_name: &str,
-    cid_font_id: Option<(u32, u16)>,
-    cid_font_name: Option<&str>,
+    font_dict: &Dictionary,
     xobject_dict: &Dictionary,
-) {
-    // Get the page's resources
-    let resources_id = doc.get_object(page_id)
+    ext_gstate_dict: &Dictionary,
+    color_space_dict: &Dictionary,
+) -> Result<()> {
+    let page = doc.get_dictionary(page_id)
+        .map_err(|_| anyhow!("Page {:?} not found", page_id))?;
+
+    let mut resources = inherited_attribute(doc, page, b"Resources")
+        .and_then(|r| r.as_dict().ok())
+        .cloned()
+        .unwrap_or_default();
+    merge_resource_dict(doc, &mut resources, b"Font", font_dict);
+    merge_resource_dict(doc, &mut resources, b"XObject", xobject_dict);
+    merge_resource_dict(doc, &mut resources, b"ExtGState", ext_gstate_dict);
+    merge_resource_dict(doc, &mut resources, b"ColorSpace", color_space_dict);
+
+    doc.get_dictionary_mut(page_id)?
+        .set("Resources", Object::Dictionary(resources));
+    Ok(())
+}
+
+/// Names of the entries in every category of a page's resources
+///
+/// `resources` is the page's Resources value, either a dictionary or a
+/// reference to one.
+pub fn resource_names(doc: &Document, resources: &Object) -> HashSet<String> {
+    fn dict<'a>(doc: &'a Document, value: &'a Object) -> Option<&'a Dictionary> {
+        doc.dereference(value).ok().and_then(|(_, value)| value.as_dict().ok())
+    }
+    let Some(resources) = dict(doc, resources) else {
+        return HashSet::new();
+    };
+    resources.iter()
+        .filter_map(|(_, category)| dict(doc, category))
+        .flat_map(|category| category.iter().map(|(name, _)| String::from_utf8_lossy(name).into_owned()))
+        .collect()
+}
+
+/// Merge `entries` into the `category` sub-dictionary of a resources dictionary
+///
+/// An existing sub-dictionary stored as an indirect object is copied rather
+/// than modified.
+fn merge_resource_dict(doc: &Document, resources: &mut Dictionary, category: &[u8], entries: &Dictionary) {
+    if entries.is_empty() {
+        return;
+    }
+    let mut merged = resources.get(category)
         .ok()
-        .and_then(|page| page.as_dict().ok())
-        .and_then(|dict| dict.get(b"Resources").ok())
-        .and_then(|r| r.as_reference().ok());
-
-    if let Some(res_id) = resources_id {
-        // Get the existing font dictionary first (before mutable borrow)
-        let font_dict_to_clone = if let Ok(res) = doc.get_dictionary(res_id) {
-            match res.get(b"Font") {
-                Ok(Object::Reference(font_dict_id)) => {
-                    doc.get_dictionary(*font_dict_id).cloned().ok()
-                }
-                Ok(Object::Dictionary(d)) => Some(d.clone()),
-                _ => None,
-            }
-        } else {
-            None
-        };
-
-        // Now modify the resources
-        if let Ok(res) = doc.get_dictionary_mut(res_id) {
-            let mut font_resources = font_dict_to_clone.unwrap_or_else(Dictionary::new);
-            font_resources.set(regular_font_name, Object::Reference(regular_font_id));
-
-            // Add CID font if provided
-            if let (Some(cid_id), Some(cid_name)) = (cid_font_id, cid_font_name) {
-                font_resources.set(cid_name, Object::Reference(cid_id));
-            }
-
-            res.set("Font", Object::Dictionary(font_resources));
-
-            // Add XObject resources
-            if !xobject_dict.is_empty() {
-                let mut xobject_resources = if let Ok(xobj) = res.get(b"XObject").and_then(|x| x.as_dict()) {
-                    xobj.clone()
-                } else {
-                    Dictionary::new()
-                };
-                for (key, value) in xobject_dict.iter() {
-                    xobject_resources.set(key.to_vec(), value.clone());
-                }
-                res.set("XObject", Object::Dictionary(xobject_resources));
-            }
-        }
+        .and_then(|value| doc.dereference(value).ok())
+        .and_then(|(_, value)| value.as_dict().ok())
+        .cloned()
+        .unwrap_or_default();
+    for (key, value) in entries.iter() {
+        merged.set(key.to_vec(), value.clone());
+    }
+    resources.set(category.to_vec(), Object::Dictionary(merged));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::dictionary;
+
+    /// Add a Pages node holding `pages_extra` and one page holding `page_extra` to `doc`
+    fn page_doc(
+        mut doc: Document,
+        pages_extra: Dictionary,
+        page_extra: Dictionary,
+    ) -> (Document, (u32, u16)) {
+        let pages_id = doc.new_object_id();
+        let mut page = page_extra;
+        page.set("Type", "Page");
+        page.set("Parent", pages_id);
+        let page_id = doc.add_object(page);
+        let mut pages = pages_extra;
+        pages.set("Type", "Pages");
+        pages.set("Kids", vec![page_id.into()]);
+        pages.set("Count", 1);
+        doc.objects.insert(pages_id, Object::Dictionary(pages));
+        (doc, page_id)
+    }
+
+    fn page_resources(doc: &Document, page_id: (u32, u16)) -> Dictionary {
+        doc.get_dictionary(page_id).unwrap()
+            .get(b"Resources").unwrap()
+            .as_dict().unwrap()
+            .clone()
+    }
+
+    fn fonts() -> Dictionary {
+        dictionary! { "F9" => Object::Reference((90, 0)) }
+    }
+
+    #[test]
+    fn test_inline_resources() {
+        let (mut doc, page_id) = page_doc(
+            Document::with_version("1.5"),
+            Dictionary::new(),
+            dictionary! { "Resources" => dictionary! { "Font" => dictionary! { "F1" => Object::Reference((10, 0)) } } },
+        );
+        let xobjects = dictionary! { "Fm1" => Object::Reference((20, 0)) };
+        update_page_resources_with_fonts(&mut doc, page_id, &fonts(), &xobjects, &Dictionary::new(), &Dictionary::new()).unwrap();
+
+        let resources = page_resources(&doc, page_id);
+        let font = resources.get(b"Font").unwrap().as_dict().unwrap();
+        assert!(font.has(b"F1") && font.has(b"F9"));
+        assert!(resources.get(b"XObject").unwrap().as_dict().unwrap().has(b"Fm1"));
+        assert!(!resources.has(b"ExtGState"));
+    }
+
+    #[test]
+    fn test_inherited_resources() {
+        let (mut doc, page_id) = page_doc(
+            Document::with_version("1.5"),
+            dictionary! { "Resources" => dictionary! { "ProcSet" => vec!["PDF".into()] } },
+            Dictionary::new(),
+        );
+        update_page_resources_with_fonts(&mut doc, page_id, &fonts(), &Dictionary::new(), &Dictionary::new(), &Dictionary::new()).unwrap();
+
+        let resources = page_resources(&doc, page_id);
+        assert!(resources.has(b"ProcSet"));
+        assert!(resources.get(b"Font").unwrap().as_dict().unwrap().has(b"F9"));
+    }
+
+    #[test]
+    fn test_resource_names() {
+        let mut doc = Document::with_version("1.5");
+        let xobjects_id = doc.add_object(dictionary! { "Fm12" => Object::Reference((12, 0)) });
+        let resources = Object::Dictionary(dictionary! {
+            "Font" => dictionary! { "F1" => Object::Reference((10, 0)) },
+            "XObject" => Object::Reference(xobjects_id),
+            "ExtGState" => dictionary! { "GS500" => Object::Reference((11, 0)) },
+            "ProcSet" => vec!["PDF".into()],
+        });
+
+        let names = resource_names(&doc, &resources);
+        let expected = ["F1", "Fm12", "GS500"].map(str::to_string);
+        assert_eq!(names, HashSet::from(expected));
+    }
+
+    #[test]
+    fn test_shared_indirect_resources_are_not_modified() {
+        let mut doc = Document::with_version("1.5");
+        let font_dict_id = doc.add_object(dictionary! { "F1" => Object::Reference((10, 0)) });
+        let shared_id = doc.add_object(dictionary! { "Font" => Object::Reference(font_dict_id) });
+        let (mut doc, page_id) = page_doc(
+            doc,
+            Dictionary::new(),
+            dictionary! { "Resources" => Object::Reference(shared_id) },
+        );
+
+        update_page_resources_with_fonts(&mut doc, page_id, &fonts(), &Dictionary::new(), &Dictionary::new(), &Dictionary::new()).unwrap();
+
+        let resources = page_resources(&doc, page_id);
+        let font = resources.get(b"Font").unwrap().as_dict().unwrap();
+        assert!(font.has(b"F1") && font.has(b"F9"));
+        // The shared objects keep their original entries
+        assert!(doc.get_dictionary(shared_id).unwrap().get(b"Font").unwrap().as_reference().is_ok());
+        assert!(!doc.get_dictionary(font_dict_id).unwrap().has(b"F9"));
     }
 }
@@ -0,0 +1,524 @@
+//! Rectangular Micro QR Code (rMQR) encoding.
+//!
+//! This module handles:
+//! - Numeric, alphanumeric and byte mode data encoding, with an ECI for text
+//!   outside Latin-1
+//! - Selection of the shortest of the 32 symbol sizes that fits the data
+//! - Reed-Solomon error correction with interleaved blocks
+//! - Finder, alignment and timing patterns, format information and the data mask
+
+use anyhow::{anyhow, Result};
+use crate::config::EcLevel;
+use super::eci::symbol_bytes;
+use super::reed_solomon::GaloisField;
+
+/// Characters of the alphanumeric mode, in code order
+const ALPHANUMERIC: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+/// ECI mode indicator, followed by the 8-bit designator of ECIs up to 127
+const ECI_MODE: u32 = 0b111;
+
+/// BCH generator of the format information
+const FORMAT_GENERATOR: u32 = 0x1F25;
+
+/// Masks of the format information next to the finder and the sub-pattern
+const FORMAT_MASK_FINDER: u32 = 0x1FAB2;
+const FORMAT_MASK_SUB_PATTERN: u32 = 0x20A7B;
+
+/// Error correction of one level: check words per block and the blocks as
+/// (count, data codewords)
+struct EcBlocks {
+    ec_per_block: usize,
+    blocks: &'static [(usize, usize)],
+}
+
+/// Symbol size with its character count indicator lengths (numeric,
+/// alphanumeric, byte) and error correction blocks for levels M and H
+struct SymbolVersion {
+    height: usize,
+    width: usize,
+    count_bits: [usize; 3],
+    m: EcBlocks,
+    h: EcBlocks,
+}
+
+macro_rules! version {
+    ($height:expr, $width:expr, $count_bits:expr, ($m_ec:expr, $m:expr), ($h_ec:expr, $h:expr)) => {
+        SymbolVersion {
+            height: $height,
+            width: $width,
+            count_bits: $count_bits,
+            m: EcBlocks { ec_per_block: $m_ec, blocks: $m },
+            h: EcBlocks { ec_per_block: $h_ec, blocks: $h },
+        }
+    };
+}
+
+/// Symbol versions in version indicator order (ISO/IEC 23941 tables 3 and 8)
+const VERSIONS: [SymbolVersion; 32] = [
+    version!(7, 43, [4, 3, 3], (7, &[(1, 6)]), (10, &[(1, 3)])),
+    version!(7, 59, [5, 5, 4], (9, &[(1, 12)]), (14, &[(1, 7)])),
+    version!(7, 77, [6, 5, 5], (12, &[(1, 20)]), (22, &[(1, 10)])),
+    version!(7, 99, [7, 6, 5], (16, &[(1, 28)]), (30, &[(1, 14)])),
+    version!(7, 139, [7, 6, 6], (24, &[(1, 44)]), (22, &[(2, 12)])),
+    version!(9, 43, [5, 5, 4], (9, &[(1, 12)]), (14, &[(1, 7)])),
+    version!(9, 59, [6, 5, 5], (12, &[(1, 21)]), (22, &[(1, 11)])),
+    version!(9, 77, [7, 6, 5], (18, &[(1, 31)]), (16, &[(1, 8), (1, 9)])),
+    version!(9, 99, [7, 6, 6], (24, &[(1, 42)]), (22, &[(2, 11)])),
+    version!(9, 139, [8, 7, 6], (18, &[(1, 31), (1, 32)]), (22, &[(3, 11)])),
+    version!(11, 27, [4, 4, 3], (8, &[(1, 7)]), (10, &[(1, 5)])),
+    version!(11, 43, [6, 5, 5], (12, &[(1, 19)]), (20, &[(1, 11)])),
+    version!(11, 59, [7, 6, 5], (16, &[(1, 31)]), (16, &[(1, 7), (1, 8)])),
+    version!(11, 77, [7, 6, 6], (24, &[(1, 43)]), (22, &[(1, 11), (1, 12)])),
+    version!(11, 99, [8, 7, 6], (16, &[(1, 28), (1, 29)]), (30, &[(1, 14), (1, 15)])),
+    version!(11, 139, [8, 7, 7], (24, &[(2, 42)]), (30, &[(3, 14)])),
+    version!(13, 27, [5, 5, 4], (9, &[(1, 12)]), (14, &[(1, 7)])),
+    version!(13, 43, [6, 6, 5], (14, &[(1, 27)]), (28, &[(1, 13)])),
+    version!(13, 59, [7, 6, 6], (22, &[(1, 38)]), (20, &[(2, 10)])),
+    version!(13, 77, [7, 7, 6], (16, &[(1, 26), (1, 27)]), (28, &[(1, 14), (1, 15)])),
+    version!(13, 99, [8, 7, 7], (20, &[(1, 36), (1, 37)]), (26, &[(1, 11), (2, 12)])),
+    version!(13, 139, [8, 8, 7], (20, &[(2, 35), (1, 36)]), (28, &[(2, 13), (2, 14)])),
+    version!(15, 43, [7, 6, 6], (18, &[(1, 33)]), (18, &[(1, 7), (1, 8)])),
+    version!(15, 59, [7, 7, 6], (26, &[(1, 48)]), (24, &[(2, 13)])),
+    version!(15, 77, [8, 7, 7], (18, &[(1, 33), (1, 34)]), (24, &[(2, 10), (1, 11)])),
+    version!(15, 99, [8, 7, 7], (24, &[(2, 44)]), (22, &[(4, 12)])),
+    version!(15, 139, [9, 8, 7], (24, &[(2, 42), (1, 43)]), (26, &[(1, 13), (4, 14)])),
+    version!(17, 43, [7, 6, 6], (22, &[(1, 39)]), (20, &[(1, 10), (1, 11)])),
+    version!(17, 59, [8, 7, 6], (16, &[(2, 28)]), (30, &[(2, 14)])),
+    version!(17, 77, [8, 7, 7], (22, &[(2, 39)]), (28, &[(1, 12), (2, 13)])),
+    version!(17, 99, [8, 8, 7], (20, &[(2, 33), (1, 34)]), (26, &[(4, 14)])),
+    version!(17, 139, [9, 8, 8], (20, &[(4, 38)]), (24, &[(2, 14), (4, 15)])),
+];
+
+impl SymbolVersion {
+    fn blocks(&self, ec_level: EcLevel) -> &EcBlocks {
+        if ec_level == EcLevel::H { &self.h } else { &self.m }
+    }
+
+    fn data_codewords(&self, ec_level: EcLevel) -> usize {
+        self.blocks(ec_level).blocks.iter().map(|(count, data)| count * data).sum()
+    }
+
+    /// Centre columns of the alignment patterns
+    fn alignment_columns(&self) -> &'static [usize] {
+        match self.width {
+            43 => &[21],
+            59 => &[19, 39],
+            77 => &[25, 51],
+            99 => &[23, 49, 75],
+            139 => &[27, 55, 83, 111],
+            _ => &[],
+        }
+    }
+}
+
+/// Data encoding modes, with their 3-bit indicators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Numeric = 1,
+    Alphanumeric = 2,
+    Byte = 3,
+}
+
+/// Bits written most significant first
+#[derive(Default)]
+struct BitWriter {
+    bits: Vec<bool>,
+}
+
+impl BitWriter {
+    fn push(&mut self, value: u32, count: usize) {
+        for i in (0..count).rev() {
+            self.bits.push(value >> i & 1 == 1);
+        }
+    }
+}
+
+/// The most compact mode that can encode all of `data`
+fn choose_mode(data: &[u8]) -> Mode {
+    if data.iter().all(u8::is_ascii_digit) {
+        Mode::Numeric
+    } else if data.iter().all(|b| ALPHANUMERIC.contains(b)) {
+        Mode::Alphanumeric
+    } else {
+        Mode::Byte
+    }
+}
+
+/// Encode the data of a single segment, without mode and count
+fn encode_segment(data: &[u8], mode: Mode) -> Vec<bool> {
+    let mut out = BitWriter::default();
+    match mode {
+        Mode::Numeric => {
+            for group in data.chunks(3) {
+                let value = group.iter().fold(0, |v, &b| v * 10 + (b - b'0') as u32);
+                out.push(value, [0, 4, 7, 10][group.len()]);
+            }
+        }
+        Mode::Alphanumeric => {
+            let code = |b: &u8| ALPHANUMERIC.iter().position(|c| c == b).unwrap_or(0) as u32;
+            for pair in data.chunks(2) {
+                match pair {
+                    [a, b] => out.push(code(a) * 45 + code(b), 11),
+                    [a] => out.push(code(a), 6),
+                    _ => unreachable!(),
+                }
+            }
+        }
+        Mode::Byte => {
+            for &b in data {
+                out.push(b as u32, 8);
+            }
+        }
+    }
+    out.bits
+}
+
+/// Data codewords of a version: the ECI if any, mode, count, data,
+/// terminator and padding, or `None` if the data does not fit
+fn data_codewords(data: &[u8], eci: Option<u32>, version: &SymbolVersion, ec_level: EcLevel) -> Option<Vec<u8>> {
+    let mode = choose_mode(data);
+    let count_bits = version.count_bits[mode as usize - 1];
+    if data.len() >= 1 << count_bits {
+        return None;
+    }
+    let capacity = version.data_codewords(ec_level) * 8;
+    let mut out = BitWriter::default();
+    if let Some(eci) = eci {
+        out.push(ECI_MODE, 3);
+        out.push(eci, 8);
+    }
+    out.push(mode as u32, 3);
+    out.push(data.len() as u32, count_bits);
+    out.bits.extend(encode_segment(data, mode));
+    if out.bits.len() > capacity {
+        return None;
+    }
+
+    // Terminator, as much of it as fits, then zeros to a codeword boundary
+    let terminator = (capacity - out.bits.len()).min(3);
+    out.push(0, terminator);
+    out.push(0, (8 - out.bits.len() % 8) % 8);
+    let mut codewords: Vec<u8> = out.bits.chunks(8)
+        .map(|byte| byte.iter().fold(0, |v, &bit| v << 1 | bit as u8))
+        .collect();
+    for pad in [0xEC, 0x11].into_iter().cycle() {
+        if codewords.len() * 8 >= capacity {
+            break;
+        }
+        codewords.push(pad);
+    }
+    Some(codewords)
+}
+
+/// Split data codewords into blocks, add their check words and interleave
+fn interleave(data: &[u8], ec: &EcBlocks) -> Vec<u8> {
+    let field = GaloisField::new(8, 0x11D);
+    let mut blocks = Vec::new();
+    let mut rest = data;
+    for &(count, size) in ec.blocks {
+        for _ in 0..count {
+            let (block, after) = rest.split_at(size);
+            let words: Vec<u16> = block.iter().map(|&b| b as u16).collect();
+            blocks.push((block, field.check_codewords(&words, ec.ec_per_block, 0)));
+            rest = after;
+        }
+    }
+
+    let mut out = Vec::new();
+    let longest = blocks.iter().map(|(block, _)| block.len()).max().unwrap_or(0);
+    for i in 0..longest {
+        out.extend(blocks.iter().filter_map(|(block, _)| block.get(i)));
+    }
+    for i in 0..ec.ec_per_block {
+        out.extend(blocks.iter().map(|(_, check)| check[i] as u8));
+    }
+    out
+}
+
+/// Format information: the error correction level and version indicator
+/// followed by their BCH(18, 6) check bits, before masking
+fn format_bits(ec_level: EcLevel, version_index: usize) -> u32 {
+    let data = ((ec_level == EcLevel::H) as u32) << 5 | version_index as u32;
+    let mut remainder = data << 12;
+    for i in (12..18).rev() {
+        if remainder >> i & 1 == 1 {
+            remainder ^= FORMAT_GENERATOR << (i - 12);
+        }
+    }
+    data << 12 | remainder
+}
+
+/// Module matrix with the function patterns marked
+struct Matrix {
+    height: usize,
+    width: usize,
+    dark: Vec<bool>,
+    function: Vec<bool>,
+}
+
+impl Matrix {
+    fn set(&mut self, row: usize, column: usize, dark: bool) {
+        let i = row * self.width + column;
+        self.dark[i] = dark;
+        self.function[i] = true;
+    }
+
+    fn is_function(&self, row: usize, column: usize) -> bool {
+        self.function[row * self.width + column]
+    }
+
+    /// Draw every function pattern of `version`, with the format information
+    fn new(version: &SymbolVersion, format: u32) -> Self {
+        let (height, width) = (version.height, version.width);
+        let mut m = Self {
+            height,
+            width,
+            dark: vec![false; height * width],
+            function: vec![false; height * width],
+        };
+
+        // Finder pattern and its separator
+        for r in 0..7 {
+            for c in 0..7 {
+                let ring = r == 0 || r == 6 || c == 0 || c == 6;
+                m.set(r, c, ring || ((2..=4).contains(&r) && (2..=4).contains(&c)));
+            }
+        }
+        for r in 0..height.min(8) {
+            m.set(r, 7, false);
+        }
+        if height >= 9 {
+            for c in 0..8 {
+                m.set(7, c, false);
+            }
+        }
+
+        // Finder sub-pattern in the bottom-right corner
+        for r in 0..5 {
+            for c in 0..5 {
+                let ring = r == 0 || r == 4 || c == 0 || c == 4;
+                m.set(height - 5 + r, width - 5 + c, ring || (r == 2 && c == 2));
+            }
+        }
+
+        // Corner finder patterns
+        for c in 0..3 {
+            m.set(height - 1, c, true);
+        }
+        if height >= 11 {
+            m.set(height - 2, 0, true);
+            m.set(height - 2, 1, false);
+        }
+        m.set(0, width - 2, true);
+        m.set(0, width - 1, true);
+        m.set(1, width - 2, false);
+        m.set(1, width - 1, true);
+
+        // Alignment patterns at the top and bottom edges
+        for &center in version.alignment_columns() {
+            for i in 0..3 {
+                for j in 0..3 {
+                    let dark = !(i == 1 && j == 1);
+                    m.set(i, center + j - 1, dark);
+                    m.set(height - 1 - i, center + j - 1, dark);
+                }
+            }
+        }
+
+        // Timing patterns along the edges and through the alignment patterns
+        for c in 0..width {
+            for r in [0, height - 1] {
+                if !m.is_function(r, c) {
+                    m.set(r, c, c % 2 == 0);
+                }
+            }
+        }
+        let columns = [0, width - 1].into_iter().chain(version.alignment_columns().iter().copied());
+        for c in columns {
+            for r in 0..height {
+                if !m.is_function(r, c) {
+                    m.set(r, c, r % 2 == 0);
+                }
+            }
+        }
+
+        // Format information next to the finder and the sub-pattern
+        let finder_side = format ^ FORMAT_MASK_FINDER;
+        let sub_pattern_side = format ^ FORMAT_MASK_SUB_PATTERN;
+        for n in 0..18 {
+            m.set(1 + n % 5, 8 + n / 5, finder_side >> n & 1 == 1);
+        }
+        for n in 0..15 {
+            m.set(height - 6 + n % 5, width - 8 + n / 5, sub_pattern_side >> n & 1 == 1);
+        }
+        for n in 15..18 {
+            m.set(height - 6, width - 20 + n, sub_pattern_side >> n & 1 == 1);
+        }
+        m
+    }
+
+    /// Place codewords in two-module columns from the bottom-right, moving up
+    /// and down in turn, and apply the data mask
+    fn place_data(&mut self, codewords: &[u8]) {
+        let mut bits = codewords.iter().flat_map(|&b| (0..8).rev().map(move |i| b >> i & 1 == 1));
+        let mut upward = true;
+        for x in (1..self.width - 1).rev().step_by(2) {
+            for i in 0..self.height {
+                let row = if upward { self.height - 1 - i } else { i };
+                for column in [x, x - 1] {
+                    if self.is_function(row, column) {
+                        continue;
+                    }
+                    let bit = bits.next().unwrap_or(false);
+                    let mask = (row / 2 + column / 3) % 2 == 0;
+                    self.dark[row * self.width + column] = bit != mask;
+                }
+            }
+            upward = !upward;
+        }
+    }
+}
+
+/// Parse a symbol size such as `"R7x43"`, returning its version index
+fn find_version(name: &str) -> Result<usize> {
+    let parsed = name.trim()
+        .strip_prefix(['R', 'r'])
+        .and_then(|s| s.split_once(['x', 'X']))
+        .and_then(|(h, w)| Some((h.trim().parse().ok()?, w.trim().parse().ok()?)));
+    VERSIONS.iter()
+        .position(|v| parsed == Some((v.height, v.width)))
+        .ok_or_else(|| anyhow!("Invalid rMQR size '{}': use \"R<height>x<width>\", e.g. \"R7x43\"", name))
+}
+
+/// Encode `data` as an rMQR symbol
+///
+/// Returns the width, height and row-major dark modules (top row first),
+/// without a quiet zone. `size` forces a symbol such as `"R11x43"`;
+/// otherwise the shortest, then narrowest, symbol that fits is chosen.
+/// Only error correction levels M and H exist. Text outside Latin-1 is
+/// stored as UTF-8 behind ECI 26.
+pub fn encode(data: &str, size: Option<&str>, ec_level: EcLevel) -> Result<(usize, usize, Vec<bool>)> {
+    if !matches!(ec_level, EcLevel::M | EcLevel::H) {
+        return Err(anyhow!("rMQR supports error correction levels M and H, not {:?}", ec_level));
+    }
+    let (bytes, eci) = symbol_bytes(data);
+    let (index, codewords) = match size {
+        Some(name) => {
+            let index = find_version(name)?;
+            let codewords = data_codewords(&bytes, eci, &VERSIONS[index], ec_level).ok_or_else(|| {
+                anyhow!("Data for rMQR does not fit in size {} with error correction level {:?}", name, ec_level)
+            })?;
+            (index, codewords)
+        }
+        None => VERSIONS.iter()
+            .enumerate()
+            .find_map(|(i, v)| Some((i, data_codewords(&bytes, eci, v, ec_level)?)))
+            .ok_or_else(|| {
+                anyhow!("Data for rMQR is too long ({} bytes) for error correction level {:?}", bytes.len(), ec_level)
+            })?,
+    };
+
+    let version = &VERSIONS[index];
+    let mut matrix = Matrix::new(version, format_bits(ec_level, index));
+    matrix.place_data(&interleave(&codewords, version.blocks(ec_level)));
+    Ok((version.width, version.height, matrix.dark))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bits from a string of 0 and 1, ignoring spaces
+    fn bits(pattern: &str) -> Vec<bool> {
+        pattern.chars().filter(|c| *c != ' ').map(|c| c == '1').collect()
+    }
+
+    #[test]
+    fn test_version_tables_fill_symbols() {
+        // Both error correction levels use every whole codeword of the data area
+        for (i, version) in VERSIONS.iter().enumerate() {
+            let matrix = Matrix::new(version, 0);
+            let data_modules = matrix.function.iter().filter(|f| !**f).count();
+            for ec_level in [EcLevel::M, EcLevel::H] {
+                let ec = version.blocks(ec_level);
+                let total: usize = ec.blocks.iter().map(|(count, data)| count * (data + ec.ec_per_block)).sum();
+                assert_eq!(total, data_modules / 8, "version {} level {:?}", i, ec_level);
+            }
+        }
+    }
+
+    #[test]
+    fn test_format_bits() {
+        // Same BCH code as QR version information (versions 7 and 8)
+        assert_eq!(format_bits(EcLevel::M, 7), 0x07C94);
+        assert_eq!(format_bits(EcLevel::M, 8), 0x085BC);
+        assert_eq!(format_bits(EcLevel::H, 0) >> 12, 0b100000);
+    }
+
+    #[test]
+    fn test_check_words() {
+        // QR code "HELLO WORLD" at 1-M uses the same Reed-Solomon code
+        let data = [32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17];
+        let codewords = interleave(&data, &EcBlocks { ec_per_block: 10, blocks: &[(1, 16)] });
+        assert_eq!(codewords[16..], [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]);
+
+        // Blocks are interleaved codeword by codeword, the longer block last
+        let codewords = interleave(&[1, 2, 3, 4, 5], &EcBlocks { ec_per_block: 2, blocks: &[(1, 2), (1, 3)] });
+        assert_eq!(codewords[..5], [1, 3, 2, 4, 5]);
+    }
+
+    #[test]
+    fn test_encode_segments() {
+        assert_eq!(encode_segment(b"01234567", Mode::Numeric), bits("0000001100 0101011001 1000011"));
+        assert_eq!(encode_segment(b"AC-42", Mode::Alphanumeric), bits("00111001110 11100111001 000010"));
+        assert_eq!(choose_mode(b"abc"), Mode::Byte);
+    }
+
+    #[test]
+    fn test_data_codewords() {
+        // Numeric mode (001), count 4 in 4 bits, "1234" and the terminator
+        let codewords = data_codewords(b"1234", None, &VERSIONS[0], EcLevel::M).unwrap();
+        assert_eq!(codewords, [0b0010_1000, 0b0011_1101, 0b1010_0000, 0xEC, 0x11, 0xEC]);
+        assert!(data_codewords(&[b'1'; 13], None, &VERSIONS[0], EcLevel::M).is_none());
+    }
+
+    #[test]
+    fn test_eci() {
+        // Latin-1 text is byte mode (011) with no ECI: count 1, then 0xE9
+        let (bytes, eci) = symbol_bytes("é");
+        let codewords = data_codewords(&bytes, eci, &VERSIONS[0], EcLevel::M).unwrap();
+        assert_eq!(codewords, [0b0110_0111, 0b1010_0100, 0, 0xEC, 0x11, 0xEC]);
+
+        // Other text starts with the ECI mode (111) and designator 26, then
+        // byte mode (011) with the UTF-8 bytes
+        let (bytes, eci) = symbol_bytes("東京");
+        let codewords = data_codewords(&bytes, eci, &VERSIONS[4], EcLevel::M).unwrap();
+        assert_eq!(codewords[0], 0b1110_0011);
+        assert_eq!(codewords[1] >> 2, 0b010_011);
+        assert!(encode("東京", None, EcLevel::M).is_ok());
+    }
+
+    #[test]
+    fn test_encode_chooses_shortest_symbol() {
+        let (width, height, modules) = encode("123456", None, EcLevel::M).unwrap();
+        assert_eq!((width, height), (43, 7));
+        // Finder pattern centre and the centre of the finder sub-pattern
+        assert!(modules[3 * 43 + 3]);
+        assert!(modules[4 * 43 + 40]);
+        assert!(!modules[3 * 43 + 40]);
+
+        let (width, height, _) = encode("PART-0042-REV-B", None, EcLevel::H).unwrap();
+        assert_eq!((width, height), (99, 7));
+        let (width, height, _) = encode("123456", Some("R11x27"), EcLevel::M).unwrap();
+        assert_eq!((width, height), (27, 11));
+    }
+
+    #[test]
+    fn test_encode_errors() {
+        assert!(encode("123456", None, EcLevel::L).is_err());
+        assert!(encode("123456", Some("R8x43"), EcLevel::M).is_err());
+        assert!(encode("ABCDEFGHIJ", Some("R7x43"), EcLevel::M).is_err());
+        assert!(encode(&"x".repeat(200), None, EcLevel::M).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+//! Built-in label sheet presets.
+//!
+//! This module provides the geometry of common label sheets (Avery, A-One,
+//! Herma), taken from the vendors' templates, in points.
+
+/// Points per millimetre
+const fn mm(value: f64) -> f64 {
+    value * 72.0 / 25.4
+}
+
+/// Points per inch
+const fn inch(value: f64) -> f64 {
+    value * 72.0
+}
+
+const A4: (f64, f64) = (mm(210.0), mm(297.0));
+const LETTER: (f64, f64) = (inch(8.5), inch(11.0));
+
+/// Geometry of a label sheet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SheetPreset {
+    pub name: &'static str,
+    pub sheet_width: f64,
+    pub sheet_height: f64,
+    pub columns: u32,
+    pub rows: u32,
+    pub cell_width: f64,
+    pub cell_height: f64,
+    pub gutter_x: f64,
+    pub gutter_y: f64,
+    pub margin_left: f64,
+    pub margin_top: f64,
+}
+
+impl SheetPreset {
+    const fn new(
+        name: &'static str,
+        (sheet_width, sheet_height): (f64, f64),
+        (columns, rows): (u32, u32),
+        (cell_width, cell_height): (f64, f64),
+        (gutter_x, gutter_y): (f64, f64),
+        (margin_left, margin_top): (f64, f64),
+    ) -> Self {
+        Self {
+            name,
+            sheet_width,
+            sheet_height,
+            columns,
+            rows,
+            cell_width,
+            cell_height,
+            gutter_x,
+            gutter_y,
+            margin_left,
+            margin_top,
+        }
+    }
+}
+
+/// Label sheets known by name
+const PRESETS: &[SheetPreset] = &[
+    // Avery US Letter
+    SheetPreset::new("avery-5160", LETTER, (3, 10), (inch(2.625), inch(1.0)), (inch(0.125), 0.0), (inch(0.1875), inch(0.5))),
+    SheetPreset::new("avery-5161", LETTER, (2, 10), (inch(4.0), inch(1.0)), (inch(0.1875), 0.0), (inch(0.15625), inch(0.5))),
+    SheetPreset::new("avery-5163", LETTER, (2, 5), (inch(4.0), inch(2.0)), (inch(0.1875), 0.0), (inch(0.15625), inch(0.5))),
+    SheetPreset::new("avery-5167", LETTER, (4, 20), (inch(1.75), inch(0.5)), (inch(0.3), 0.0), (inch(0.3), inch(0.5))),
+    // Avery A4
+    SheetPreset::new("avery-3474", A4, (3, 8), (mm(70.0), mm(37.0)), (0.0, 0.0), (0.0, mm(0.5))),
+    SheetPreset::new("avery-l7160", A4, (3, 7), (mm(63.5), mm(38.1)), (mm(2.54), 0.0), (mm(7.21), mm(15.15))),
+    SheetPreset::new("avery-l7163", A4, (2, 7), (mm(99.1), mm(38.1)), (mm(2.5), 0.0), (mm(4.65), mm(15.15))),
+    SheetPreset::new("avery-l7651", A4, (5, 13), (mm(38.1), mm(21.2)), (mm(2.5), 0.0), (mm(4.75), mm(10.7))),
+    // A-One A4
+    SheetPreset::new("a-one-72324", A4, (3, 8), (mm(66.04), mm(33.9)), (0.0, 0.0), (mm(5.94), mm(12.9))),
+    // Herma A4
+    SheetPreset::new("herma-4360", A4, (3, 8), (mm(70.0), mm(36.0)), (0.0, 0.0), (0.0, mm(4.5))),
+];
+
+/// Look up a label sheet by name, ignoring case
+pub fn find_preset(name: &str) -> Option<&'static SheetPreset> {
+    PRESETS.iter().find(|preset| preset.name.eq_ignore_ascii_case(name.trim()))
+}
+
+/// Names of all label sheets, for error messages
+pub fn preset_names() -> Vec<&'static str> {
+    PRESETS.iter().map(|preset| preset.name).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_preset() {
+        let preset = find_preset("Avery-5160").unwrap();
+        assert_eq!((preset.columns, preset.rows), (3, 10));
+        assert_eq!(preset.sheet_width, 612.0);
+        assert!(find_preset("avery-0000").is_none());
+    }
+
+    #[test]
+    fn test_presets_fit_their_sheets() {
+        // Every preset's grid ends inside the sheet and no more than 1 mm
+        // from being symmetric
+        for preset in PRESETS {
+            let right = preset.margin_left
+                + preset.columns as f64 * preset.cell_width
+                + (preset.columns - 1) as f64 * preset.gutter_x;
+            let bottom = preset.margin_top
+                + preset.rows as f64 * preset.cell_height
+                + (preset.rows - 1) as f64 * preset.gutter_y;
+            assert!(right <= preset.sheet_width + 0.01, "{}", preset.name);
+            assert!(bottom <= preset.sheet_height + 0.01, "{}", preset.name);
+            assert!((preset.sheet_width - right - preset.margin_left).abs() < mm(1.0), "{}", preset.name);
+            assert!((preset.sheet_height - bottom - preset.margin_top).abs() < mm(1.0), "{}", preset.name);
+        }
+    }
+}
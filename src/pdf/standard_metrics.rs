@@ -0,0 +1,98 @@
+//! Glyph widths of the standard PDF Type1 fonts.
+//!
+//! Widths come from the Adobe Font Metrics (AFM) files for the core fonts and
+//! are given in 1000-unit glyph space for the printable ASCII range
+//! (0x20-0x7E) under StandardEncoding.
+
+/// First character code covered by the width tables
+const FIRST_CHAR: u32 = 0x20;
+
+/// Width used for characters the tables do not cover
+pub const DEFAULT_WIDTH: u16 = 556;
+
+/// Every Courier glyph has the same width
+pub const COURIER_WIDTH: u16 = 600;
+
+/// Average glyph width used for the Symbol and ZapfDingbats fonts
+pub const SYMBOLIC_WIDTH: u16 = 600;
+
+pub const HELVETICA: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 222, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556,
+    278, 278, 584, 584, 584, 556, 1015,
+    667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833,
+    722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611,
+    278, 278, 278, 469, 556, 222,
+    556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833,
+    556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500,
+    334, 260, 334, 584,
+];
+
+pub const HELVETICA_BOLD: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 278, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556,
+    333, 333, 584, 584, 584, 611, 975,
+    722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833,
+    722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611,
+    333, 278, 333, 584, 556, 278,
+    556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889,
+    611, 611, 611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500,
+    389, 280, 389, 584,
+];
+
+pub const TIMES_ROMAN: [u16; 95] = [
+    250, 333, 408, 500, 500, 833, 778, 333, 333, 333, 500, 564, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+    278, 278, 564, 564, 564, 444, 921,
+    722, 667, 667, 722, 611, 556, 722, 722, 333, 389, 722, 611, 889,
+    722, 722, 556, 722, 667, 556, 611, 722, 722, 944, 722, 722, 611,
+    333, 278, 333, 469, 500, 333,
+    444, 500, 444, 500, 444, 333, 500, 500, 278, 278, 500, 278, 778,
+    500, 500, 500, 500, 333, 389, 278, 500, 500, 722, 500, 500, 444,
+    480, 200, 480, 541,
+];
+
+pub const TIMES_BOLD: [u16; 95] = [
+    250, 333, 555, 500, 500, 1000, 833, 333, 333, 333, 500, 570, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+    333, 333, 570, 570, 570, 500, 930,
+    722, 667, 722, 722, 667, 611, 778, 778, 389, 500, 778, 667, 944,
+    722, 778, 611, 778, 722, 556, 667, 722, 722, 1000, 722, 722, 667,
+    333, 278, 333, 581, 500, 333,
+    500, 556, 444, 556, 444, 333, 500, 556, 278, 333, 556, 278, 833,
+    556, 500, 556, 556, 444, 389, 333, 556, 500, 722, 500, 500, 444,
+    394, 220, 394, 520,
+];
+
+pub const TIMES_ITALIC: [u16; 95] = [
+    250, 333, 420, 500, 500, 833, 778, 333, 333, 333, 500, 675, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+    333, 333, 675, 675, 675, 500, 920,
+    611, 611, 667, 722, 611, 611, 722, 722, 333, 444, 667, 556, 833,
+    667, 722, 611, 722, 611, 500, 556, 722, 611, 833, 611, 556, 556,
+    389, 278, 389, 422, 500, 333,
+    500, 500, 444, 500, 444, 278, 500, 500, 278, 278, 444, 278, 722,
+    500, 500, 500, 500, 389, 389, 278, 500, 444, 667, 444, 444, 389,
+    400, 275, 400, 541,
+];
+
+pub const TIMES_BOLD_ITALIC: [u16; 95] = [
+    250, 389, 555, 500, 500, 833, 778, 333, 333, 333, 500, 570, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+    333, 333, 570, 570, 570, 500, 832,
+    667, 667, 667, 722, 667, 667, 722, 778, 389, 500, 667, 611, 889,
+    722, 722, 611, 722, 667, 556, 611, 722, 667, 889, 667, 611, 611,
+    333, 278, 333, 570, 500, 333,
+    500, 500, 444, 500, 444, 333, 500, 556, 278, 278, 500, 278, 778,
+    556, 500, 500, 500, 389, 389, 278, 556, 444, 667, 500, 444, 389,
+    348, 220, 348, 570,
+];
+
+/// Look up a character's width in a table, falling back to `DEFAULT_WIDTH`
+pub fn table_width(table: &[u16; 95], c: char) -> u16 {
+    (c as u32)
+        .checked_sub(FIRST_CHAR)
+        .and_then(|index| table.get(index as usize))
+        .copied()
+        .unwrap_or(DEFAULT_WIDTH)
+}